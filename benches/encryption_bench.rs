@@ -0,0 +1,59 @@
+//! Throughput of the single-shot, streaming and parallel streaming code paths
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cryptocrate::{Argon2Params, CipherAlgorithm, Decryptor, Encryptor};
+use std::fs;
+use tempfile::TempDir;
+
+/// Cheap Argon2 parameters so the KDF does not dominate the measurement
+const BENCH_ARGON2: Argon2Params = Argon2Params {
+    memory_kb: 8192,
+    time_cost: 1,
+    parallelism: 1,
+};
+
+fn bench_encrypt_decrypt(c: &mut Criterion) {
+    let temp_dir = TempDir::new().unwrap();
+    let input_path = temp_dir.path().join("input.bin");
+    let encrypted_path = temp_dir.path().join("input.bin.crat");
+    let decrypted_path = temp_dir.path().join("output.bin");
+
+    let size = 8 * 1024 * 1024;
+    fs::write(&input_path, vec![0x5Au8; size]).unwrap();
+
+    let mut group = c.benchmark_group("encrypt_decrypt");
+    group.throughput(Throughput::Bytes(size as u64));
+    group.sample_size(10);
+
+    for algorithm in [CipherAlgorithm::Aes256Gcm, CipherAlgorithm::XChaCha20Poly1305] {
+        // Thread count 0 uses one worker per CPU
+        for (mode, streaming, threads) in [
+            ("single-shot", false, 1),
+            ("streaming", true, 1),
+            ("streaming-parallel", true, 0),
+        ] {
+            let encryptor = Encryptor::with_password("benchmark")
+                .algorithm(algorithm)
+                .argon2(BENCH_ARGON2)
+                .streaming(streaming)
+                .threads(threads);
+            let decryptor = Decryptor::with_password("benchmark")
+                .streaming(streaming)
+                .threads(threads);
+
+            group.bench_function(BenchmarkId::new(format!("encrypt/{}", algorithm), mode), |b| {
+                b.iter(|| encryptor.encrypt_file(&input_path, &encrypted_path).unwrap())
+            });
+
+            encryptor.encrypt_file(&input_path, &encrypted_path).unwrap();
+            group.bench_function(BenchmarkId::new(format!("decrypt/{}", algorithm), mode), |b| {
+                b.iter(|| decryptor.decrypt_file(&encrypted_path, &decrypted_path).unwrap())
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_encrypt_decrypt);
+criterion_main!(benches);
@@ -0,0 +1,491 @@
+//! Single-file encrypted archives
+//!
+//! `encrypt --archive` packs many files into one crate, so the ciphertext
+//! reveals neither the directory layout nor the number or sizes of the
+//! files, only their total. The crate has `FLAG_ARCHIVE` set and its chunked
+//! payload is:
+//!
+//! - magic "CRATARC2" (8 bytes)
+//! - entry count (4 bytes)
+//! - per entry: path length (2 bytes) + path (UTF-8, relative, `/`
+//!   separated), size (8 bytes), which times follow (1 byte: 1 = modified,
+//!   2 = accessed), then each of those times as seconds since the epoch
+//!   (8 bytes, signed) and nanoseconds (4 bytes)
+//! - the contents of every entry, in index order
+//!
+//! Archives from older versions start with "CRATARC1" and store only the
+//! modified time, in whole seconds (8 bytes, 0 = unknown).
+//!
+//! The index comes first, so listing an archive only decrypts its first
+//! chunks. With compression on, the whole payload is one compressed stream.
+
+use std::borrow::Cow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::compression::{looks_compressible, read_sample, Compression, CompressionAlgorithm};
+use crate::crypto::encryption::EncryptOptions;
+use crate::crypto::keyslot::Credentials;
+use crate::error::{CrateError, Result};
+use crate::file_handler::{join_relative, FileEntry};
+use crate::format::{read_u16, read_u32, read_u64, read_u8, read_vec, FLAG_ARCHIVE};
+use crate::metadata::{decode_time, encode_time, FileMetadata, Timestamps};
+use crate::streaming::{CrateReader, CrateWriter};
+
+/// Magic bytes at the start of an archive payload
+const ARCHIVE_MAGIC: &[u8; 8] = b"CRATARC2";
+
+/// Magic bytes of archives whose index only has whole-second modified times
+const ARCHIVE_MAGIC_V1: &[u8; 8] = b"CRATARC1";
+
+/// Index flags: which times are stored for an entry
+const TIME_MODIFIED: u8 = 1;
+const TIME_ACCESSED: u8 = 2;
+
+/// One file stored in an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// Path relative to the archive root, `/`-separated
+    pub path: String,
+    pub size: u64,
+    /// Modification and access time with nanoseconds
+    pub timestamps: Timestamps,
+}
+
+impl ArchiveEntry {
+    /// Index entry for a file found by `collect_files`
+    fn from_file(file: &FileEntry) -> Result<Self> {
+        Ok(Self {
+            path: file.portable_path()?,
+            size: file.size,
+            timestamps: FileMetadata::from_file(&file.path, CompressionAlgorithm::None)?
+                .timestamps,
+        })
+    }
+
+    /// Where the entry is extracted to under `dir`
+    ///
+    /// Absolute paths and `..` are refused, so an archive can never write
+    /// outside `dir`.
+    pub fn target<P: AsRef<Path>>(&self, dir: P) -> Result<PathBuf> {
+        join_relative(dir, &self.path)
+    }
+}
+
+/// Pack `files` (see `file_handler::collect_files`) into one crate at `output_path`
+///
+/// Each file is stored under its `relative_path`. Returns the index that
+/// was written.
+///
+/// The archive is one compressed stream, so in `auto` mode it is compressed
+/// unless most of its bytes are in files that look compressed already.
+pub fn create_archive<P: AsRef<Path>>(
+    files: &[FileEntry],
+    output_path: P,
+    credentials: &Credentials,
+    options: &EncryptOptions,
+) -> Result<Vec<ArchiveEntry>> {
+    let output_path = output_path.as_ref();
+    let entries = files
+        .iter()
+        .map(ArchiveEntry::from_file)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut options = Cow::Borrowed(options);
+    if options.compression.auto {
+        let mut compressible = 0;
+        for (file, entry) in files.iter().zip(&entries) {
+            let input = File::open(&file.path)
+                .map_err(|e| CrateError::FileNotFound(format!("{}: {}", file.path.display(), e)))?;
+            if looks_compressible(&read_sample(input)?) {
+                compressible += entry.size;
+            }
+        }
+        let total: u64 = entries.iter().map(|entry| entry.size).sum();
+        if compressible * 2 < total {
+            options.to_mut().compression = Compression::NONE;
+        }
+    }
+
+    let metadata = FileMetadata {
+        filename: output_path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("archive")
+            .to_string(),
+        original_size: entries.iter().map(|entry| entry.size).sum(),
+        modified_time: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs()),
+        compression: CompressionAlgorithm::None,
+        relative_path: None,
+        attributes: Default::default(),
+        timestamps: Default::default(),
+    };
+
+    let output_file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(output_path)?;
+    let mut writer = CrateWriter::with_flags(
+        BufWriter::new(output_file),
+        credentials,
+        &options,
+        &metadata,
+        FLAG_ARCHIVE,
+    )?;
+
+    writer.write_all(&encode_index(&entries)?)?;
+    for (file, entry) in files.iter().zip(&entries) {
+        let input = File::open(&file.path)
+            .map_err(|e| CrateError::FileNotFound(format!("{}: {}", file.path.display(), e)))?;
+        // The index already promised `size` bytes
+        if io::copy(&mut input.take(entry.size), &mut writer)? != entry.size {
+            return Err(CrateError::Encryption(format!(
+                "{} shrank while it was being archived",
+                file.path.display()
+            )));
+        }
+    }
+    writer.finish()?;
+
+    Ok(entries)
+}
+
+/// List the files in the archive at `path`
+pub fn list_archive<P: AsRef<Path>>(path: P, credentials: &Credentials) -> Result<Vec<ArchiveEntry>> {
+    Ok(ArchiveReader::open(path, credentials)?.entries)
+}
+
+/// Extract the archive at `path` into `output_dir`, recreating its tree
+///
+/// Existing files are skipped unless `overwrite` is set. Returns the
+/// number of files written.
+pub fn extract_archive<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    output_dir: Q,
+    credentials: &Credentials,
+    overwrite: bool,
+) -> Result<usize> {
+    ArchiveReader::open(path, credentials)?.extract_to(output_dir, overwrite)
+}
+
+/// An archive whose index has been read, ready for extraction
+pub struct ArchiveReader<R: Read> {
+    reader: CrateReader<R>,
+    entries: Vec<ArchiveEntry>,
+}
+
+impl ArchiveReader<File> {
+    /// Open the archive at `path`
+    pub fn open<P: AsRef<Path>>(path: P, credentials: &Credentials) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|e| CrateError::FileNotFound(format!("{}: {}", path.display(), e)))?;
+        Self::new(file, credentials)
+    }
+}
+
+impl<R: Read> ArchiveReader<R> {
+    /// Unlock the crate in `inner` and read its index
+    pub fn new(inner: R, credentials: &Credentials) -> Result<Self> {
+        let mut reader = CrateReader::new(inner, credentials)?;
+        if !reader.header().is_archive() {
+            return Err(CrateError::InvalidFormat(
+                "Not an archive; decrypt it instead".to_string(),
+            ));
+        }
+        let entries = decode_index(&mut reader)?;
+        Ok(Self { reader, entries })
+    }
+
+    /// The files in the archive, in the order they are stored
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    /// The metadata of the archive itself
+    pub fn metadata(&self) -> &FileMetadata {
+        self.reader.metadata()
+    }
+
+    /// Extract every entry into `dir`, skipping existing files unless `overwrite`
+    ///
+    /// Every path is checked before anything is written, and each file gets
+    /// its recorded times back. Existing files and symlinks are replaced,
+    /// never written through. Returns the number of files written.
+    pub fn extract_to<P: AsRef<Path>>(mut self, dir: P, overwrite: bool) -> Result<usize> {
+        let targets = self
+            .entries
+            .iter()
+            .map(|entry| entry.target(dir.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut extracted = 0;
+        for (entry, target) in self.entries.iter().zip(&targets) {
+            // A symlink counts as existing even when dangling, and is
+            // replaced rather than followed
+            if fs::symlink_metadata(target).is_ok() {
+                if !overwrite {
+                    copy_entry(&mut self.reader, entry, &mut io::sink())?;
+                    continue;
+                }
+                fs::remove_file(target)?;
+            }
+
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let file = OpenOptions::new().write(true).create_new(true).open(target)?;
+            let mut writer = BufWriter::new(file);
+            let result = copy_entry(&mut self.reader, entry, &mut writer)
+                .and_then(|()| Ok(writer.flush()?));
+            drop(writer);
+            if let Err(e) = result {
+                let _ = fs::remove_file(target);
+                return Err(e);
+            }
+            entry.timestamps.apply_to(target)?;
+            extracted += 1;
+        }
+
+        // Reading to the end authenticates the final chunk
+        if self.reader.read(&mut [0u8; 1])? != 0 {
+            return Err(CrateError::InvalidFormat(
+                "Unexpected data after the last archive entry".to_string(),
+            ));
+        }
+        Ok(extracted)
+    }
+}
+
+/// Copy the contents of `entry` from `reader` to `writer`
+fn copy_entry<R: Read, W: Write>(reader: &mut R, entry: &ArchiveEntry, writer: &mut W) -> Result<()> {
+    if io::copy(&mut reader.take(entry.size), writer)? != entry.size {
+        return Err(CrateError::InvalidFormat(format!(
+            "Archive ends in the middle of {}",
+            entry.path
+        )));
+    }
+    Ok(())
+}
+
+fn encode_index(entries: &[ArchiveEntry]) -> Result<Vec<u8>> {
+    let count = u32::try_from(entries.len())
+        .map_err(|_| CrateError::Encryption("Too many files for one archive".to_string()))?;
+
+    let mut bytes = ARCHIVE_MAGIC.to_vec();
+    bytes.extend_from_slice(&count.to_le_bytes());
+    for entry in entries {
+        let path = entry.path.as_bytes();
+        let path_len = u16::try_from(path.len()).map_err(|_| {
+            CrateError::Encryption(format!("Path too long to archive: {}", entry.path))
+        })?;
+        bytes.extend_from_slice(&path_len.to_le_bytes());
+        bytes.extend_from_slice(path);
+        bytes.extend_from_slice(&entry.size.to_le_bytes());
+
+        let Timestamps { modified, accessed } = entry.timestamps;
+        let flags = modified.map_or(0, |_| TIME_MODIFIED) | accessed.map_or(0, |_| TIME_ACCESSED);
+        bytes.push(flags);
+        for time in [modified, accessed].into_iter().flatten() {
+            bytes.extend_from_slice(&encode_time(time));
+        }
+    }
+    Ok(bytes)
+}
+
+fn decode_index<R: Read>(reader: &mut R) -> Result<Vec<ArchiveEntry>> {
+    let magic = read_vec(reader, ARCHIVE_MAGIC.len())?;
+    let version_1 = magic == ARCHIVE_MAGIC_V1;
+    if !version_1 && magic != ARCHIVE_MAGIC {
+        return Err(CrateError::InvalidFormat(
+            "Archive index is missing".to_string(),
+        ));
+    }
+
+    let count = read_u32(reader)?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let path_len = read_u16(reader)? as usize;
+        let path = String::from_utf8(read_vec(reader, path_len)?).map_err(|_| {
+            CrateError::InvalidFormat("Archive path is not valid UTF-8".to_string())
+        })?;
+        let size = read_u64(reader)?;
+        let timestamps = if version_1 {
+            Timestamps {
+                modified: Some(read_u64(reader)?)
+                    .filter(|&secs| secs != 0)
+                    .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+                accessed: None,
+            }
+        } else {
+            let flags = read_u8(reader)?;
+            Timestamps {
+                modified: read_time(reader, flags & TIME_MODIFIED != 0, &path)?,
+                accessed: read_time(reader, flags & TIME_ACCESSED != 0, &path)?,
+            }
+        };
+        entries.push(ArchiveEntry {
+            path,
+            size,
+            timestamps,
+        });
+    }
+    Ok(entries)
+}
+
+/// Read one of an entry's times, if the index says it is there
+fn read_time<R: Read>(reader: &mut R, present: bool, path: &str) -> Result<Option<SystemTime>> {
+    if !present {
+        return Ok(None);
+    }
+    decode_time(&read_vec(reader, 12)?)
+        .map(Some)
+        .map_err(|_| CrateError::InvalidFormat(format!("Invalid timestamp for {}", path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::encryption::test_options;
+    use crate::file_handler::collect_files;
+    use crate::streaming::decrypt_file_streaming_with_credentials;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("project");
+        fs::create_dir_all(source.join("src/nested")).unwrap();
+        fs::write(source.join("README.md"), b"# Project").unwrap();
+        fs::write(source.join("src/main.rs"), b"fn main() {}").unwrap();
+        fs::write(source.join("src/nested/empty.txt"), b"").unwrap();
+        let big: Vec<u8> = (0..3 * 1024 * 1024 + 5).map(|i| (i % 251) as u8).collect();
+        fs::write(source.join("src/nested/big.bin"), &big).unwrap();
+        let times = Timestamps {
+            modified: Some(SystemTime::UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789)),
+            accessed: Some(SystemTime::UNIX_EPOCH + Duration::new(1_600_000_100, 987_654_321)),
+        };
+        times.apply_to(source.join("src/main.rs")).unwrap();
+
+        let credentials = Credentials::password("archive");
+        let archive_path = temp_dir.path().join("project.crat");
+        let files = collect_files(&source, None).unwrap();
+        let written = create_archive(&files, &archive_path, &credentials, &test_options()).unwrap();
+        assert_eq!(written.len(), 4);
+        assert!(written.iter().any(|entry| entry.path == "project/src/nested/big.bin"));
+
+        let listed = list_archive(&archive_path, &credentials).unwrap();
+        assert_eq!(listed, written);
+
+        let output = temp_dir.path().join("restored");
+        assert_eq!(extract_archive(archive_path.as_path(), &output, &credentials, false).unwrap(), 4);
+        // (Checked before reading the files back updates their access times)
+        let restored = fs::metadata(output.join("project/src/main.rs")).unwrap();
+        assert_eq!(restored.modified().ok(), times.modified);
+        assert_eq!(restored.accessed().ok(), times.accessed);
+        for file in &files {
+            let restored = output.join(&file.relative_path);
+            assert_eq!(fs::read(restored).unwrap(), fs::read(&file.path).unwrap());
+        }
+
+        // Existing files are left alone unless overwriting
+        fs::write(output.join("project/README.md"), b"edited").unwrap();
+        fs::remove_file(output.join("project/src/main.rs")).unwrap();
+        assert_eq!(extract_archive(&archive_path, &output, &credentials, false).unwrap(), 1);
+        assert_eq!(fs::read(output.join("project/README.md")).unwrap(), b"edited");
+        assert_eq!(extract_archive(&archive_path, &output, &credentials, true).unwrap(), 4);
+        assert_eq!(fs::read(output.join("project/README.md")).unwrap(), b"# Project");
+
+        // Archives are not decrypted as a single file
+        let result = decrypt_file_streaming_with_credentials(
+            &archive_path,
+            &temp_dir.path().join("blob"),
+            &credentials,
+        );
+        assert!(matches!(result, Err(CrateError::InvalidFormat(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_extract_replaces_symlinks() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("data");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("a.txt"), b"archived a").unwrap();
+        fs::write(source.join("b.txt"), b"archived b").unwrap();
+
+        let credentials = Credentials::password("archive");
+        let archive_path = temp_dir.path().join("data.crat");
+        let files = collect_files(&source, None).unwrap();
+        create_archive(&files, &archive_path, &credentials, &test_options()).unwrap();
+
+        // A dangling link and a live one where the entries would go
+        let output = temp_dir.path().join("out");
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir_all(output.join("data")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("victim.txt"), b"untouched").unwrap();
+        std::os::unix::fs::symlink(outside.join("created.txt"), output.join("data/a.txt")).unwrap();
+        std::os::unix::fs::symlink(outside.join("victim.txt"), output.join("data/b.txt")).unwrap();
+
+        // Both count as existing, so nothing is written through them
+        assert_eq!(extract_archive(&archive_path, &output, &credentials, false).unwrap(), 0);
+        assert!(!outside.join("created.txt").exists());
+
+        // Overwriting replaces the links themselves
+        assert_eq!(extract_archive(&archive_path, &output, &credentials, true).unwrap(), 2);
+        assert!(!outside.join("created.txt").exists());
+        assert_eq!(fs::read(outside.join("victim.txt")).unwrap(), b"untouched");
+        for name in ["a.txt", "b.txt"] {
+            let target = output.join("data").join(name);
+            assert!(fs::symlink_metadata(&target).unwrap().is_file());
+            assert_eq!(fs::read(&target).unwrap(), fs::read(source.join(name)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_version_1_index() {
+        let mut index = ARCHIVE_MAGIC_V1.to_vec();
+        index.extend_from_slice(&2u32.to_le_bytes());
+        for (path, modified) in [("a.txt", 1_600_000_000u64), ("b.txt", 0)] {
+            index.extend_from_slice(&(path.len() as u16).to_le_bytes());
+            index.extend_from_slice(path.as_bytes());
+            index.extend_from_slice(&7u64.to_le_bytes());
+            index.extend_from_slice(&modified.to_le_bytes());
+        }
+
+        let entries = decode_index(&mut index.as_slice()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].timestamps.modified,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000))
+        );
+        assert_eq!(entries[1].timestamps, Timestamps::default());
+        assert_eq!(entries[1].size, 7);
+    }
+
+    #[test]
+    fn test_unsafe_paths_rejected() {
+        for path in ["../escape.txt", "/etc/passwd", "a/../../b", ""] {
+            let entry = ArchiveEntry {
+                path: path.to_string(),
+                size: 0,
+                timestamps: Timestamps::default(),
+            };
+            assert!(entry.target("out").is_err(), "{:?} was accepted", path);
+        }
+
+        let entry = ArchiveEntry {
+            path: "a/b.txt".to_string(),
+            size: 0,
+            timestamps: Timestamps::default(),
+        };
+        assert_eq!(entry.target("out").unwrap(), Path::new("out/a/b.txt"));
+    }
+}
@@ -0,0 +1,402 @@
+//! Tokio `AsyncRead` / `AsyncWrite` versions of `CrateReader` and `CrateWriter`
+//!
+//! Enabled with the `tokio` cargo feature. The stream format is the same
+//! chunked layout the sync adapters read and write. Key derivation (Argon2id
+//! can take a second and 64 MB) runs on tokio's blocking pool; sealing and
+//! opening a chunk is cheap enough to stay on the calling task.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::crypto::encryption::EncryptOptions;
+use crate::crypto::keyslot::Credentials;
+use crate::error::{CrateError, Result};
+use crate::format::{read_header, FileHeader, TAG_LENGTH};
+use crate::metadata::FileMetadata;
+use crate::secret::Zeroizing;
+use crate::streaming::{begin_crate, open_crate, ChunkOpener, ChunkSealer, CHUNK_SIZE};
+
+/// Headers larger than this are rejected instead of buffered (1 MB)
+const MAX_HEADER_READ: usize = 1024 * 1024;
+
+/// Run `f` on the blocking pool
+async fn run_blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| CrateError::Io(io::Error::other(e)))?
+}
+
+/// Read the header and metadata block, returning any payload bytes read past them
+async fn read_header_async<R: AsyncRead + Unpin>(
+    inner: &mut R,
+) -> Result<(FileHeader, Vec<u8>, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut scratch = [0u8; 8192];
+
+    loop {
+        let mut cursor = &buf[..];
+        match read_header(&mut cursor) {
+            Ok((header, metadata_block)) => return Ok((header, metadata_block, cursor.to_vec())),
+            // The parser ran out of bytes: fetch more unless the input is exhausted
+            Err(e) if cursor.is_empty() && buf.len() < MAX_HEADER_READ => {
+                let n = inner.read(&mut scratch).await?;
+                if n == 0 {
+                    return Err(e);
+                }
+                buf.extend_from_slice(&scratch[..n]);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Encrypts into any `AsyncWrite`; see `CrateWriter`
+///
+/// Call `finish` (or `shutdown`) when done: until then the last chunk is
+/// not sealed and the stream will fail authentication.
+pub struct AsyncCrateWriter<W: AsyncWrite + Unpin> {
+    inner: W,
+    sealer: ChunkSealer,
+    chunk: Zeroizing<Vec<u8>>,
+    /// Sealed ciphertext not yet accepted by `inner`
+    pending: Vec<u8>,
+    written: usize,
+    finished: bool,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncCrateWriter<W> {
+    /// Write the header and metadata for a new crate to `inner`
+    ///
+    /// Compression is not supported: `options.compression` must be off.
+    pub async fn new(
+        mut inner: W,
+        credentials: &Credentials,
+        options: &EncryptOptions,
+        metadata: &FileMetadata,
+    ) -> Result<Self> {
+        if options.compression.is_enabled() {
+            return Err(CrateError::InvalidFormat(
+                "Compression is not supported by AsyncCrateWriter; use CrateWriter".to_string(),
+            ));
+        }
+
+        let options = options.clone();
+        let (credentials, metadata) = (credentials.clone(), metadata.clone());
+        let (preamble, sealer) =
+            run_blocking(move || begin_crate(&credentials, &options, &metadata)).await?;
+        inner.write_all(&preamble).await?;
+
+        Ok(Self {
+            inner,
+            sealer,
+            chunk: Zeroizing::new(Vec::with_capacity(CHUNK_SIZE)),
+            pending: Vec::new(),
+            written: 0,
+            finished: false,
+        })
+    }
+
+    /// Seal the final chunk, flush, and return the underlying writer
+    pub async fn finish(mut self) -> Result<W> {
+        std::future::poll_fn(|cx| self.poll_finish(cx)).await?;
+        Ok(self.inner)
+    }
+
+    /// Write out sealed ciphertext
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            let n =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_finish(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_pending(cx))?;
+        if !self.finished {
+            self.pending = self.sealer.seal(&self.chunk, true)?;
+            self.chunk.clear();
+            self.finished = true;
+            ready!(self.poll_pending(cx))?;
+        }
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncCrateWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.finished {
+            return Poll::Ready(Err(io::Error::other("write after the crate was finished")));
+        }
+        ready!(this.poll_pending(cx))?;
+
+        // A full chunk is held back until more data shows it is not the last
+        if this.chunk.len() == CHUNK_SIZE && !buf.is_empty() {
+            this.pending = this.sealer.seal(&this.chunk, false)?;
+            this.chunk.clear();
+        }
+
+        let len = buf.len().min(CHUNK_SIZE - this.chunk.len());
+        this.chunk.extend_from_slice(&buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    /// Flushes sealed chunks only; the chunk being filled stays buffered
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    /// Seals the final chunk, then shuts down the underlying writer
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_finish(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Decrypts from any `AsyncRead`; see `CrateReader`
+pub struct AsyncCrateReader<R: AsyncRead + Unpin> {
+    inner: R,
+    opener: ChunkOpener,
+    header: FileHeader,
+    metadata: FileMetadata,
+    /// Encrypted segment being collected, plus one byte of lookahead
+    ciphertext: Vec<u8>,
+    filled: usize,
+    segment_length: usize,
+    eof: bool,
+    plaintext: Zeroizing<Vec<u8>>,
+    position: usize,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsyncCrateReader<R> {
+    /// Read the header and metadata from `inner` and unlock the file key
+    pub async fn new(mut inner: R, credentials: &Credentials) -> Result<Self> {
+        let (header, metadata_block, leftover) = read_header_async(&mut inner).await?;
+        if header.is_compressed_stream() {
+            return Err(CrateError::InvalidFormat(
+                "Compressed streams are not supported by AsyncCrateReader; use CrateReader"
+                    .to_string(),
+            ));
+        }
+
+        let credentials = credentials.clone();
+        let (header, metadata, opener) = run_blocking(move || {
+            let (metadata, opener) = open_crate(&header, &metadata_block, &credentials)?;
+            Ok((header, metadata, opener))
+        })
+        .await?;
+
+        let mut reader = Self {
+            inner,
+            opener,
+            header,
+            metadata,
+            ciphertext: leftover,
+            filled: 0,
+            segment_length: 0,
+            eof: false,
+            plaintext: Zeroizing::new(Vec::new()),
+            position: 0,
+            done: false,
+        };
+
+        if reader.header.is_chunked() {
+            reader.segment_length = reader.header.chunk_size as usize + TAG_LENGTH;
+            reader.filled = reader.ciphertext.len();
+            let capacity = reader.filled.max(reader.segment_length + 1);
+            reader.ciphertext.resize(capacity, 0);
+        } else {
+            // Single-shot payloads carry one tag for the whole file
+            let mut ciphertext = std::mem::take(&mut reader.ciphertext);
+            reader.inner.read_to_end(&mut ciphertext).await?;
+            reader.plaintext = reader
+                .opener
+                .open_single_shot(&ciphertext, &reader.metadata)?;
+            reader.done = true;
+        }
+
+        Ok(reader)
+    }
+
+    /// The metadata stored in the crate
+    pub fn metadata(&self) -> &FileMetadata {
+        &self.metadata
+    }
+
+    /// The parsed file header
+    pub fn header(&self) -> &FileHeader {
+        &self.header
+    }
+
+    /// Authenticate the buffered segment into the plaintext buffer
+    fn open_chunk(&mut self) -> Result<()> {
+        // Without a byte of lookahead the input has ended, so this is the last segment
+        let last = self.filled <= self.segment_length;
+        let len = self.filled.min(self.segment_length);
+
+        self.plaintext = self.opener.open(&self.ciphertext[..len], last)?;
+        self.ciphertext.copy_within(len..self.filled, 0);
+        self.filled -= len;
+        self.position = 0;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncCrateReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        while this.position == this.plaintext.len() {
+            if this.done || out.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            if !this.eof && this.filled <= this.segment_length {
+                let mut read_buf = ReadBuf::new(&mut this.ciphertext[this.filled..]);
+                ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+                match read_buf.filled().len() {
+                    0 => this.eof = true,
+                    n => this.filled += n,
+                }
+                continue;
+            }
+
+            this.open_chunk()?;
+        }
+
+        let len = out.remaining().min(this.plaintext.len() - this.position);
+        out.put_slice(&this.plaintext[this.position..this.position + len]);
+        this.position += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::CompressionAlgorithm;
+    use crate::crypto::encryption::{encrypt_file_with_options, test_options};
+    use crate::streaming::{CrateReader, CrateWriter};
+    use std::fs;
+    use std::io::{Read, Write};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_async_roundtrip_against_sync() {
+        let options = test_options();
+        let credentials = Credentials::password("async");
+        let metadata = FileMetadata {
+            filename: "upload.bin".to_string(),
+            original_size: 0,
+            modified_time: None,
+            compression: CompressionAlgorithm::None,
+            relative_path: None,
+            attributes: Default::default(),
+            timestamps: Default::default(),
+        };
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 333).map(|i| (i % 239) as u8).collect();
+
+        // Async writer, sync reader
+        let mut writer = AsyncCrateWriter::new(Vec::new(), &credentials, &options, &metadata)
+            .await
+            .unwrap();
+        for piece in data.chunks(10_000) {
+            writer.write_all(piece).await.unwrap();
+        }
+        let encrypted = writer.finish().await.unwrap();
+
+        // Compression is refused rather than silently dropped
+        let compressed = EncryptOptions {
+            compression: "zstd".parse().unwrap(),
+            ..options.clone()
+        };
+        let result = AsyncCrateWriter::new(Vec::new(), &credentials, &compressed, &metadata).await;
+        assert!(matches!(result, Err(CrateError::InvalidFormat(_))));
+
+        let mut reader = CrateReader::new(&encrypted[..], &credentials).unwrap();
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, data);
+        assert_eq!(reader.metadata().filename, "upload.bin");
+
+        // Sync writer, async reader
+        let mut writer = CrateWriter::new(Vec::new(), &credentials, &options, &metadata).unwrap();
+        writer.write_all(&data).unwrap();
+        let encrypted = writer.finish().unwrap();
+
+        let mut reader = AsyncCrateReader::new(&encrypted[..], &credentials)
+            .await
+            .unwrap();
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).await.unwrap();
+        assert_eq!(decrypted, data);
+
+        // A tampered chunk surfaces as the original error
+        let mut tampered = encrypted.clone();
+        let index = tampered.len() - 400;
+        tampered[index] ^= 1;
+        let mut reader = AsyncCrateReader::new(&tampered[..], &credentials)
+            .await
+            .unwrap();
+        let result = reader
+            .read_to_end(&mut Vec::new())
+            .await
+            .map_err(CrateError::from);
+        assert!(matches!(result, Err(CrateError::AuthenticationFailed(_))));
+
+        let result = AsyncCrateReader::new(&encrypted[..], &Credentials::password("wrong")).await;
+        assert!(matches!(result, Err(CrateError::InvalidPassword)));
+    }
+
+    #[tokio::test]
+    async fn test_async_reader_single_shot() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("notes.txt");
+        let encrypted_path = temp_dir.path().join("notes.txt.crat");
+        let data = b"Compressible notes. ".repeat(500);
+        fs::write(&input_path, &data).unwrap();
+
+        let options = EncryptOptions {
+            compression: "lz4".parse().unwrap(),
+            ..test_options()
+        };
+        let credentials = Credentials::password("async");
+        encrypt_file_with_options(&input_path, &encrypted_path, &credentials, &options).unwrap();
+
+        let encrypted = fs::read(&encrypted_path).unwrap();
+        let mut reader = AsyncCrateReader::new(&encrypted[..], &credentials)
+            .await
+            .unwrap();
+        assert!(reader.metadata().is_compressed());
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).await.unwrap();
+        assert_eq!(decrypted, data);
+    }
+}
@@ -0,0 +1,447 @@
+//! Builder-style entry points for library users
+//!
+//! `Encryptor` and `Decryptor` collect credentials and options, then pick
+//! single-shot or streaming mode per file the same way the CLI does.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::archive::{create_archive, ArchiveEntry, ArchiveReader};
+use crate::compression::{Compression, CompressionAlgorithm};
+use crate::config::Config;
+use crate::crypto::cipher::CipherAlgorithm;
+use crate::crypto::encryption::{decrypt_file_with_credentials, encrypt_entry_with_options, EncryptOptions};
+use crate::crypto::key_derivation::Argon2Params;
+use crate::crypto::keyslot::Credentials;
+use crate::error::Result;
+use crate::file_handler::FileEntry;
+use crate::identity::{read_identity, Identity, Recipient};
+use crate::inspect::{inspect_file_with_credentials, FileInfo};
+use crate::keyfile::read_keyfile;
+use crate::metadata::FileMetadata;
+use crate::secret::{SecretKey, SecretString};
+use crate::streaming::{
+    decrypt_file_streaming_with_threads, encrypt_entry_streaming_with_options,
+    decrypt_range_with_credentials, should_use_streaming, CrateReader, CrateWriter,
+};
+
+/// Encrypts files with a fixed set of credentials and options
+///
+/// ```no_run
+/// use cryptocrate::{CipherAlgorithm, Encryptor};
+///
+/// # fn main() -> cryptocrate::Result<()> {
+/// let encryptor = Encryptor::with_password("correct horse battery staple")
+///     .algorithm(CipherAlgorithm::XChaCha20Poly1305)
+///     .compress(true);
+///
+/// encryptor.encrypt_file("report.pdf", "report.pdf.crat")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Encryptor {
+    credentials: Credentials,
+    options: EncryptOptions,
+    streaming: Option<bool>,
+}
+
+impl Encryptor {
+    /// An encryptor with default options and no credentials yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An encryptor protected by `password`
+    pub fn with_password(password: impl Into<SecretString>) -> Self {
+        Self::new().password(password)
+    }
+
+    /// Take compression, metadata, algorithm and Argon2 settings from `config`
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let argon2 = config.argon2_params();
+        argon2.validate()?;
+
+        let compression = if config.compress_by_default {
+            config.compression()?
+        } else {
+            Compression::NONE
+        };
+
+        Ok(Self::new()
+            .compression(compression)
+            .encrypt_metadata(config.encrypt_metadata)
+            .algorithm(config.cipher_algorithm()?)
+            .argon2(argon2)
+            .threads(config.threads)
+            .preserve_attributes(config.preserve_attributes))
+    }
+
+    /// Protect files with a password (an empty password is ignored)
+    pub fn password(mut self, password: impl Into<SecretString>) -> Self {
+        self.credentials.password = Some(password.into()).filter(|p| !p.is_empty());
+        self
+    }
+
+    /// Protect files with a key file hash (see `keyfile::read_keyfile`)
+    pub fn keyfile(mut self, keyfile_hash: SecretKey) -> Self {
+        self.credentials.keyfile = Some(keyfile_hash);
+        self
+    }
+
+    /// Protect files with the key file at `path`
+    pub fn keyfile_path<P: AsRef<Path>>(self, path: P) -> Result<Self> {
+        Ok(self.keyfile(read_keyfile(path)?))
+    }
+
+    /// Also let `recipient`'s identity open the files
+    pub fn recipient(mut self, recipient: Recipient) -> Self {
+        self.options.recipients.push(recipient);
+        self
+    }
+
+    /// Replace the password and key file with `credentials`
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Compress with zstd at its default level before encrypting
+    pub fn compress(self, compress: bool) -> Self {
+        self.compression(if compress {
+            CompressionAlgorithm::Zstd.into()
+        } else {
+            Compression::NONE
+        })
+    }
+
+    /// Compression algorithm and level for new files
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.options.compression = compression;
+        self
+    }
+
+    /// Encrypt filenames, sizes and timestamps (on by default)
+    pub fn encrypt_metadata(mut self, encrypt_metadata: bool) -> Self {
+        self.options.encrypt_metadata = encrypt_metadata;
+        self
+    }
+
+    /// AEAD algorithm for new files
+    pub fn algorithm(mut self, algorithm: CipherAlgorithm) -> Self {
+        self.options.algorithm = algorithm;
+        self
+    }
+
+    /// Argon2id cost parameters for password slots
+    pub fn argon2(mut self, params: Argon2Params) -> Self {
+        self.options.argon2 = params;
+        self
+    }
+
+    /// Force streaming mode on or off
+    ///
+    /// By default files larger than `streaming::STREAMING_THRESHOLD` are streamed.
+    pub fn streaming(mut self, streaming: bool) -> Self {
+        self.streaming = Some(streaming);
+        self
+    }
+
+    /// Worker threads for streamed files (0 = one per CPU, default 1)
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.options.threads = threads;
+        self
+    }
+
+    /// Record permissions, ownership, extended attributes and symlinks
+    pub fn preserve_attributes(mut self, preserve: bool) -> Self {
+        self.options.preserve_attributes = preserve;
+        self
+    }
+
+    /// The options new files are written with
+    pub fn options(&self) -> &EncryptOptions {
+        &self.options
+    }
+
+    /// Encrypt `input` into `output`, returning the metadata that was stored
+    pub fn encrypt_file<P: AsRef<Path>>(&self, input: P, output: P) -> Result<FileMetadata> {
+        self.encrypt_with_path(input.as_ref(), output.as_ref(), None)
+    }
+
+    /// Encrypt a collected file, storing its path relative to the folder it came from
+    pub fn encrypt_entry<P: AsRef<Path>>(
+        &self,
+        entry: &FileEntry,
+        output: P,
+    ) -> Result<FileMetadata> {
+        let relative_path = entry.portable_path()?;
+        self.encrypt_with_path(&entry.path, output.as_ref(), Some(relative_path))
+    }
+
+    fn encrypt_with_path(
+        &self,
+        input: &Path,
+        output: &Path,
+        relative_path: Option<String>,
+    ) -> Result<FileMetadata> {
+        let streaming = match self.streaming {
+            Some(streaming) => streaming,
+            // A preserved symlink is stored without contents
+            None if self.options.preserve_attributes && input.is_symlink() => false,
+            None => should_use_streaming(input)?,
+        };
+
+        if streaming {
+            encrypt_entry_streaming_with_options(
+                input,
+                output,
+                relative_path,
+                &self.credentials,
+                &self.options,
+            )
+        } else {
+            encrypt_entry_with_options(input, output, relative_path, &self.credentials, &self.options)
+        }
+    }
+
+    /// Start a chunked crate on `inner`; see `CrateWriter`
+    pub fn writer<W: Write>(&self, inner: W, metadata: &FileMetadata) -> Result<CrateWriter<W>> {
+        CrateWriter::new(inner, &self.credentials, &self.options, metadata)
+    }
+
+    /// Pack `files` into a single archive at `output`; see `archive`
+    pub fn encrypt_archive<P: AsRef<Path>>(
+        &self,
+        files: &[FileEntry],
+        output: P,
+    ) -> Result<Vec<ArchiveEntry>> {
+        create_archive(files, output, &self.credentials, &self.options)
+    }
+}
+
+/// Decrypts and inspects files with a fixed set of credentials
+///
+/// ```no_run
+/// use cryptocrate::Decryptor;
+///
+/// # fn main() -> cryptocrate::Result<()> {
+/// let metadata = Decryptor::with_password("correct horse battery staple")
+///     .decrypt_file("report.pdf.crat", "report.pdf")?;
+/// println!("restored {}", metadata.filename);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Decryptor {
+    credentials: Credentials,
+    streaming: Option<bool>,
+    threads: usize,
+}
+
+impl Default for Decryptor {
+    fn default() -> Self {
+        Self {
+            credentials: Credentials::default(),
+            streaming: None,
+            threads: 1,
+        }
+    }
+}
+
+impl Decryptor {
+    /// A decryptor with no credentials yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A decryptor using `password`
+    pub fn with_password(password: impl Into<SecretString>) -> Self {
+        Self::new().password(password)
+    }
+
+    /// Use a password (an empty password is ignored)
+    pub fn password(mut self, password: impl Into<SecretString>) -> Self {
+        self.credentials.password = Some(password.into()).filter(|p| !p.is_empty());
+        self
+    }
+
+    /// Use a key file hash (see `keyfile::read_keyfile`)
+    pub fn keyfile(mut self, keyfile_hash: SecretKey) -> Self {
+        self.credentials.keyfile = Some(keyfile_hash);
+        self
+    }
+
+    /// Use the key file at `path`
+    pub fn keyfile_path<P: AsRef<Path>>(self, path: P) -> Result<Self> {
+        Ok(self.keyfile(read_keyfile(path)?))
+    }
+
+    /// Try `identity` against recipient key slots
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.credentials.identities.push(identity);
+        self
+    }
+
+    /// Try the identity file at `path` against recipient key slots
+    pub fn identity_path<P: AsRef<Path>>(self, path: P) -> Result<Self> {
+        Ok(self.identity(read_identity(path)?))
+    }
+
+    /// Replace all credentials
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Force streaming mode on or off
+    ///
+    /// By default files larger than `streaming::STREAMING_THRESHOLD` are streamed.
+    pub fn streaming(mut self, streaming: bool) -> Self {
+        self.streaming = Some(streaming);
+        self
+    }
+
+    /// Worker threads for streamed files (0 = one per CPU, default 1)
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Decrypt `input` into `output`, returning the stored metadata
+    pub fn decrypt_file<P: AsRef<Path>>(&self, input: P, output: P) -> Result<FileMetadata> {
+        let streaming = match self.streaming {
+            Some(streaming) => streaming,
+            None => should_use_streaming(input.as_ref())?,
+        };
+
+        if streaming {
+            decrypt_file_streaming_with_threads(input, output, &self.credentials, self.threads)
+        } else {
+            decrypt_file_with_credentials(input, output, &self.credentials)
+        }
+    }
+
+    /// Decrypt `length` bytes at plaintext `offset` of `input` into `writer`
+    ///
+    /// Chunked files only have the chunks covering the range authenticated;
+    /// single-shot files are decrypted whole. Returns the bytes written.
+    pub fn decrypt_range<P: AsRef<Path>, W: Write>(
+        &self,
+        input: P,
+        offset: u64,
+        length: u64,
+        writer: &mut W,
+    ) -> Result<u64> {
+        decrypt_range_with_credentials(input, writer, &self.credentials, offset, length)
+    }
+
+    /// Decrypt the crate read from `inner`; see `CrateReader`
+    pub fn reader<R: Read>(&self, inner: R) -> Result<CrateReader<R>> {
+        CrateReader::new(inner, &self.credentials)
+    }
+
+    /// Open the archive at `path` and read its index; see `archive`
+    pub fn open_archive<P: AsRef<Path>>(&self, path: P) -> Result<ArchiveReader<File>> {
+        ArchiveReader::open(path, &self.credentials)
+    }
+
+    /// Read the header and metadata of `path` without decrypting the payload
+    pub fn inspect<P: AsRef<Path>>(&self, path: P) -> Result<FileInfo> {
+        inspect_file_with_credentials(path, &self.credentials)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key_derivation::test_params;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_builder_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("plan.txt");
+        let encrypted_path = temp_dir.path().join("plan.txt.crat");
+        let decrypted_path = temp_dir.path().join("out.txt");
+        fs::write(&input_path, b"Builder data ".repeat(50)).unwrap();
+
+        let identity = Identity::generate();
+
+        // Streaming is forced so both code paths see the same options
+        for (streaming, compression) in [(false, "brotli:5"), (true, "xz:1")] {
+            let compression: Compression = compression.parse().unwrap();
+            Encryptor::with_password("builder")
+                .recipient(identity.recipient())
+                .algorithm(CipherAlgorithm::XChaCha20Poly1305)
+                .argon2(test_params())
+                .compression(compression)
+                .streaming(streaming)
+                .encrypt_file(&input_path, &encrypted_path)
+                .unwrap();
+
+            let metadata = Decryptor::with_password("builder")
+                .decrypt_file(&encrypted_path, &decrypted_path)
+                .unwrap();
+            assert_eq!(metadata.compression, compression.algorithm);
+            assert_eq!(fs::read(&decrypted_path).unwrap(), fs::read(&input_path).unwrap());
+
+            let info = Decryptor::new().identity(identity.clone()).inspect(&encrypted_path).unwrap();
+            assert_eq!(info.key_slots, vec!["password".to_string(), "recipient".to_string()]);
+        }
+
+        assert!(Decryptor::with_password("wrong")
+            .decrypt_file(&encrypted_path, &decrypted_path)
+            .is_err());
+    }
+
+    #[test]
+    fn test_auto_compression() {
+        use crate::file_handler::collect_files;
+        use crate::format::read_header;
+        use rand::RngCore;
+
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("album");
+        fs::create_dir(&source).unwrap();
+        let photo_path = source.join("photo.jpg");
+        let notes_path = source.join("notes.txt");
+        let mut photo = b"\xFF\xD8\xFF\xE0".to_vec();
+        photo.resize(3 * crate::streaming::CHUNK_SIZE / 2, 0);
+        rand::thread_rng().fill_bytes(&mut photo[4..]);
+        fs::write(&photo_path, &photo).unwrap();
+        fs::write(&notes_path, b"Taken at the lake. ".repeat(100)).unwrap();
+
+        let encryptor = Encryptor::with_password("auto")
+            .argon2(test_params())
+            .compression("auto:lz4".parse().unwrap());
+        let encrypted_path = temp_dir.path().join("out.crat");
+        let decrypted_path = temp_dir.path().join("out");
+
+        // The decision is recorded per file, whichever path encrypts it
+        for (streaming, threads) in [(false, 1), (true, 1), (true, 4)] {
+            let encryptor = encryptor.clone().streaming(streaming).threads(threads);
+            for (path, expected) in [
+                (&photo_path, CompressionAlgorithm::None),
+                (&notes_path, CompressionAlgorithm::Lz4),
+            ] {
+                let stored = encryptor.encrypt_file(path, &encrypted_path).unwrap();
+                assert_eq!(stored.compression, expected);
+
+                let metadata = Decryptor::with_password("auto")
+                    .decrypt_file(&encrypted_path, &decrypted_path)
+                    .unwrap();
+                assert_eq!(metadata.compression, expected);
+                assert_eq!(fs::read(&decrypted_path).unwrap(), fs::read(path).unwrap());
+            }
+        }
+
+        // Most of the archive's bytes are in the photo, so it is left uncompressed
+        let files = collect_files(&source, None).unwrap();
+        encryptor.encrypt_archive(&files, &encrypted_path).unwrap();
+        let (header, _) = read_header(&mut File::open(&encrypted_path).unwrap()).unwrap();
+        assert!(!header.is_compressed_stream());
+    }
+}
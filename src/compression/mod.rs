@@ -1,15 +1,303 @@
-//! Compression utilities using zstd
+//! Compression before encryption: zstd, lz4, xz and brotli
+//!
+//! Every crate records the algorithm it was compressed with (see
+//! `CompressionAlgorithm::id`), so decompression never depends on the
+//! settings of whoever decrypts it. Single-shot files are compressed in one
+//! go. Streamed files are compressed as one stream that is then cut into
+//! chunks, so memory stays bounded on both sides.
+//!
+//! In `auto` mode each file's first bytes are sampled first (see `sample`),
+//! and files that look compressed already are stored as is.
+
+mod sample;
+
+pub use sample::{looks_compressible, SAMPLE_SIZE};
+pub(crate) use sample::read_sample;
+
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 
 use crate::error::{CrateError, Result};
+use crate::secret::Zeroizing;
 
-/// Default compression level (1-21, higher = better compression but slower)
+/// Default zstd compression level (1-22, higher = better compression but slower)
 const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
 
+/// Brotli window size (log2 of bytes), the reference encoder's default
+const BROTLI_WINDOW: u32 = 22;
+
+/// Internal buffer size for brotli and for `CompressingReader`
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Supported compression algorithms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionAlgorithm {
+    /// Stored as is
+    #[default]
+    None,
+    /// Zstandard: a good balance of speed and ratio
+    Zstd,
+    /// LZ4 frames: the fastest, with a lower ratio
+    Lz4,
+    /// XZ (LZMA2): the best ratio, and slow
+    Xz,
+    /// Brotli: close to xz on text, faster to decompress
+    Brotli,
+}
+
+impl CompressionAlgorithm {
+    /// Identifier stored in the metadata
+    ///
+    /// Older versions stored a 0/1 flag, and always used zstd, which is why it is 1.
+    pub fn id(&self) -> u8 {
+        match self {
+            CompressionAlgorithm::None => 0,
+            CompressionAlgorithm::Zstd => 1,
+            CompressionAlgorithm::Lz4 => 2,
+            CompressionAlgorithm::Xz => 3,
+            CompressionAlgorithm::Brotli => 4,
+        }
+    }
+
+    /// Look up an algorithm by its metadata identifier
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(CompressionAlgorithm::None),
+            1 => Ok(CompressionAlgorithm::Zstd),
+            2 => Ok(CompressionAlgorithm::Lz4),
+            3 => Ok(CompressionAlgorithm::Xz),
+            4 => Ok(CompressionAlgorithm::Brotli),
+            _ => Err(CrateError::InvalidFormat(format!(
+                "Unsupported compression algorithm (id {})",
+                id
+            ))),
+        }
+    }
+
+    /// Human-readable name
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::None => "none",
+            CompressionAlgorithm::Zstd => "zstd",
+            CompressionAlgorithm::Lz4 => "lz4",
+            CompressionAlgorithm::Xz => "xz",
+            CompressionAlgorithm::Brotli => "brotli",
+        }
+    }
+
+    /// Accepted compression levels, or `None` if the algorithm has no levels
+    pub fn levels(&self) -> Option<RangeInclusive<i32>> {
+        match self {
+            CompressionAlgorithm::Zstd => Some(1..=22),
+            CompressionAlgorithm::Xz => Some(0..=9),
+            CompressionAlgorithm::Brotli => Some(0..=11),
+            CompressionAlgorithm::None | CompressionAlgorithm::Lz4 => None,
+        }
+    }
+
+    /// Level used when none is given
+    fn default_level(&self) -> i32 {
+        match self {
+            CompressionAlgorithm::Zstd => DEFAULT_COMPRESSION_LEVEL,
+            CompressionAlgorithm::Xz => 6,
+            CompressionAlgorithm::Brotli => 6,
+            CompressionAlgorithm::None | CompressionAlgorithm::Lz4 => 0,
+        }
+    }
+
+    /// Decompress a whole payload compressed with this algorithm
+    ///
+    /// Fails rather than allocate more than `max_size` bytes. Zstd goes
+    /// through the stream decoder too: `zstd::bulk` reserves `max_size` up
+    /// front, which callers wiping the result would then zero in full.
+    pub fn decompress(&self, data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        match self {
+            CompressionAlgorithm::None => Ok(data.to_vec()),
+            _ => {
+                let mut decompressed = Vec::new();
+                Decoder::new(*self, data)?
+                    .take(max_size as u64 + 1)
+                    .read_to_end(&mut decompressed)
+                    .map_err(decompression_error)?;
+                if decompressed.len() > max_size {
+                    return Err(CrateError::Decryption(format!(
+                        "Decompressed data is larger than {} bytes",
+                        max_size
+                    )));
+                }
+                Ok(decompressed)
+            }
+        }
+    }
+}
+
+impl fmt::Display for CompressionAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for CompressionAlgorithm {
+    type Err = CrateError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "none" | "off" => Ok(CompressionAlgorithm::None),
+            "zstd" | "zstandard" => Ok(CompressionAlgorithm::Zstd),
+            "lz4" => Ok(CompressionAlgorithm::Lz4),
+            "xz" | "lzma" => Ok(CompressionAlgorithm::Xz),
+            "brotli" | "br" => Ok(CompressionAlgorithm::Brotli),
+            _ => Err(CrateError::InvalidFormat(format!(
+                "Unknown compression '{}' (expected none, zstd, lz4, xz or brotli)",
+                s
+            ))),
+        }
+    }
+}
+
+/// How new files are compressed: an algorithm and, optionally, a level
+///
+/// Parses from `algo` or `algo:level`, e.g. `zstd:19` or `lz4`, optionally
+/// prefixed with `auto:`. A bare `auto` means `auto:zstd`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Compression {
+    pub algorithm: CompressionAlgorithm,
+    /// `None` for the algorithm's default
+    pub level: Option<i32>,
+    /// Store files whose first bytes look incompressible as is
+    pub auto: bool,
+}
+
+impl Compression {
+    /// No compression
+    pub const NONE: Compression = Compression {
+        algorithm: CompressionAlgorithm::None,
+        level: None,
+        auto: false,
+    };
+
+    /// `algorithm` at `level`, checking that the level is valid for it
+    pub fn new(algorithm: CompressionAlgorithm, level: Option<i32>) -> Result<Self> {
+        if let Some(level) = level {
+            match algorithm.levels() {
+                Some(levels) if levels.contains(&level) => {}
+                Some(levels) => {
+                    return Err(CrateError::InvalidFormat(format!(
+                        "{} levels are {}-{}, not {}",
+                        algorithm,
+                        levels.start(),
+                        levels.end(),
+                        level
+                    )))
+                }
+                None => {
+                    return Err(CrateError::InvalidFormat(format!(
+                        "{} has no compression levels",
+                        algorithm
+                    )))
+                }
+            }
+        }
+        Ok(Self {
+            algorithm,
+            level,
+            auto: false,
+        })
+    }
+
+    /// Whether anything is compressed at all
+    pub fn is_enabled(&self) -> bool {
+        self.algorithm != CompressionAlgorithm::None
+    }
+
+    /// The level in effect
+    pub fn level(&self) -> i32 {
+        self.level.unwrap_or_else(|| self.algorithm.default_level())
+    }
+
+    /// The compression to use for contents starting with `sample`
+    ///
+    /// Outside `auto` mode this is always `self`.
+    pub fn for_sample(&self, sample: &[u8]) -> Compression {
+        if self.auto && !looks_compressible(sample) {
+            Compression::NONE
+        } else {
+            *self
+        }
+    }
+
+    /// Compress a whole payload
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self.algorithm {
+            CompressionAlgorithm::None => Ok(data.to_vec()),
+            CompressionAlgorithm::Zstd => compress(data, Some(self.level())),
+            _ => {
+                let mut encoder = Encoder::new(*self, Vec::new())?;
+                encoder.write_all(data).map_err(compression_error)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+impl From<CompressionAlgorithm> for Compression {
+    fn from(algorithm: CompressionAlgorithm) -> Self {
+        Self {
+            algorithm,
+            level: None,
+            auto: false,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.auto {
+            write!(f, "auto:")?;
+        }
+        match self.level {
+            Some(level) => write!(f, "{}:{}", self.algorithm, level),
+            None => write!(f, "{}", self.algorithm),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = CrateError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("auto") {
+            return "auto:zstd".parse();
+        }
+        if s.get(..5).is_some_and(|prefix| prefix.eq_ignore_ascii_case("auto:")) {
+            let compression: Compression = s[5..].parse()?;
+            return Ok(Self {
+                auto: true,
+                ..compression
+            });
+        }
+
+        let (algorithm, level) = match s.split_once(':') {
+            Some((algorithm, level)) => {
+                let level = level.trim().parse().map_err(|_| {
+                    CrateError::InvalidFormat(format!("Invalid compression level '{}'", level))
+                })?;
+                (algorithm, Some(level))
+            }
+            None => (s, None),
+        };
+        Self::new(algorithm.trim().parse()?, level)
+    }
+}
+
 /// Compress data using zstd
 ///
 /// # Arguments
 /// * `data` - Data to compress
-/// * `level` - Compression level (1-21, default: 3)
+/// * `level` - Compression level (1-22, default: 3)
 ///
 /// # Returns
 /// Compressed data
@@ -35,6 +323,260 @@ pub fn decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>> {
     Ok(decompressed)
 }
 
+/// A streaming compressor for any algorithm, writing into `W`
+///
+/// Only used over in-memory buffers: brotli cannot report write errors
+/// while finishing its stream.
+enum Encoder<W: Write> {
+    None(W),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    Lz4(lz4_flex::frame::FrameEncoder<W>),
+    Xz(xz2::write::XzEncoder<W>),
+    Brotli(Box<brotli::CompressorWriter<W>>),
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(compression: Compression, writer: W) -> Result<Self> {
+        let level = compression.level();
+        Ok(match compression.algorithm {
+            CompressionAlgorithm::None => Encoder::None(writer),
+            CompressionAlgorithm::Zstd => Encoder::Zstd(
+                zstd::stream::write::Encoder::new(writer, level).map_err(compression_error)?,
+            ),
+            CompressionAlgorithm::Lz4 => Encoder::Lz4(lz4_flex::frame::FrameEncoder::new(writer)),
+            CompressionAlgorithm::Xz => {
+                Encoder::Xz(xz2::write::XzEncoder::new(writer, level as u32))
+            }
+            CompressionAlgorithm::Brotli => Encoder::Brotli(Box::new(
+                brotli::CompressorWriter::new(writer, BUFFER_SIZE, level as u32, BROTLI_WINDOW),
+            )),
+        })
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        match self {
+            Encoder::None(writer) => writer,
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+            Encoder::Lz4(encoder) => encoder.get_mut(),
+            Encoder::Xz(encoder) => encoder.get_mut(),
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+        }
+    }
+
+    /// End the stream and return the writer
+    fn finish(self) -> Result<W> {
+        match self {
+            Encoder::None(writer) => Ok(writer),
+            Encoder::Zstd(encoder) => encoder.finish().map_err(compression_error),
+            Encoder::Lz4(encoder) => encoder.finish().map_err(|e| compression_error(e.into())),
+            Encoder::Xz(encoder) => encoder.finish().map_err(compression_error),
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::None(writer) => writer.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+            Encoder::Lz4(encoder) => encoder.write(buf),
+            Encoder::Xz(encoder) => encoder.write(buf),
+            Encoder::Brotli(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(writer) => writer.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+            Encoder::Lz4(encoder) => encoder.flush(),
+            Encoder::Xz(encoder) => encoder.flush(),
+            Encoder::Brotli(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Compressed output waiting to be picked up, zeroed when dropped
+struct Output(Zeroizing<Vec<u8>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A stream compressor that is fed one piece at a time
+pub(crate) struct StreamCompressor {
+    encoder: Encoder<Output>,
+}
+
+impl StreamCompressor {
+    pub(crate) fn new(compression: Compression) -> Result<Self> {
+        Ok(Self {
+            encoder: Encoder::new(compression, Output(Zeroizing::new(Vec::new())))?,
+        })
+    }
+
+    /// Compress `input`, handing compressed bytes to `sink` as they come out
+    pub(crate) fn compress(
+        &mut self,
+        input: &[u8],
+        mut sink: impl FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        self.encoder.write_all(input).map_err(compression_error)?;
+        let output = &mut self.encoder.get_mut().0;
+        if !output.is_empty() {
+            sink(output)?;
+            output.clear();
+        }
+        Ok(())
+    }
+
+    /// End the stream, handing the remaining compressed bytes to `sink`
+    pub(crate) fn finish(self, mut sink: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        let output = self.encoder.finish()?;
+        sink(&output.0)
+    }
+}
+
+/// Compresses everything read from `reader` into one stream
+pub(crate) struct CompressingReader<R: Read> {
+    reader: R,
+    compressor: Option<StreamCompressor>,
+    input: Zeroizing<Vec<u8>>,
+    output: Zeroizing<Vec<u8>>,
+    position: usize,
+}
+
+impl<R: Read> CompressingReader<R> {
+    pub(crate) fn new(reader: R, compression: Compression) -> Result<Self> {
+        Ok(Self {
+            reader,
+            compressor: Some(StreamCompressor::new(compression)?),
+            input: Zeroizing::new(vec![0u8; BUFFER_SIZE]),
+            output: Zeroizing::new(Vec::new()),
+            position: 0,
+        })
+    }
+}
+
+impl<R: Read> Read for CompressingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.output.len() {
+            let Some(compressor) = self.compressor.as_mut() else {
+                return Ok(0);
+            };
+            self.output.clear();
+            self.position = 0;
+
+            let output = &mut self.output;
+            let collect = |compressed: &[u8]| {
+                output.extend_from_slice(compressed);
+                Ok(())
+            };
+            match self.reader.read(&mut self.input) {
+                Ok(0) => self
+                    .compressor
+                    .take()
+                    .expect("checked above")
+                    .finish(collect)?,
+                Ok(n) => compressor.compress(&self.input[..n], collect)?,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let len = buf.len().min(self.output.len() - self.position);
+        buf[..len].copy_from_slice(&self.output[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// A streaming decompressor for any algorithm, reading compressed bytes from `R`
+pub(crate) enum Decoder<R: BufRead> {
+    None(R),
+    Zstd(zstd::stream::read::Decoder<'static, R>),
+    Lz4(lz4_flex::frame::FrameDecoder<R>),
+    Xz(xz2::bufread::XzDecoder<R>),
+    Brotli(Box<brotli::Decompressor<R>>),
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub(crate) fn new(algorithm: CompressionAlgorithm, reader: R) -> Result<Self> {
+        Ok(match algorithm {
+            CompressionAlgorithm::None => Decoder::None(reader),
+            CompressionAlgorithm::Zstd => Decoder::Zstd(
+                zstd::stream::read::Decoder::with_buffer(reader).map_err(decompression_error)?,
+            ),
+            CompressionAlgorithm::Lz4 => Decoder::Lz4(lz4_flex::frame::FrameDecoder::new(reader)),
+            CompressionAlgorithm::Xz => Decoder::Xz(xz2::bufread::XzDecoder::new(reader)),
+            CompressionAlgorithm::Brotli => {
+                Decoder::Brotli(Box::new(brotli::Decompressor::new(reader, BUFFER_SIZE)))
+            }
+        })
+    }
+
+    pub(crate) fn get_ref(&self) -> &R {
+        match self {
+            Decoder::None(reader) => reader,
+            Decoder::Zstd(decoder) => decoder.get_ref(),
+            Decoder::Lz4(decoder) => decoder.get_ref(),
+            Decoder::Xz(decoder) => decoder.get_ref(),
+            Decoder::Brotli(decoder) => decoder.get_ref(),
+        }
+    }
+
+    /// The reader, with whatever the decoder has not consumed yet
+    pub(crate) fn into_inner(self) -> R {
+        match self {
+            Decoder::None(reader) => reader,
+            Decoder::Zstd(decoder) => decoder.finish(),
+            Decoder::Lz4(decoder) => decoder.into_inner(),
+            Decoder::Xz(decoder) => decoder.into_inner(),
+            Decoder::Brotli(decoder) => decoder.into_inner(),
+        }
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    /// Errors from `R` come through unchanged; anything else is reported as corrupt data
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = match self {
+            Decoder::None(reader) => reader.read(buf),
+            Decoder::Zstd(decoder) => decoder.read(buf),
+            Decoder::Lz4(decoder) => decoder.read(buf),
+            Decoder::Xz(decoder) => decoder.read(buf),
+            Decoder::Brotli(decoder) => decoder.read(buf),
+        };
+        result.map_err(|e| {
+            if e.get_ref().is_some_and(|inner| inner.is::<CrateError>()) {
+                e
+            } else {
+                decompression_error(e).into()
+            }
+        })
+    }
+}
+
+fn compression_error(e: io::Error) -> CrateError {
+    CrateError::Encryption(format!("Compression failed: {}", e))
+}
+
+fn decompression_error(e: io::Error) -> CrateError {
+    // Already described (e.g. by `Decoder::read`)
+    if e.get_ref().is_some_and(|inner| inner.is::<CrateError>()) {
+        return e.into();
+    }
+    CrateError::Decryption(format!("Decompression failed: {}", e))
+}
+
 /// Calculate compression ratio
 pub fn compression_ratio(original_size: usize, compressed_size: usize) -> f64 {
     if original_size == 0 {
@@ -58,6 +600,81 @@ mod tests {
         assert_eq!(decompressed, original);
     }
 
+    #[test]
+    fn test_every_algorithm() {
+        let original = b"Hello, World! This is a test of compression. ".repeat(1000);
+
+        for spec in ["zstd:19", "lz4", "xz:1", "brotli", "none"] {
+            let compression: Compression = spec.parse().unwrap();
+            let algorithm = compression.algorithm;
+            assert_eq!(
+                CompressionAlgorithm::from_id(algorithm.id()).unwrap(),
+                algorithm
+            );
+
+            let compressed = compression.compress(&original).unwrap();
+            assert_eq!(compressed.len() < original.len(), compression.is_enabled());
+            assert_eq!(
+                algorithm.decompress(&compressed, original.len()).unwrap(),
+                original
+            );
+            if compression.is_enabled() {
+                assert!(algorithm
+                    .decompress(&compressed, original.len() - 1)
+                    .is_err());
+            }
+
+            // Compressed piece by piece, and read back incrementally
+            let mut streamed = Vec::new();
+            let mut compressor = StreamCompressor::new(compression).unwrap();
+            for piece in original.chunks(7777) {
+                compressor
+                    .compress(piece, |data| {
+                        streamed.extend_from_slice(data);
+                        Ok(())
+                    })
+                    .unwrap();
+            }
+            compressor
+                .finish(|data| {
+                    streamed.extend_from_slice(data);
+                    Ok(())
+                })
+                .unwrap();
+            let mut decompressed = Vec::new();
+            let mut decoder = Decoder::new(algorithm, &streamed[..]).unwrap();
+            decoder.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, original);
+
+            let mut pulled = Vec::new();
+            let mut reader = CompressingReader::new(&original[..], compression).unwrap();
+            reader.read_to_end(&mut pulled).unwrap();
+            let decompressed = algorithm.decompress(&pulled, original.len()).unwrap();
+            assert_eq!(decompressed, original);
+        }
+
+        assert!("lz4:3".parse::<Compression>().is_err());
+        assert!("zstd:30".parse::<Compression>().is_err());
+        assert!("gzip".parse::<Compression>().is_err());
+        assert_eq!("LZMA:9".parse::<Compression>().unwrap().to_string(), "xz:9");
+    }
+
+    #[test]
+    fn test_auto_compression() {
+        let auto: Compression = "auto".parse().unwrap();
+        assert_eq!(auto.to_string(), "auto:zstd");
+        assert_eq!("Auto:xz:9".parse::<Compression>().unwrap().to_string(), "auto:xz:9");
+        assert!("auto:gzip".parse::<Compression>().is_err());
+
+        let text = b"compressible ".repeat(100);
+        assert_eq!(auto.for_sample(&text), auto);
+        assert_eq!(auto.for_sample(b"PK\x03\x04 zipped"), Compression::NONE);
+
+        // Without `auto`, the sample is not looked at
+        let zstd: Compression = "zstd".parse().unwrap();
+        assert_eq!(zstd.for_sample(b"PK\x03\x04 zipped"), zstd);
+    }
+
     #[test]
     fn test_compression_ratio() {
         let ratio = compression_ratio(1000, 500);
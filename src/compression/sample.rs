@@ -0,0 +1,109 @@
+//! Content sampling for `auto` compression
+//!
+//! Images, video and archives are compressed already and only grow when
+//! compressed again. Their first bytes usually give them away, either through
+//! a well-known signature or through near-random byte statistics.
+
+use std::io::{self, Read};
+
+use crate::format::MAGIC_BYTES;
+use crate::secret::Zeroizing;
+
+/// Bytes read from the start of a file to decide whether to compress it
+pub const SAMPLE_SIZE: usize = 64 * 1024;
+
+/// Entropy (bits per byte) from which a sample counts as incompressible
+const ENTROPY_THRESHOLD: f64 = 7.5;
+
+/// Signatures of formats that are compressed already, at their offsets
+const COMPRESSED_SIGNATURES: &[(usize, &[u8])] = &[
+    (0, b"\xFF\xD8\xFF"),         // JPEG
+    (0, b"\x89PNG\r\n\x1A\n"),    // PNG
+    (0, b"GIF8"),                 // GIF
+    (8, b"WEBP"),                 // WebP
+    (4, b"ftyp"),                 // MP4, MOV, HEIC, M4A
+    (0, b"\x1A\x45\xDF\xA3"),     // Matroska, WebM
+    (0, b"ID3"),                  // MP3
+    (0, b"OggS"),                 // Ogg
+    (0, b"fLaC"),                 // FLAC
+    (0, b"PK\x03\x04"),           // ZIP, JAR, Office documents
+    (0, b"\x1F\x8B"),             // gzip
+    (0, b"BZh"),                  // bzip2
+    (0, b"\xFD7zXZ\x00"),         // xz
+    (0, b"\x28\xB5\x2F\xFD"),     // zstd
+    (0, b"\x04\x22\x4D\x18"),     // lz4
+    (0, b"7z\xBC\xAF\x27\x1C"),   // 7-Zip
+    (0, b"Rar!\x1A\x07"),         // RAR
+    (0, MAGIC_BYTES),             // CryptoCrate
+];
+
+/// Whether `data` looks worth compressing, judging by its first `SAMPLE_SIZE` bytes
+///
+/// Empty samples count as compressible.
+pub fn looks_compressible(data: &[u8]) -> bool {
+    let sample = &data[..data.len().min(SAMPLE_SIZE)];
+    let known_format = COMPRESSED_SIGNATURES.iter().any(|(offset, signature)| {
+        sample
+            .get(*offset..)
+            .is_some_and(|rest| rest.starts_with(signature))
+    });
+    !known_format && entropy(sample) < ENTROPY_THRESHOLD
+}
+
+/// Shannon entropy of `data` in bits per byte (0 when empty)
+fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Read up to `SAMPLE_SIZE` bytes from the start of `reader`
+pub(crate) fn read_sample<R: Read>(reader: R) -> io::Result<Zeroizing<Vec<u8>>> {
+    let mut sample = Zeroizing::new(Vec::with_capacity(SAMPLE_SIZE));
+    reader.take(SAMPLE_SIZE as u64).read_to_end(&mut sample)?;
+    Ok(sample)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    #[test]
+    fn test_looks_compressible() {
+        let text = b"2024-05-01 12:00:00 INFO request served in 12ms\n".repeat(2000);
+        assert!(looks_compressible(&text));
+        assert!(looks_compressible(b""));
+
+        let mut random = vec![0u8; SAMPLE_SIZE];
+        rand::thread_rng().fill_bytes(&mut random);
+        assert!(!looks_compressible(&random));
+
+        // Signatures win even when the rest of the sample is plain text
+        let mut jpeg = b"\xFF\xD8\xFF\xE0".to_vec();
+        jpeg.extend_from_slice(&text);
+        assert!(!looks_compressible(&jpeg));
+        let mut mp4 = b"\x00\x00\x00\x20ftypisom".to_vec();
+        mp4.extend_from_slice(&text);
+        assert!(!looks_compressible(&mp4));
+
+        let zstd = crate::compression::compress(&text, None).unwrap();
+        assert!(!looks_compressible(&zstd));
+
+        // Only the start is sampled
+        let mut tail = text.clone();
+        tail.extend_from_slice(&random);
+        assert!(looks_compressible(&tail));
+    }
+}
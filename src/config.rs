@@ -4,6 +4,9 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use crate::compression::{Compression, CompressionAlgorithm};
+use crate::crypto::cipher::CipherAlgorithm;
+use crate::crypto::key_derivation::Argon2Params;
 use crate::error::{CrateError, Result};
 
 /// Default configuration file name
@@ -12,10 +15,15 @@ const CONFIG_FILE_NAME: &str = "cryptocrate.toml";
 /// CryptoCrate configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    /// Default compression level (1-21)
+    /// Default compression level (zstd, 1-22)
     #[serde(default = "default_compression_level")]
     pub compression_level: i32,
 
+    /// Compression algorithm, optionally with a level (`zstd`, `lz4`, `xz:9`, `brotli:11`, ...),
+    /// prefixed with `auto:` to skip files that look compressed already
+    #[serde(default = "default_compression")]
+    pub compression: String,
+
     /// Enable compression by default
     #[serde(default)]
     pub compress_by_default: bool,
@@ -32,6 +40,14 @@ pub struct Config {
     #[serde(default = "default_true")]
     pub show_detailed_progress: bool,
 
+    /// Encrypt filenames, sizes and timestamps stored in crates
+    #[serde(default = "default_true")]
+    pub encrypt_metadata: bool,
+
+    /// Encryption algorithm for new files (aes-256-gcm or xchacha20-poly1305)
+    #[serde(default = "default_algorithm")]
+    pub algorithm: String,
+
     /// Argon2 memory cost in KB
     #[serde(default = "default_argon2_memory")]
     pub argon2_memory_kb: u32,
@@ -43,16 +59,36 @@ pub struct Config {
     /// Argon2 parallelism
     #[serde(default = "default_argon2_parallelism")]
     pub argon2_parallelism: u32,
+
+    /// Worker threads for large (streamed) files (0 = one per CPU)
+    #[serde(default)]
+    pub threads: usize,
+
+    /// Files encrypted or decrypted at the same time (0 = one per CPU)
+    #[serde(default = "default_jobs")]
+    pub jobs: usize,
+
+    /// Record Unix permissions, ownership, xattrs and symlinks when encrypting
+    #[serde(default)]
+    pub preserve_attributes: bool,
 }
 
 fn default_compression_level() -> i32 {
     3
 }
 
+fn default_compression() -> String {
+    "zstd".to_string()
+}
+
 fn default_true() -> bool {
     true
 }
 
+fn default_algorithm() -> String {
+    "aes-256-gcm".to_string()
+}
+
 fn default_argon2_memory() -> u32 {
     65536 // 64 MB
 }
@@ -65,17 +101,27 @@ fn default_argon2_parallelism() -> u32 {
     4
 }
 
+fn default_jobs() -> usize {
+    1
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             compression_level: default_compression_level(),
+            compression: default_compression(),
             compress_by_default: false,
             default_output_dir: None,
             confirm_overwrite: true,
             show_detailed_progress: true,
+            encrypt_metadata: true,
+            algorithm: default_algorithm(),
             argon2_memory_kb: default_argon2_memory(),
             argon2_time_cost: default_argon2_time(),
             argon2_parallelism: default_argon2_parallelism(),
+            threads: 0,
+            jobs: default_jobs(),
+            preserve_attributes: false,
         }
     }
 }
@@ -84,7 +130,7 @@ impl Config {
     /// Load configuration from a file
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
         let content = fs::read_to_string(path.as_ref())
-            .map_err(|e| CrateError::Io(e))?;
+            .map_err(CrateError::Io)?;
         
         let config: Config = toml::from_str(&content)
             .map_err(|e| CrateError::InvalidFormat(format!("Invalid config file: {}", e)))?;
@@ -127,6 +173,34 @@ impl Config {
         Ok(())
     }
 
+    /// Encryption algorithm for newly encrypted files
+    pub fn cipher_algorithm(&self) -> Result<CipherAlgorithm> {
+        self.algorithm.parse()
+    }
+
+    /// Compression for newly encrypted files, when compression is on
+    ///
+    /// `compression_level` applies to zstd when `compression` names no level.
+    pub fn compression(&self) -> Result<Compression> {
+        let compression: Compression = self.compression.parse()?;
+        if compression.algorithm == CompressionAlgorithm::Zstd && compression.level.is_none() {
+            return Ok(Compression {
+                auto: compression.auto,
+                ..Compression::new(CompressionAlgorithm::Zstd, Some(self.compression_level))?
+            });
+        }
+        Ok(compression)
+    }
+
+    /// Argon2id parameters to record in newly encrypted files
+    pub fn argon2_params(&self) -> Argon2Params {
+        Argon2Params {
+            memory_kb: self.argon2_memory_kb,
+            time_cost: self.argon2_time_cost,
+            parallelism: self.argon2_parallelism,
+        }
+    }
+
     /// Get the default user config path
     pub fn default_user_config_path() -> Option<PathBuf> {
         dirs::home_dir().map(|home| {
@@ -139,9 +213,17 @@ impl Config {
         r#"# CryptoCrate Configuration File
 # This file configures default behavior for the cryptocrate tool.
 
-# Default compression level (1-21, higher = better compression but slower)
+# Default compression level (zstd, 1-22, higher = better compression but slower)
 compression_level = 3
 
+# Compression algorithm, optionally with a level (can be overridden with
+# --compression): "zstd" (balanced), "lz4" (fastest), "xz" (smallest, slow)
+# or "brotli". Levels: zstd 1-22 (defaults to compression_level above),
+# xz 0-9, brotli 0-11; lz4 has none. Example: "xz:9"
+# Prefix with "auto:" (or use just "auto") to sample each file first and
+# store images, video and archives uncompressed: "auto:xz:9"
+compression = "zstd"
+
 # Enable compression by default (can be overridden with --compress flag)
 compress_by_default = false
 
@@ -154,11 +236,34 @@ confirm_overwrite = true
 # Show detailed progress information
 show_detailed_progress = true
 
+# Encrypt filenames, sizes and timestamps so `inspect` cannot read them
+# without the password (can be overridden with --plain-metadata)
+encrypt_metadata = true
+
+# Encryption algorithm: "aes-256-gcm" (fastest with hardware AES) or
+# "xchacha20-poly1305" (fast everywhere; can be overridden with --algorithm)
+algorithm = "aes-256-gcm"
+
 # Argon2 key derivation parameters (advanced users only)
-# Higher values = more secure but slower
+# Higher values = more secure but slower. They are stored in every new file,
+# so changing them never affects decryption of existing files.
 argon2_memory_kb = 65536  # 64 MB
 argon2_time_cost = 3       # iterations
 argon2_parallelism = 4     # threads
+
+# Worker threads that encrypt/decrypt chunks of large (streamed) files in
+# parallel. 0 = one per CPU core, 1 = single-threaded. The output is the
+# same either way.
+threads = 0
+
+# Files processed at the same time when encrypting/decrypting many files
+# (can be overridden with --jobs). 0 = one per CPU core. Each job runs its
+# own Argon2 derivation, so memory use grows with the job count.
+jobs = 1
+
+# Record Unix permissions, owner, extended attributes and symlinks so that
+# `decrypt` can restore them (can be enabled per run with --preserve)
+preserve_attributes = false
 "#.to_string()
     }
 }
@@ -174,6 +279,14 @@ mod tests {
         assert_eq!(config.compression_level, 3);
         assert!(!config.compress_by_default);
         assert!(config.confirm_overwrite);
+        assert!(config.encrypt_metadata);
+        assert_eq!(config.cipher_algorithm().unwrap(), CipherAlgorithm::Aes256Gcm);
+        assert_eq!(config.compression().unwrap().to_string(), "zstd:3");
+        let config = Config {
+            compression: "auto".to_string(),
+            ..Config::default()
+        };
+        assert_eq!(config.compression().unwrap().to_string(), "auto:zstd:3");
     }
 
     #[test]
@@ -1,24 +1,57 @@
-//! File encryption and decryption using AES-256-GCM
+//! File encryption and decryption using AES-256-GCM or XChaCha20-Poly1305
 
-use aes_gcm::{
-    aead::{Aead, KeyInit},
-    Aes256Gcm, Nonce,
-};
-use rand::RngCore;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use std::path::Path;
 
-use crate::compression;
-use crate::crypto::key_derivation::derive_key;
+use crate::compression::{Compression, CompressionAlgorithm};
+use crate::crypto::cipher::{Cipher, CipherAlgorithm};
+use crate::crypto::key_derivation::Argon2Params;
+use crate::crypto::keyslot::{create_file_key, Credentials};
 use crate::error::{CrateError, Result};
+use crate::identity::Recipient;
 use crate::format::*;
 use crate::metadata::FileMetadata;
+use crate::secret::Zeroizing;
+use crate::streaming::decrypt_file_streaming_with_credentials;
 
 /// Maximum decompressed file size (1 GB safety limit)
-const MAX_DECOMPRESSED_SIZE: usize = 1024 * 1024 * 1024;
+pub(crate) const MAX_DECOMPRESSED_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Options controlling how files are encrypted
+#[derive(Debug, Clone)]
+pub struct EncryptOptions {
+    /// Algorithm and level to compress with before encrypting
+    pub compression: Compression,
+    /// Encrypt the metadata block so filename, size and timestamps stay private
+    pub encrypt_metadata: bool,
+    /// Argon2id cost parameters, recorded in the header
+    pub argon2: Argon2Params,
+    /// AEAD algorithm, recorded in the header
+    pub algorithm: CipherAlgorithm,
+    /// Public keys that get a key slot of their own
+    pub recipients: Vec<Recipient>,
+    /// Worker threads for sealing chunks in streaming mode (0 = one per CPU)
+    pub threads: usize,
+    /// Record permissions, ownership, xattrs and symlinks (see `file_handler::attributes`)
+    pub preserve_attributes: bool,
+}
 
-/// Encrypt a file with AES-256-GCM
+impl Default for EncryptOptions {
+    fn default() -> Self {
+        Self {
+            compression: Compression::NONE,
+            encrypt_metadata: true,
+            argon2: Argon2Params::default(),
+            algorithm: CipherAlgorithm::default(),
+            recipients: Vec::new(),
+            threads: 1,
+            preserve_attributes: false,
+        }
+    }
+}
+
+/// Encrypt a file with the default algorithm (AES-256-GCM)
 ///
 /// # Arguments
 /// * `input_path` - Path to the file to encrypt
@@ -34,51 +67,91 @@ pub fn encrypt_file<P: AsRef<Path>>(
     password: &str,
     compress: bool,
 ) -> Result<()> {
+    let options = EncryptOptions {
+        compression: if compress {
+            CompressionAlgorithm::Zstd.into()
+        } else {
+            Compression::NONE
+        },
+        ..EncryptOptions::default()
+    };
+    encrypt_file_with_options(input_path, output_path, &Credentials::password(password), &options)
+}
+
+/// Encrypt a file using explicit options
+///
+/// The payload is encrypted with a random file key, wrapped in a key slot
+/// for `credentials` and one for each of `options.recipients`.
+pub fn encrypt_file_with_options<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    credentials: &Credentials,
+    options: &EncryptOptions,
+) -> Result<()> {
+    encrypt_entry_with_options(input_path, output_path, None, credentials, options)?;
+    Ok(())
+}
+
+/// Like `encrypt_file_with_options`, recording `relative_path` in the metadata
+///
+/// Returns the metadata that was stored.
+pub(crate) fn encrypt_entry_with_options<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    relative_path: Option<String>,
+    credentials: &Credentials,
+    options: &EncryptOptions,
+) -> Result<FileMetadata> {
     let input_path = input_path.as_ref();
     let output_path = output_path.as_ref();
 
-    // Read input file
-    let mut input_file = File::open(input_path)
-        .map_err(|e| CrateError::FileNotFound(format!("{}: {}", input_path.display(), e)))?;
+    let mut metadata = FileMetadata {
+        relative_path,
+        ..FileMetadata::capture(
+            input_path,
+            options.compression.algorithm,
+            options.preserve_attributes,
+        )?
+    };
 
-    let mut plaintext = Vec::new();
-    input_file.read_to_end(&mut plaintext)?;
+    // Read input file (a preserved symlink has no contents of its own)
+    let mut plaintext = Zeroizing::new(Vec::new());
+    if !metadata.attributes.is_symlink() {
+        let mut input_file = File::open(input_path)
+            .map_err(|e| CrateError::FileNotFound(format!("{}: {}", input_path.display(), e)))?;
+        input_file.read_to_end(&mut plaintext)?;
+    }
 
-    // Compress if requested
-    let data_to_encrypt = if compress && !plaintext.is_empty() {
-        compression::compress(&plaintext, None)?
+    // Compress if requested (an empty file, or with `auto` one that looks
+    // compressed already, is stored as is)
+    let compression = options.compression.for_sample(&plaintext);
+    if plaintext.is_empty() || !compression.is_enabled() {
+        metadata.compression = CompressionAlgorithm::None;
+    }
+    let data_to_encrypt = if metadata.is_compressed() {
+        Zeroizing::new(compression.compress(&plaintext)?)
     } else {
-        plaintext.clone()
+        plaintext
     };
 
-    // Generate random salt and nonce
-    let mut salt = [0u8; SALT_LENGTH];
-    let mut nonce_bytes = [0u8; NONCE_LENGTH];
-
-    let mut rng = rand::thread_rng();
-    rng.fill_bytes(&mut salt);
-    rng.fill_bytes(&mut nonce_bytes);
-
-    // Derive encryption key from password
-    let key = derive_key(password, &salt)?;
+    // Create header with a random nonce and file key
+    let nonce_bytes = options.algorithm.random_nonce();
+    let mut header = FileHeader::new(options.algorithm, KdfDescriptor::KeySlots, nonce_bytes, 0);
+    if options.encrypt_metadata {
+        header.flags |= FLAG_ENCRYPTED_METADATA;
+    }
+    let key = create_file_key(&mut header, credentials, &options.recipients, &options.argon2)?;
 
     // Create cipher
-    let cipher = Aes256Gcm::new_from_slice(&key)
-        .map_err(|e| CrateError::Encryption(e.to_string()))?;
-
-    let nonce = Nonce::from_slice(&nonce_bytes);
+    let cipher = Cipher::new(options.algorithm, &key)?;
 
-    // Encrypt the data
-    let ciphertext = cipher
-        .encrypt(nonce, data_to_encrypt.as_ref())
-        .map_err(|e| CrateError::Encryption(e.to_string()))?;
-
-    // Create metadata
-    let metadata = FileMetadata::from_file(input_path, compress)?;
+    // Create the (optionally encrypted) metadata block
     let metadata_bytes = metadata.to_bytes();
+    let metadata_block = seal_metadata(&cipher, &mut header, &metadata_bytes)?;
 
-    // Create header
-    let header = FileHeader::new(salt, nonce_bytes, metadata_bytes.len() as u32);
+    // Encrypt the data, authenticating header and metadata alongside it
+    let aad = header.associated_data(&metadata_block);
+    let ciphertext = cipher.encrypt(&header.nonce, &data_to_encrypt, &aad)?;
 
     // Write encrypted file
     let mut output_file = OpenOptions::new()
@@ -87,33 +160,18 @@ pub fn encrypt_file<P: AsRef<Path>>(
         .truncate(true)
         .open(output_path)?;
 
-    // Write magic bytes
-    output_file.write_all(MAGIC_BYTES)?;
-
-    // Write version
-    output_file.write_all(&[header.version])?;
-
-    // Write algorithm
-    output_file.write_all(&[header.algorithm])?;
-
-    // Write salt
-    output_file.write_all(&header.salt)?;
-
-    // Write nonce
-    output_file.write_all(&header.nonce)?;
-
-    // Write metadata length
-    output_file.write_all(&header.metadata_length.to_le_bytes())?;
+    // Write header
+    header.write_to(&mut output_file)?;
 
     // Write metadata
-    output_file.write_all(&metadata_bytes)?;
+    output_file.write_all(&metadata_block)?;
 
     // Write encrypted data
     output_file.write_all(&ciphertext)?;
 
     output_file.flush()?;
 
-    Ok(())
+    Ok(metadata)
 }
 
 /// Decrypt a file encrypted with CryptoCrate
@@ -130,97 +188,92 @@ pub fn decrypt_file<P: AsRef<Path>>(
     output_path: P,
     password: &str,
 ) -> Result<FileMetadata> {
-    let input_path = input_path.as_ref();
-    let output_path = output_path.as_ref();
+    decrypt_file_with_credentials(input_path, output_path, &Credentials::password(password))
+}
 
-    // Read encrypted file
-    let mut input_file = File::open(input_path)
-        .map_err(|e| CrateError::FileNotFound(format!("{}: {}", input_path.display(), e)))?;
+/// Decrypt a file with a password, a key file, or both
+///
+/// Chunked files are verified and written chunk by chunk; single-shot files
+/// are decrypted and decompressed in memory.
+pub fn decrypt_file_with_credentials<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    credentials: &Credentials,
+) -> Result<FileMetadata> {
+    // The reader handles single-shot and chunked payloads alike
+    decrypt_file_streaming_with_credentials(input_path, output_path, credentials)
+}
 
-    // Read and verify magic bytes
-    let mut magic = [0u8; 4];
-    input_file.read_exact(&mut magic)?;
-    if &magic != MAGIC_BYTES {
-        return Err(CrateError::InvalidFormat(
-            "Not a valid CryptoCrate file".to_string(),
-        ));
+/// Build the on-disk metadata block for `header`, updating its metadata length
+///
+/// When the header carries `FLAG_ENCRYPTED_METADATA` the serialized metadata
+/// is sealed under the file key with a fresh nonce, authenticating the
+/// header (minus key slots) as associated data. Otherwise it is stored as is.
+pub(crate) fn seal_metadata(
+    cipher: &Cipher,
+    header: &mut FileHeader,
+    metadata_bytes: &[u8],
+) -> Result<Vec<u8>> {
+    if !header.has_encrypted_metadata() {
+        header.metadata_length = metadata_bytes.len() as u32;
+        return Ok(metadata_bytes.to_vec());
     }
 
-    // Read version
-    let mut version = [0u8; 1];
-    input_file.read_exact(&mut version)?;
-    if version[0] != VERSION {
-        return Err(CrateError::UnsupportedVersion(version[0]));
+    header.metadata_length = header.metadata_block_length(metadata_bytes.len()) as u32;
+
+    let mut block = header.algorithm.random_nonce();
+    let ciphertext = cipher.encrypt(&block, metadata_bytes, &header.authenticated_bytes())?;
+    block.extend_from_slice(&ciphertext);
+    Ok(block)
+}
+
+/// Recover the metadata from an on-disk metadata block
+pub(crate) fn open_metadata(
+    cipher: &Cipher,
+    header: &FileHeader,
+    metadata_block: &[u8],
+) -> Result<FileMetadata> {
+    if !header.has_encrypted_metadata() {
+        return FileMetadata::from_bytes(metadata_block);
     }
 
-    // Read algorithm
-    let mut algorithm = [0u8; 1];
-    input_file.read_exact(&mut algorithm)?;
-    if algorithm[0] != ALGORITHM_AES256_GCM {
+    let nonce_length = header.algorithm.nonce_length();
+    if metadata_block.len() < nonce_length + TAG_LENGTH {
         return Err(CrateError::InvalidFormat(
-            "Unsupported encryption algorithm".to_string(),
+            "Encrypted metadata too short".to_string(),
         ));
     }
+    let (nonce_bytes, ciphertext) = metadata_block.split_at(nonce_length);
+
+    // Key slots have already checked the credentials; files without them
+    // decrypt nothing earlier, so a wrong password shows up here
+    let metadata_bytes = cipher
+        .decrypt(nonce_bytes, ciphertext, &header.authenticated_bytes())
+        .map_err(|_| {
+            if header.kdf == KdfDescriptor::KeySlots {
+                CrateError::AuthenticationFailed("metadata was tampered with".to_string())
+            } else {
+                CrateError::InvalidPassword
+            }
+        })?;
+
+    FileMetadata::from_bytes(&metadata_bytes)
+}
 
-    // Read salt
-    let mut salt = [0u8; SALT_LENGTH];
-    input_file.read_exact(&mut salt)?;
-
-    // Read nonce
-    let mut nonce_bytes = [0u8; NONCE_LENGTH];
-    input_file.read_exact(&mut nonce_bytes)?;
-
-    // Read metadata length
-    let mut metadata_len_bytes = [0u8; 4];
-    input_file.read_exact(&mut metadata_len_bytes)?;
-    let metadata_len = u32::from_le_bytes(metadata_len_bytes) as usize;
-
-    // Read metadata
-    let mut metadata_bytes = vec![0u8; metadata_len];
-    input_file.read_exact(&mut metadata_bytes)?;
-    let metadata = FileMetadata::from_bytes(&metadata_bytes)?;
-
-    // Read encrypted data
-    let mut ciphertext = Vec::new();
-    input_file.read_to_end(&mut ciphertext)?;
-
-    // Derive decryption key
-    let key = derive_key(password, &salt)?;
-
-    // Create cipher
-    let cipher = Aes256Gcm::new_from_slice(&key)
-        .map_err(|e| CrateError::Decryption(e.to_string()))?;
-
-    let nonce = Nonce::from_slice(&nonce_bytes);
-
-    // Decrypt the data
-    let decrypted_data = cipher
-        .decrypt(nonce, ciphertext.as_ref())
-        .map_err(|_| CrateError::InvalidPassword)?;
-
-    // Decompress if needed
-    let plaintext = if metadata.is_compressed {
-        compression::decompress(&decrypted_data, MAX_DECOMPRESSED_SIZE)?
-    } else {
-        decrypted_data
-    };
-
-    // Write decrypted file
-    let mut output_file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(output_path)?;
-
-    output_file.write_all(&plaintext)?;
-    output_file.flush()?;
-
-    Ok(metadata)
+/// Default options with cheap Argon2 parameters, for tests
+#[cfg(test)]
+pub(crate) fn test_options() -> EncryptOptions {
+    EncryptOptions {
+        argon2: crate::crypto::key_derivation::test_params(),
+        ..EncryptOptions::default()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::key_derivation::{derive_key, test_params};
+    use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
     use std::fs;
     use tempfile::TempDir;
 
@@ -238,14 +291,14 @@ mod tests {
         let password = "super_secret_password";
 
         // Encrypt without compression
-        encrypt_file(&input_path, &encrypted_path, password, false).unwrap();
+        encrypt_file_with_options(&input_path, &encrypted_path, &Credentials::password(password), &test_options()).unwrap();
         assert!(encrypted_path.exists());
 
         // Decrypt
         let metadata = decrypt_file(&encrypted_path, &decrypted_path, password).unwrap();
         assert!(decrypted_path.exists());
         assert_eq!(metadata.filename, "test.txt");
-        assert!(!metadata.is_compressed);
+        assert!(!metadata.is_compressed());
 
         // Verify content
         let decrypted_data = fs::read(&decrypted_path).unwrap();
@@ -266,11 +319,19 @@ mod tests {
         let password = "super_secret_password";
 
         // Encrypt with compression
-        encrypt_file(&input_path, &encrypted_path, password, true).unwrap();
+        encrypt_file_with_options(
+            &input_path,
+            &encrypted_path,
+            &Credentials::password(password),
+            &EncryptOptions {
+                compression: CompressionAlgorithm::Zstd.into(),
+                ..test_options()
+            },
+        ).unwrap();
 
         // Decrypt
         let metadata = decrypt_file(&encrypted_path, &decrypted_path, password).unwrap();
-        assert!(metadata.is_compressed);
+        assert!(metadata.is_compressed());
 
         // Verify content
         let decrypted_data = fs::read(&decrypted_path).unwrap();
@@ -286,10 +347,208 @@ mod tests {
 
         fs::write(&input_path, b"Secret data").unwrap();
 
-        encrypt_file(&input_path, &encrypted_path, "correct_password", false).unwrap();
+        encrypt_file_with_options(&input_path, &encrypted_path, &Credentials::password("correct_password"), &test_options()).unwrap();
 
         let result = decrypt_file(&encrypted_path, &decrypted_path, "wrong_password");
         assert!(result.is_err());
         assert!(matches!(result.unwrap_err(), CrateError::InvalidPassword));
     }
+
+    #[test]
+    fn test_decrypt_version_1_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let encrypted_path = temp_dir.path().join("legacy.txt.crat");
+        let decrypted_path = temp_dir.path().join("legacy_decrypted.txt");
+
+        // Build a file exactly as version 1 wrote it
+        let password = "legacy_password";
+        let salt = [3u8; SALT_LENGTH];
+        let nonce = [4u8; NONCE_LENGTH];
+        let key = derive_key(password, &salt).unwrap();
+        let ciphertext = Aes256Gcm::new_from_slice(&key[..])
+            .unwrap()
+            .encrypt(Nonce::from_slice(&nonce), b"old data".as_ref())
+            .unwrap();
+        let metadata = FileMetadata {
+            filename: "legacy.txt".to_string(),
+            original_size: 8,
+            modified_time: None,
+            compression: CompressionAlgorithm::None,
+            relative_path: None,
+            attributes: Default::default(),
+            timestamps: Default::default(),
+        }
+        .to_bytes();
+
+        let mut file = Vec::new();
+        file.extend_from_slice(MAGIC_BYTES);
+        file.push(VERSION_1);
+        file.push(ALGORITHM_AES256_GCM);
+        file.extend_from_slice(&salt);
+        file.extend_from_slice(&nonce);
+        file.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+        file.extend_from_slice(&metadata);
+        file.extend_from_slice(&ciphertext);
+        fs::write(&encrypted_path, file).unwrap();
+
+        let metadata = decrypt_file(&encrypted_path, &decrypted_path, password).unwrap();
+        assert_eq!(metadata.filename, "legacy.txt");
+        assert_eq!(fs::read(&decrypted_path).unwrap(), b"old data");
+    }
+
+    #[test]
+    fn test_tampered_metadata_is_rejected() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("test.txt");
+        let encrypted_path = temp_dir.path().join("test.txt.crat");
+        let decrypted_path = temp_dir.path().join("test_decrypted.txt");
+
+        fs::write(&input_path, b"Secret data").unwrap();
+        let options = EncryptOptions {
+            encrypt_metadata: false,
+            ..test_options()
+        };
+        let credentials = Credentials::password("password");
+        encrypt_file_with_options(&input_path, &encrypted_path, &credentials, &options).unwrap();
+
+        // Rename the stored filename from "test.txt" to "best.txt"
+        let mut encrypted = fs::read(&encrypted_path).unwrap();
+        let header = FileHeader::read_from(&mut encrypted.as_slice()).unwrap();
+        let filename_offset = header.to_bytes().len() + 2;
+        assert_eq!(encrypted[filename_offset], b't');
+        encrypted[filename_offset] = b'b';
+        fs::write(&encrypted_path, &encrypted).unwrap();
+
+        let result = decrypt_file(&encrypted_path, &decrypted_path, "password");
+        assert!(matches!(result, Err(CrateError::AuthenticationFailed(_))));
+    }
+
+    #[test]
+    fn test_tampered_encrypted_metadata_is_not_a_wrong_password() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("test.txt");
+        let encrypted_path = temp_dir.path().join("test.txt.crat");
+        let decrypted_path = temp_dir.path().join("test_decrypted.txt");
+
+        fs::write(&input_path, b"Secret data").unwrap();
+        encrypt_file_with_options(&input_path, &encrypted_path, &Credentials::password("password"), &test_options()).unwrap();
+
+        // Flip a bit in the sealed metadata, just past its nonce
+        let mut encrypted = fs::read(&encrypted_path).unwrap();
+        let header = FileHeader::read_from(&mut encrypted.as_slice()).unwrap();
+        let metadata_offset = header.to_bytes().len() + header.algorithm.nonce_length();
+        encrypted[metadata_offset] ^= 1;
+        fs::write(&encrypted_path, &encrypted).unwrap();
+
+        let result = decrypt_file(&encrypted_path, &decrypted_path, "password");
+        assert!(matches!(result, Err(CrateError::AuthenticationFailed(_))));
+    }
+
+    #[test]
+    fn test_encrypted_metadata_hides_filename() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("very_private_name.txt");
+        let encrypted_path = temp_dir.path().join("out.crat");
+        let decrypted_path = temp_dir.path().join("out.txt");
+
+        fs::write(&input_path, b"Secret data").unwrap();
+        encrypt_file_with_options(&input_path, &encrypted_path, &Credentials::password("password"), &test_options()).unwrap();
+
+        let encrypted = fs::read(&encrypted_path).unwrap();
+        assert!(!encrypted
+            .windows(b"very_private_name".len())
+            .any(|w| w == b"very_private_name"));
+
+        let metadata = decrypt_file(&encrypted_path, &decrypted_path, "password").unwrap();
+        assert_eq!(metadata.filename, "very_private_name.txt");
+        assert_eq!(metadata.original_size, 11);
+    }
+
+    #[test]
+    fn test_argon2_parameters_read_from_header() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("test.txt");
+        let encrypted_path = temp_dir.path().join("test.txt.crat");
+        let decrypted_path = temp_dir.path().join("test_decrypted.txt");
+
+        fs::write(&input_path, b"Cheap to derive").unwrap();
+        let params = test_params();
+        let options = EncryptOptions {
+            argon2: params,
+            ..test_options()
+        };
+        let credentials = Credentials::password("password");
+        encrypt_file_with_options(&input_path, &encrypted_path, &credentials, &options).unwrap();
+
+        let header = FileHeader::read_from(&mut File::open(&encrypted_path).unwrap()).unwrap();
+        let slots = crate::crypto::keyslot::read_key_slots(&header).unwrap();
+        let KdfDescriptor::Subkey { base, .. } = &slots[0].kdf else {
+            panic!("unexpected KDF: {:?}", slots[0].kdf);
+        };
+        assert!(matches!(**base, KdfDescriptor::Argon2id { params: p, .. } if p == params));
+
+        decrypt_file(&encrypted_path, &decrypted_path, "password").unwrap();
+        assert_eq!(fs::read(&decrypted_path).unwrap(), b"Cheap to derive");
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_xchacha20() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("test.txt");
+        let encrypted_path = temp_dir.path().join("test.txt.crat");
+        let decrypted_path = temp_dir.path().join("test_decrypted.txt");
+
+        fs::write(&input_path, b"Hello over ChaCha").unwrap();
+        let options = EncryptOptions {
+            algorithm: CipherAlgorithm::XChaCha20Poly1305,
+            ..test_options()
+        };
+        let credentials = Credentials::password("password");
+        encrypt_file_with_options(&input_path, &encrypted_path, &credentials, &options).unwrap();
+
+        let header = FileHeader::read_from(&mut File::open(&encrypted_path).unwrap()).unwrap();
+        assert_eq!(header.algorithm, CipherAlgorithm::XChaCha20Poly1305);
+        assert_eq!(header.nonce.len(), XNONCE_LENGTH);
+
+        let metadata = decrypt_file(&encrypted_path, &decrypted_path, "password").unwrap();
+        assert_eq!(metadata.filename, "test.txt");
+        assert_eq!(fs::read(&decrypted_path).unwrap(), b"Hello over ChaCha");
+
+        let result = decrypt_file(&encrypted_path, &decrypted_path, "wrong");
+        assert!(matches!(result, Err(CrateError::InvalidPassword)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_preserve_attributes() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("secrets.env");
+        let link_path = temp_dir.path().join("current.env");
+        fs::write(&input_path, b"TOKEN=abc").unwrap();
+        fs::set_permissions(&input_path, fs::Permissions::from_mode(0o600)).unwrap();
+        symlink("secrets.env", &link_path).unwrap();
+
+        let options = EncryptOptions {
+            preserve_attributes: true,
+            ..test_options()
+        };
+        let credentials = Credentials::password("password");
+        for (path, expected) in [(&input_path, &b"TOKEN=abc"[..]), (&link_path, &b""[..])] {
+            let encrypted_path = path.with_extension("crat");
+            let decrypted_path = temp_dir.path().join("decrypted");
+            encrypt_file_with_options(path, &encrypted_path, &credentials, &options).unwrap();
+
+            let metadata = decrypt_file(&encrypted_path, &decrypted_path, "password").unwrap();
+            assert_eq!(fs::read(&decrypted_path).unwrap(), expected);
+            if path == &link_path {
+                assert_eq!(metadata.attributes.symlink_target.as_deref(), Some("secrets.env"));
+                assert_eq!(metadata.original_size, 0);
+            } else {
+                assert_eq!(metadata.attributes.mode, Some(0o600));
+                assert!(metadata.attributes.owner.is_some());
+            }
+        }
+    }
 }
@@ -1,8 +1,89 @@
 //! Key derivation using Argon2id
+//!
+//! Each set of credentials has its own construction, recorded in the KDF
+//! descriptor of its key slot:
+//!
+//! - password: Argon2id(password, salt)
+//! - key file: HKDF-SHA256(ikm = key file hash, salt, info = "cryptocrate keyfile v1")
+//! - both: Argon2id(password, salt) with the key file hash as the Argon2 secret
+//! - batch: HKDF-SHA256(ikm = one of the Argon2id keys above, per-file salt,
+//!   info = "cryptocrate subkey v1"), so files sharing a password pay for
+//!   Argon2id once (see `Credentials::base_key`)
+//!
+//! Files written before these constructions fed a hex string built from the
+//! key file into plain Argon2id; `derive_key_from_credentials` still opens them.
 
 use argon2::{Argon2, ParamsBuilder, Version};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::crypto::keyslot::Credentials;
 use crate::error::{CrateError, Result};
-use crate::format::KEY_LENGTH;
+use crate::format::{KdfDescriptor, KEY_LENGTH};
+use crate::keyfile::combine_password_and_keyfile;
+use crate::secret::{SecretKey, Zeroizing};
+
+/// HKDF info label for keys derived from a key file alone
+const KEYFILE_INFO: &[u8] = b"cryptocrate keyfile v1";
+
+/// HKDF info label for per-file subkeys of a shared Argon2id key
+const SUBKEY_INFO: &[u8] = b"cryptocrate subkey v1";
+
+/// Upper bounds accepted when reading parameters from a file, so a crafted
+/// header cannot make decryption allocate unbounded memory or spin forever
+const MAX_MEMORY_KB: u32 = 4 * 1024 * 1024; // 4 GB
+const MAX_TIME_COST: u32 = 100;
+const MAX_PARALLELISM: u32 = 64;
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Params {
+    /// Memory cost in KB
+    pub memory_kb: u32,
+    /// Number of iterations
+    pub time_cost: u32,
+    /// Number of parallel lanes
+    pub parallelism: u32,
+}
+
+impl Argon2Params {
+    /// Parameters used by every version 1 file (64 MB, 3 iterations, 4 lanes)
+    pub const V1: Argon2Params = Argon2Params {
+        memory_kb: 65536,
+        time_cost: 3,
+        parallelism: 4,
+    };
+
+    /// Check the parameters are usable and within sane bounds
+    pub fn validate(&self) -> Result<()> {
+        if self.memory_kb > MAX_MEMORY_KB
+            || self.time_cost > MAX_TIME_COST
+            || self.parallelism > MAX_PARALLELISM
+        {
+            return Err(CrateError::KeyDerivation(format!(
+                "Argon2 parameters out of range (max {} KB, {} iterations, {} lanes)",
+                MAX_MEMORY_KB, MAX_TIME_COST, MAX_PARALLELISM
+            )));
+        }
+        self.build().map(|_| ())
+    }
+
+    fn build(&self) -> Result<argon2::Params> {
+        ParamsBuilder::new()
+            .m_cost(self.memory_kb)
+            .t_cost(self.time_cost)
+            .p_cost(self.parallelism)
+            .output_len(KEY_LENGTH)
+            .build()
+            .map_err(|e| CrateError::KeyDerivation(e.to_string()))
+    }
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self::V1
+    }
+}
 
 /// Derive a cryptographic key from a password using Argon2id
 ///
@@ -11,38 +92,146 @@ use crate::format::KEY_LENGTH;
 /// - `salt`: A unique salt for this encryption operation
 ///
 /// # Security
-/// Uses Argon2id with recommended parameters:
+/// Uses Argon2id with the default parameters:
 /// - Memory cost: 64 MB
 /// - Time cost: 3 iterations
 /// - Parallelism: 4 threads
-pub fn derive_key(password: &str, salt: &[u8]) -> Result<[u8; KEY_LENGTH]> {
-    // Configure Argon2id parameters
-    let mut params_builder = ParamsBuilder::new();
-    params_builder
-        .m_cost(65536) // 64 MB
-        .t_cost(3) // 3 iterations
-        .p_cost(4) // 4 parallel threads
-        .output_len(KEY_LENGTH)
-        .map_err(|e| CrateError::KeyDerivation(e.to_string()))?;
+pub fn derive_key(password: &str, salt: &[u8]) -> Result<SecretKey> {
+    derive_key_with_params(password, salt, &Argon2Params::default())
+}
 
-    let params = params_builder
-        .build()
-        .map_err(|e| CrateError::KeyDerivation(e.to_string()))?;
+/// Derive a cryptographic key from a password using Argon2id with explicit parameters
+pub fn derive_key_with_params(
+    password: &str,
+    salt: &[u8],
+    params: &Argon2Params,
+) -> Result<SecretKey> {
+    params.validate()?;
 
     let argon2 = Argon2::new(
         argon2::Algorithm::Argon2id,
         Version::V0x13,
-        params,
+        params.build()?,
     );
 
-    let mut key = [0u8; KEY_LENGTH];
+    let mut key = SecretKey::zeroed();
     argon2
-        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .hash_password_into(password.as_bytes(), salt, &mut *key)
+        .map_err(|e| CrateError::KeyDerivation(e.to_string()))?;
+
+    Ok(key)
+}
+
+/// Derive a key using Argon2id with the key file hash as the Argon2 secret
+///
+/// The key file acts as a pepper: without it the password cannot even be
+/// tested, and the two inputs never share a hash as in a plain concatenation.
+pub fn derive_key_with_keyfile(
+    password: &str,
+    keyfile_hash: &[u8; 32],
+    salt: &[u8],
+    params: &Argon2Params,
+) -> Result<SecretKey> {
+    params.validate()?;
+
+    let argon2 = Argon2::new_with_secret(
+        keyfile_hash,
+        argon2::Algorithm::Argon2id,
+        Version::V0x13,
+        params.build()?,
+    )
+    .map_err(|e| CrateError::KeyDerivation(e.to_string()))?;
+
+    let mut key = SecretKey::zeroed();
+    argon2
+        .hash_password_into(password.as_bytes(), salt, &mut *key)
+        .map_err(|e| CrateError::KeyDerivation(e.to_string()))?;
+
+    Ok(key)
+}
+
+/// Derive a key from a key file alone using HKDF-SHA256
+///
+/// A generated key file already holds 4 KB of randomness, so it needs no
+/// memory-hard stretching.
+pub fn derive_key_from_keyfile(keyfile_hash: &[u8; 32], salt: &[u8]) -> Result<SecretKey> {
+    let mut key = SecretKey::zeroed();
+    Hkdf::<Sha256>::new(Some(salt), keyfile_hash)
+        .expand(KEYFILE_INFO, &mut *key)
         .map_err(|e| CrateError::KeyDerivation(e.to_string()))?;
+    Ok(key)
+}
 
+/// Derive a per-file key from a key shared by a batch of files
+pub fn derive_subkey(base_key: &[u8; KEY_LENGTH], salt: &[u8]) -> Result<SecretKey> {
+    let mut key = SecretKey::zeroed();
+    Hkdf::<Sha256>::new(Some(salt), base_key)
+        .expand(SUBKEY_INFO, &mut *key)
+        .map_err(|e| CrateError::KeyDerivation(e.to_string()))?;
     Ok(key)
 }
 
+/// Derive a key as described by a file header's KDF descriptor
+pub fn derive_key_from_descriptor(password: &str, kdf: &KdfDescriptor) -> Result<SecretKey> {
+    derive_key_from_credentials(&Credentials::password(password), kdf)
+}
+
+/// Derive a key from a password and/or key file as described by `kdf`
+pub fn derive_key_from_credentials(
+    credentials: &Credentials,
+    kdf: &KdfDescriptor,
+) -> Result<SecretKey> {
+    let password = credentials.password.as_ref().map(|p| p.as_str());
+    let keyfile = credentials.keyfile.as_deref();
+
+    match (kdf, password, keyfile) {
+        (KdfDescriptor::Subkey { base, salt }, _, _) => {
+            let base_key = credentials.base_key(base)?;
+            derive_subkey(&base_key, salt)
+        }
+        (KdfDescriptor::Argon2id { params, salt }, Some(password), None) => {
+            derive_key_with_params(password, salt, params)
+        }
+        // Files written before the keyed constructions existed
+        (KdfDescriptor::Argon2id { params, salt }, None, Some(keyfile)) => {
+            derive_key_with_params(&Zeroizing::new(hex::encode(keyfile)), salt, params)
+        }
+        (KdfDescriptor::Argon2id { params, salt }, Some(password), Some(keyfile)) => {
+            let combined = combine_password_and_keyfile(password, keyfile);
+            derive_key_with_params(&Zeroizing::new(hex::encode(combined)), salt, params)
+        }
+        (KdfDescriptor::Argon2idKeyfile { params, salt }, Some(password), Some(keyfile)) => {
+            derive_key_with_keyfile(password, keyfile, salt, params)
+        }
+        (KdfDescriptor::HkdfSha256 { salt }, None, Some(keyfile)) => {
+            derive_key_from_keyfile(keyfile, salt)
+        }
+        (KdfDescriptor::KeySlots, _, _) => Err(CrateError::KeyDerivation(
+            "the file key is stored in key slots".to_string(),
+        )),
+        (KdfDescriptor::X25519 { .. }, _, _) => Err(CrateError::KeyDerivation(
+            "an identity is needed, not a password".to_string(),
+        )),
+        (_, None, None) => Err(CrateError::KeyDerivation(
+            "no password or key file given".to_string(),
+        )),
+        (kdf, _, _) => Err(CrateError::KeyDerivation(format!(
+            "{} needs a different combination of password and key file",
+            kdf.name()
+        ))),
+    }
+}
+
+/// Cheap parameters (8 MB, 1 iteration, 1 lane) that keep tests fast
+#[cfg(test)]
+pub(crate) fn test_params() -> Argon2Params {
+    Argon2Params {
+        memory_kb: 8192,
+        time_cost: 1,
+        parallelism: 1,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -52,16 +241,58 @@ mod tests {
         let password = "test_password";
         let salt = [0u8; 32];
 
-        let key = derive_key(password, &salt).unwrap();
+        let key = derive_key_with_params(password, &salt, &test_params()).unwrap();
         assert_eq!(key.len(), KEY_LENGTH);
 
         // Same password and salt should produce same key
-        let key2 = derive_key(password, &salt).unwrap();
-        assert_eq!(key, key2);
+        let key2 = derive_key_with_params(password, &salt, &test_params()).unwrap();
+        assert_eq!(*key, *key2);
 
         // Different salt should produce different key
         let salt2 = [1u8; 32];
-        let key3 = derive_key(password, &salt2).unwrap();
-        assert_ne!(key, key3);
+        let key3 = derive_key_with_params(password, &salt2, &test_params()).unwrap();
+        assert_ne!(*key, *key3);
+    }
+
+    #[test]
+    fn test_parameters_change_key() {
+        let salt = [0u8; 32];
+        let cheap = test_params();
+
+        let key1 = derive_key_with_params("test_password", &salt, &cheap).unwrap();
+        let key2 = derive_key("test_password", &salt).unwrap();
+        assert_ne!(*key1, *key2);
+    }
+
+    #[test]
+    fn test_keyfile_constructions() {
+        let salt = [5u8; 32];
+        let keyfile = [9u8; 32];
+        let cheap = test_params();
+
+        let keyed = derive_key_with_keyfile("pw", &keyfile, &salt, &cheap).unwrap();
+        assert_ne!(*keyed, *derive_key_with_params("pw", &salt, &cheap).unwrap());
+        assert_ne!(*keyed, *derive_key_with_keyfile("pw", &[8u8; 32], &salt, &cheap).unwrap());
+
+        let hkdf = derive_key_from_keyfile(&keyfile, &salt).unwrap();
+        assert_ne!(*hkdf, *derive_subkey(&keyfile, &salt).unwrap());
+        assert_eq!(*hkdf, *derive_key_from_keyfile(&keyfile, &salt).unwrap());
+        assert_ne!(*hkdf, *derive_key_from_keyfile(&keyfile, &[6u8; 32]).unwrap());
+
+        // Descriptors only accept the credentials they were made for
+        let kdf = KdfDescriptor::HkdfSha256 { salt };
+        let keyfile_only = Credentials::new(None, Some(keyfile.into()));
+        assert_eq!(*derive_key_from_credentials(&keyfile_only, &kdf).unwrap(), *hkdf);
+        assert!(derive_key_from_credentials(&Credentials::password("pw"), &kdf).is_err());
+    }
+
+    #[test]
+    fn test_parameter_bounds() {
+        let huge = Argon2Params {
+            memory_kb: u32::MAX,
+            ..Argon2Params::default()
+        };
+        assert!(huge.validate().is_err());
+        assert!(Argon2Params::default().validate().is_ok());
     }
 }
@@ -0,0 +1,903 @@
+//! Key slots (LUKS-style)
+//!
+//! The payload of a crate is encrypted with a random file key. Each key slot
+//! holds a copy of that key, wrapped under a key derived from one set of
+//! credentials: a password, a key file, both, or a recipient's public key
+//! (see `identity`). Slots can be added and removed without re-encrypting
+//! the payload, because the slot record is not part of the data the payload
+//! authenticates.
+//!
+//! Password slots derive their key-encryption key as an HKDF subkey of an
+//! Argon2id key. `Credentials` remembers the last few Argon2id keys it derived,
+//! so encrypting or decrypting a batch of files runs Argon2id once.
+
+use rand::RngCore;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tempfile::NamedTempFile;
+
+use crate::crypto::cipher::{Cipher, CipherAlgorithm};
+use crate::crypto::key_derivation::{derive_key_from_credentials, Argon2Params};
+use crate::error::{CrateError, Result};
+use crate::format::*;
+use crate::identity::{Identity, Recipient};
+use crate::secret::{SecretKey, SecretString};
+
+/// Maximum number of key slots in one file
+pub const MAX_KEY_SLOTS: usize = 16;
+
+/// Argon2id keys one `Credentials` remembers; older ones are dropped
+const MAX_BASE_KEYS: usize = 8;
+
+const SLOT_PASSWORD: u8 = 1;
+const SLOT_KEYFILE: u8 = 2;
+const SLOT_PASSWORD_KEYFILE: u8 = 3;
+const SLOT_RECIPIENT: u8 = 4;
+
+/// What a key slot is unlocked with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotKind {
+    Password,
+    Keyfile,
+    PasswordKeyfile,
+    /// X25519 public key, opened with the matching identity
+    Recipient,
+}
+
+impl SlotKind {
+    /// Identifier stored in the slot record
+    pub fn id(&self) -> u8 {
+        match self {
+            SlotKind::Password => SLOT_PASSWORD,
+            SlotKind::Keyfile => SLOT_KEYFILE,
+            SlotKind::PasswordKeyfile => SLOT_PASSWORD_KEYFILE,
+            SlotKind::Recipient => SLOT_RECIPIENT,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            SLOT_PASSWORD => Ok(SlotKind::Password),
+            SLOT_KEYFILE => Ok(SlotKind::Keyfile),
+            SLOT_PASSWORD_KEYFILE => Ok(SlotKind::PasswordKeyfile),
+            SLOT_RECIPIENT => Ok(SlotKind::Recipient),
+            _ => Err(CrateError::InvalidFormat(format!(
+                "Unsupported key slot type: {}",
+                id
+            ))),
+        }
+    }
+
+    /// Human-readable name
+    pub fn name(&self) -> &'static str {
+        match self {
+            SlotKind::Password => "password",
+            SlotKind::Keyfile => "key file",
+            SlotKind::PasswordKeyfile => "password + key file",
+            SlotKind::Recipient => "recipient",
+        }
+    }
+}
+
+impl fmt::Display for SlotKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Secrets that unlock a key slot
+#[derive(Clone, Default)]
+pub struct Credentials {
+    pub password: Option<SecretString>,
+    /// SHA-256 of the key file contents (see `keyfile::read_keyfile`)
+    pub keyfile: Option<SecretKey>,
+    /// Identities tried against recipient slots
+    pub identities: Vec<Identity>,
+    base_keys: BaseKeys,
+}
+
+/// Argon2id keys derived so far, shared by every clone of a `Credentials`
+///
+/// Holds the `MAX_BASE_KEYS` most recent descriptors, so decrypting many
+/// unrelated files does not grow it without end.
+#[derive(Clone, Default)]
+struct BaseKeys(Arc<Mutex<VecDeque<Arc<BaseKey>>>>);
+
+struct BaseKey {
+    kdf: KdfDescriptor,
+    // The public fields of `Credentials` can change after a key is cached
+    password: Option<SecretString>,
+    keyfile: Option<SecretKey>,
+    /// Filled in by the first thread that needs it
+    key: Mutex<Option<SecretKey>>,
+}
+
+impl BaseKey {
+    fn derived_from(&self, credentials: &Credentials) -> bool {
+        self.password.as_deref() == credentials.password.as_deref()
+            && self.keyfile.as_deref() == credentials.keyfile.as_deref()
+    }
+}
+
+impl Credentials {
+    /// Credentials from an optional password and key file hash
+    ///
+    /// An empty password counts as no password.
+    pub fn new(password: Option<SecretString>, keyfile: Option<SecretKey>) -> Self {
+        Self {
+            password: password.filter(|p| !p.is_empty()),
+            keyfile,
+            ..Self::default()
+        }
+    }
+
+    /// Password-only credentials
+    pub fn password(password: &str) -> Self {
+        Self::new(Some(password.into()), None)
+    }
+
+    /// Credentials holding only an identity
+    pub fn identity(identity: Identity) -> Self {
+        Self {
+            identities: vec![identity],
+            ..Self::default()
+        }
+    }
+
+    /// The kind of key slot the password and/or key file open
+    pub fn kind(&self) -> Result<SlotKind> {
+        self.secret_kind().ok_or_else(|| {
+            CrateError::KeyDerivation("no password or key file given".to_string())
+        })
+    }
+
+    fn secret_kind(&self) -> Option<SlotKind> {
+        match (&self.password, &self.keyfile) {
+            (Some(_), None) => Some(SlotKind::Password),
+            (None, Some(_)) => Some(SlotKind::Keyfile),
+            (Some(_), Some(_)) => Some(SlotKind::PasswordKeyfile),
+            (None, None) => None,
+        }
+    }
+
+    /// The Argon2id key `kdf` derives from these credentials
+    ///
+    /// Derived on first use, then cached for this value and its clones.
+    pub(crate) fn base_key(&self, kdf: &KdfDescriptor) -> Result<SecretKey> {
+        if !matches!(
+            kdf,
+            KdfDescriptor::Argon2id { .. } | KdfDescriptor::Argon2idKeyfile { .. }
+        ) {
+            return Err(CrateError::KeyDerivation(
+                "subkeys must be derived from an Argon2id key".to_string(),
+            ));
+        }
+
+        // The list is only locked to find or add the entry, so jobs that
+        // need other keys are not held up by this derivation
+        let entry = {
+            let mut keys = self.base_keys.0.lock().unwrap();
+            match keys.iter().find(|k| &k.kdf == kdf && k.derived_from(self)) {
+                Some(entry) => Arc::clone(entry),
+                None => {
+                    let entry = Arc::new(BaseKey {
+                        kdf: kdf.clone(),
+                        password: self.password.clone(),
+                        keyfile: self.keyfile.clone(),
+                        key: Mutex::new(None),
+                    });
+                    if keys.len() == MAX_BASE_KEYS {
+                        keys.pop_front();
+                    }
+                    keys.push_back(Arc::clone(&entry));
+                    entry
+                }
+            }
+        };
+
+        // Held during the derivation, so parallel jobs needing the same key
+        // wait for it rather than repeat it
+        let mut key = entry.key.lock().unwrap();
+        if let Some(key) = key.as_ref() {
+            return Ok(key.clone());
+        }
+        let derived = derive_key_from_credentials(self, kdf)?;
+        *key = Some(derived.clone());
+        Ok(derived)
+    }
+
+    /// The Argon2id descriptor new `kind` slots with `params` derive from
+    ///
+    /// Reuses one already derived by these credentials (so a batch shares
+    /// it), or picks a fresh salt and derives it now.
+    fn batch_base(&self, kind: SlotKind, params: &Argon2Params) -> Result<KdfDescriptor> {
+        let reusable = |kdf: &KdfDescriptor| match (kind, kdf) {
+            (SlotKind::Password, KdfDescriptor::Argon2id { params: p, .. })
+            | (SlotKind::PasswordKeyfile, KdfDescriptor::Argon2idKeyfile { params: p, .. }) => {
+                p == params
+            }
+            _ => false,
+        };
+        let cached = self.base_keys.0.lock().unwrap().iter().find_map(|k| {
+            (reusable(&k.kdf) && k.derived_from(self)).then(|| k.kdf.clone())
+        });
+        if let Some(kdf) = cached {
+            return Ok(kdf);
+        }
+
+        let mut salt = [0u8; SALT_LENGTH];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let params = *params;
+        let kdf = match kind {
+            SlotKind::Password => KdfDescriptor::Argon2id { params, salt },
+            SlotKind::PasswordKeyfile => KdfDescriptor::Argon2idKeyfile { params, salt },
+            _ => unreachable!("only password slots use Argon2id"),
+        };
+        self.base_key(&kdf)?;
+        Ok(kdf)
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("keyfile", &self.keyfile.as_ref().map(|_| "<redacted>"))
+            .field("identities", &self.identities)
+            .finish()
+    }
+}
+
+/// One wrapped copy of the file key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySlot {
+    pub kind: SlotKind,
+    /// How the key-encryption key is derived from the credentials
+    pub kdf: KdfDescriptor,
+    pub nonce: Vec<u8>,
+    /// The file key, sealed under the key-encryption key
+    pub wrapped_key: Vec<u8>,
+}
+
+impl KeySlot {
+    /// Wrap `file_key` under a fresh key derived from `credentials`
+    pub fn seal(
+        credentials: &Credentials,
+        file_key: &[u8; KEY_LENGTH],
+        algorithm: CipherAlgorithm,
+        params: &Argon2Params,
+    ) -> Result<Self> {
+        let kind = credentials.kind()?;
+
+        let mut salt = [0u8; SALT_LENGTH];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let kdf = match kind {
+            SlotKind::Password | SlotKind::PasswordKeyfile => KdfDescriptor::Subkey {
+                base: Box::new(credentials.batch_base(kind, params)?),
+                salt,
+            },
+            SlotKind::Keyfile => KdfDescriptor::HkdfSha256 { salt },
+            SlotKind::Recipient => unreachable!("credentials never have the recipient kind"),
+        };
+        let kek = derive_key_from_credentials(credentials, &kdf)?;
+
+        Self::wrap(kind, kdf, &kek, file_key, algorithm)
+    }
+
+    /// Wrap `file_key` so that only `recipient`'s identity can unwrap it
+    pub fn seal_for_recipient(
+        recipient: &Recipient,
+        file_key: &[u8; KEY_LENGTH],
+        algorithm: CipherAlgorithm,
+    ) -> Result<Self> {
+        let (ephemeral, kek) = recipient.derive_kek()?;
+        let kdf = KdfDescriptor::X25519 { ephemeral };
+        Self::wrap(SlotKind::Recipient, kdf, &kek, file_key, algorithm)
+    }
+
+    fn wrap(
+        kind: SlotKind,
+        kdf: KdfDescriptor,
+        kek: &[u8; KEY_LENGTH],
+        file_key: &[u8; KEY_LENGTH],
+        algorithm: CipherAlgorithm,
+    ) -> Result<Self> {
+        let nonce = algorithm.random_nonce();
+        let wrapped_key = Cipher::new(algorithm, kek)?.encrypt(&nonce, file_key, &[kind.id()])?;
+
+        Ok(Self {
+            kind,
+            kdf,
+            nonce,
+            wrapped_key,
+        })
+    }
+
+    /// Unwrap the file key, or `None` if the credentials do not open this slot
+    pub fn open(
+        &self,
+        credentials: &Credentials,
+        algorithm: CipherAlgorithm,
+    ) -> Result<Option<SecretKey>> {
+        if let KdfDescriptor::X25519 { ephemeral } = &self.kdf {
+            for identity in &credentials.identities {
+                // A malformed ephemeral key just means this slot is not ours
+                let Ok(kek) = identity.derive_kek(ephemeral) else {
+                    continue;
+                };
+                if let Some(file_key) = self.unwrap(&kek, algorithm)? {
+                    return Ok(Some(file_key));
+                }
+            }
+            return Ok(None);
+        }
+
+        // Skip the expensive KDF for slots of a different kind
+        if credentials.secret_kind() != Some(self.kind) {
+            return Ok(None);
+        }
+
+        let kek = derive_key_from_credentials(credentials, &self.kdf)?;
+        self.unwrap(&kek, algorithm)
+    }
+
+    fn unwrap(
+        &self,
+        kek: &[u8; KEY_LENGTH],
+        algorithm: CipherAlgorithm,
+    ) -> Result<Option<SecretKey>> {
+        let cipher = Cipher::new(algorithm, kek)?;
+        match cipher.decrypt(&self.nonce, &self.wrapped_key, &[self.kind.id()]) {
+            Ok(key) if key.len() == KEY_LENGTH => {
+                let mut file_key = SecretKey::zeroed();
+                file_key.copy_from_slice(&key);
+                Ok(Some(file_key))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.push(self.kind.id());
+        self.kdf.encode(bytes);
+        bytes.push(self.nonce.len() as u8);
+        bytes.extend_from_slice(&self.nonce);
+        bytes.push(self.wrapped_key.len() as u8);
+        bytes.extend_from_slice(&self.wrapped_key);
+    }
+
+    fn decode(reader: &mut &[u8]) -> Result<Self> {
+        let kind = SlotKind::from_id(read_u8(reader)?)?;
+        let kdf = KdfDescriptor::decode(reader)?;
+        let nonce_len = read_u8(reader)? as usize;
+        let nonce = read_vec(reader, nonce_len)?;
+        let wrapped_len = read_u8(reader)? as usize;
+        let wrapped_key = read_vec(reader, wrapped_len)?;
+
+        Ok(Self {
+            kind,
+            kdf,
+            nonce,
+            wrapped_key,
+        })
+    }
+}
+
+/// Key slots stored in `header` (empty if it has none)
+pub fn read_key_slots(header: &FileHeader) -> Result<Vec<KeySlot>> {
+    let Some(record) = header.extension(EXT_KEY_SLOTS) else {
+        return Ok(Vec::new());
+    };
+
+    let mut reader = record.data.as_slice();
+    let count = read_u8(&mut reader)? as usize;
+    if count > MAX_KEY_SLOTS {
+        return Err(CrateError::InvalidFormat(format!(
+            "Too many key slots: {}",
+            count
+        )));
+    }
+    (0..count).map(|_| KeySlot::decode(&mut reader)).collect()
+}
+
+/// Store `slots` in `header`, replacing the existing ones
+pub fn write_key_slots(header: &mut FileHeader, slots: &[KeySlot]) -> Result<()> {
+    if slots.is_empty() {
+        return Err(CrateError::InvalidFormat(
+            "A file needs at least one key slot".to_string(),
+        ));
+    }
+    if slots.len() > MAX_KEY_SLOTS {
+        return Err(CrateError::InvalidFormat(format!(
+            "A file can have at most {} key slots",
+            MAX_KEY_SLOTS
+        )));
+    }
+
+    let mut data = vec![slots.len() as u8];
+    for slot in slots {
+        slot.encode(&mut data);
+    }
+
+    header.set_extension(EXT_KEY_SLOTS, data);
+    Ok(())
+}
+
+/// Generate the random file key for a new file and store it in key slots
+///
+/// One slot is created for the password and/or key file in `credentials`
+/// (if any), plus one per recipient.
+pub fn create_file_key(
+    header: &mut FileHeader,
+    credentials: &Credentials,
+    recipients: &[Recipient],
+    params: &Argon2Params,
+) -> Result<SecretKey> {
+    if credentials.secret_kind().is_none() && recipients.is_empty() {
+        return Err(CrateError::KeyDerivation(
+            "no password, key file or recipient given".to_string(),
+        ));
+    }
+
+    let mut file_key = SecretKey::zeroed();
+    rand::thread_rng().fill_bytes(&mut *file_key);
+
+    let mut slots = Vec::new();
+    if credentials.secret_kind().is_some() {
+        slots.push(KeySlot::seal(credentials, &file_key, header.algorithm, params)?);
+    }
+    for recipient in recipients {
+        slots.push(KeySlot::seal_for_recipient(recipient, &file_key, header.algorithm)?);
+    }
+    header.kdf = KdfDescriptor::KeySlots;
+    write_key_slots(header, &slots)?;
+
+    Ok(file_key)
+}
+
+/// Recover the key the payload was encrypted with
+pub fn unlock(header: &FileHeader, credentials: &Credentials) -> Result<SecretKey> {
+    match &header.kdf {
+        KdfDescriptor::KeySlots => unlock_slot(header, credentials).map(|(_, key)| key),
+        // Version 1 and early version 2 files derive the key directly
+        kdf => derive_key_from_credentials(credentials, kdf),
+    }
+}
+
+/// Find the slot `credentials` open, returning its index and the file key
+fn unlock_slot(header: &FileHeader, credentials: &Credentials) -> Result<(usize, SecretKey)> {
+    for (index, slot) in read_key_slots(header)?.iter().enumerate() {
+        if let Some(key) = slot.open(credentials, header.algorithm)? {
+            return Ok((index, key));
+        }
+    }
+    Err(CrateError::InvalidPassword)
+}
+
+fn require_key_slots(header: &FileHeader) -> Result<()> {
+    if header.kdf != KdfDescriptor::KeySlots {
+        return Err(CrateError::InvalidFormat(
+            "This file has no key slots (it predates them); decrypt and re-encrypt it first"
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// List the key slots of an encrypted file
+pub fn list_key_slots<P: AsRef<Path>>(path: P) -> Result<Vec<KeySlot>> {
+    let path = path.as_ref();
+    let mut file = File::open(path)
+        .map_err(|e| CrateError::FileNotFound(format!("{}: {}", path.display(), e)))?;
+    let header = FileHeader::read_from(&mut file)?;
+    require_key_slots(&header)?;
+    read_key_slots(&header)
+}
+
+/// Add a key slot for `new` credentials, authorised by `existing` ones
+///
+/// Returns the index of the new slot.
+pub fn add_key_slot<P: AsRef<Path>>(
+    path: P,
+    existing: &Credentials,
+    new: &Credentials,
+    params: &Argon2Params,
+) -> Result<usize> {
+    let mut index = 0;
+    update_key_slots(path.as_ref(), existing, |slots, _, file_key, algorithm| {
+        slots.push(KeySlot::seal(new, file_key, algorithm, params)?);
+        index = slots.len() - 1;
+        Ok(())
+    })?;
+    Ok(index)
+}
+
+/// Add a key slot for `recipient`, authorised by `existing` credentials
+///
+/// Returns the index of the new slot.
+pub fn add_recipient_slot<P: AsRef<Path>>(
+    path: P,
+    existing: &Credentials,
+    recipient: &Recipient,
+) -> Result<usize> {
+    let mut index = 0;
+    update_key_slots(path.as_ref(), existing, |slots, _, file_key, algorithm| {
+        slots.push(KeySlot::seal_for_recipient(recipient, file_key, algorithm)?);
+        index = slots.len() - 1;
+        Ok(())
+    })?;
+    Ok(index)
+}
+
+/// Remove the key slot at `index`, authorised by `credentials`
+///
+/// The last remaining slot cannot be removed.
+pub fn remove_key_slot<P: AsRef<Path>>(
+    path: P,
+    credentials: &Credentials,
+    index: usize,
+) -> Result<()> {
+    update_key_slots(path.as_ref(), credentials, |slots, _, _, _| {
+        if index >= slots.len() {
+            return Err(CrateError::InvalidFormat(format!(
+                "No key slot {} (the file has {})",
+                index,
+                slots.len()
+            )));
+        }
+        slots.remove(index);
+        Ok(())
+    })
+}
+
+/// Unlock a file, let `update` edit its key slots, then rewrite the header
+///
+/// `update` receives the slots, the index of the slot that was unlocked,
+/// the file key and the file's algorithm. The payload is copied unchanged
+/// into a temporary file which then replaces the original, so the file is
+/// never left half-written.
+pub(crate) fn update_key_slots<F>(path: &Path, credentials: &Credentials, update: F) -> Result<()>
+where
+    F: FnOnce(&mut Vec<KeySlot>, usize, &[u8; KEY_LENGTH], CipherAlgorithm) -> Result<()>,
+{
+    stage_key_slots(path, credentials, update)?.commit()
+}
+
+/// Replace the credentials protecting `path`
+///
+/// The slot opened by `old` is re-sealed for `new`; other slots are kept.
+pub fn rekey_file<P: AsRef<Path>>(
+    path: P,
+    old: &Credentials,
+    new: &Credentials,
+    params: &Argon2Params,
+) -> Result<()> {
+    stage_rekey(path, old, new, params)?.commit()
+}
+
+/// Prepare a rekey of `path` without touching the original yet
+///
+/// Staging every file first and committing afterwards lets a batch rekey
+/// stop before anything changes if one file cannot be opened.
+pub fn stage_rekey<P: AsRef<Path>>(
+    path: P,
+    old: &Credentials,
+    new: &Credentials,
+    params: &Argon2Params,
+) -> Result<StagedUpdate> {
+    stage_key_slots(path.as_ref(), old, |slots, unlocked, file_key, algorithm| {
+        slots[unlocked] = KeySlot::seal(new, file_key, algorithm, params)?;
+        Ok(())
+    })
+}
+
+/// A rewritten crate waiting in a temporary file next to the original
+#[derive(Debug)]
+#[must_use = "a staged update does nothing until it is committed"]
+pub struct StagedUpdate {
+    path: PathBuf,
+    temp_file: NamedTempFile,
+}
+
+impl StagedUpdate {
+    /// The file that will be replaced
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Atomically replace the original file
+    pub fn commit(self) -> Result<()> {
+        // A failed rename drops (and so deletes) the temporary file
+        self.temp_file
+            .persist(&self.path)
+            .map_err(|e| CrateError::Io(e.error))?;
+        Ok(())
+    }
+
+    /// Drop the staged changes, leaving the original untouched
+    pub fn discard(self) {}
+}
+
+fn stage_key_slots<F>(path: &Path, credentials: &Credentials, update: F) -> Result<StagedUpdate>
+where
+    F: FnOnce(&mut Vec<KeySlot>, usize, &[u8; KEY_LENGTH], CipherAlgorithm) -> Result<()>,
+{
+    let mut reader = BufReader::new(
+        File::open(path)
+            .map_err(|e| CrateError::FileNotFound(format!("{}: {}", path.display(), e)))?,
+    );
+    let (mut header, metadata_block) = read_header(&mut reader)?;
+    require_key_slots(&header)?;
+
+    let (unlocked, file_key) = unlock_slot(&header, credentials)?;
+    let mut slots = read_key_slots(&header)?;
+    update(&mut slots, unlocked, &file_key, header.algorithm)?;
+    write_key_slots(&mut header, &slots)?;
+
+    // A uniquely named file in the same directory, so the rename is atomic
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut temp_file = NamedTempFile::new_in(dir)?;
+
+    let mut writer = BufWriter::new(temp_file.as_file_mut());
+    header.write_to(&mut writer)?;
+    writer.write_all(&metadata_block)?;
+    io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+    drop(writer);
+
+    let file = temp_file.as_file();
+    file.set_permissions(fs::metadata(path)?.permissions())?;
+    file.sync_all()?;
+
+    Ok(StagedUpdate {
+        path: path.to_path_buf(),
+        temp_file,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::encryption::{
+        decrypt_file_with_credentials, encrypt_file_with_options, test_options, EncryptOptions,
+    };
+    use crate::crypto::key_derivation::{derive_key_with_params, test_params};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_slot_roundtrip() {
+        let file_key = [9u8; KEY_LENGTH];
+        let credentials = Credentials::password("hunter2");
+        let slot = KeySlot::seal(&credentials, &file_key, CipherAlgorithm::Aes256Gcm, &test_params()).unwrap();
+
+        let mut header = FileHeader::new(
+            CipherAlgorithm::Aes256Gcm,
+            KdfDescriptor::KeySlots,
+            vec![0u8; NONCE_LENGTH],
+            0,
+        );
+        write_key_slots(&mut header, std::slice::from_ref(&slot)).unwrap();
+        let parsed = FileHeader::read_from(&mut header.to_bytes().as_slice()).unwrap();
+        assert_eq!(read_key_slots(&parsed).unwrap(), vec![slot]);
+
+        assert_eq!(*unlock(&parsed, &credentials).unwrap(), file_key);
+        assert!(matches!(
+            unlock(&parsed, &Credentials::password("wrong")),
+            Err(CrateError::InvalidPassword)
+        ));
+        // A key file never opens a password slot
+        assert!(matches!(
+            unlock(&parsed, &Credentials::new(None, Some([1u8; 32].into()))),
+            Err(CrateError::InvalidPassword)
+        ));
+    }
+
+    #[test]
+    fn test_slot_constructions() {
+        let file_key = [4u8; KEY_LENGTH];
+        let keyfile = Credentials::new(None, Some([1u8; 32].into()));
+        let both = Credentials::new(Some("pw".into()), Some([1u8; 32].into()));
+
+        let slot = KeySlot::seal(&keyfile, &file_key, CipherAlgorithm::Aes256Gcm, &test_params()).unwrap();
+        assert!(matches!(slot.kdf, KdfDescriptor::HkdfSha256 { .. }));
+        let slot = KeySlot::seal(&both, &file_key, CipherAlgorithm::Aes256Gcm, &test_params()).unwrap();
+        assert!(matches!(
+            &slot.kdf,
+            KdfDescriptor::Subkey { base, .. } if matches!(**base, KdfDescriptor::Argon2idKeyfile { .. })
+        ));
+        assert_eq!(*slot.open(&both, CipherAlgorithm::Aes256Gcm).unwrap().unwrap(), file_key);
+
+        // Slots from before the keyed constructions fed hex into plain Argon2id
+        let kdf = KdfDescriptor::Argon2id {
+            params: test_params(),
+            salt: [2u8; SALT_LENGTH],
+        };
+        let kek = derive_key_with_params(&hex::encode([1u8; 32]), &[2u8; SALT_LENGTH], &test_params()).unwrap();
+        let legacy =
+            KeySlot::wrap(SlotKind::Keyfile, kdf, &kek, &file_key, CipherAlgorithm::Aes256Gcm).unwrap();
+        assert_eq!(*legacy.open(&keyfile, CipherAlgorithm::Aes256Gcm).unwrap().unwrap(), file_key);
+    }
+
+    #[test]
+    fn test_batch_shares_argon2() {
+        let file_key = [5u8; KEY_LENGTH];
+        let algorithm = CipherAlgorithm::Aes256Gcm;
+        let base_of = |slot: &KeySlot| match &slot.kdf {
+            KdfDescriptor::Subkey { base, salt } => ((**base).clone(), *salt),
+            kdf => panic!("unexpected KDF: {:?}", kdf),
+        };
+
+        let credentials = Credentials::password("batch");
+        let first = KeySlot::seal(&credentials, &file_key, algorithm, &test_params()).unwrap();
+        let second = KeySlot::seal(&credentials.clone(), &file_key, algorithm, &test_params()).unwrap();
+        let (first_base, first_salt) = base_of(&first);
+        let (second_base, second_salt) = base_of(&second);
+        assert_eq!(first_base, second_base);
+        assert_ne!(first_salt, second_salt);
+
+        // Other parameters or a changed password get a base of their own
+        let costlier = Argon2Params {
+            time_cost: 2,
+            ..test_params()
+        };
+        let slot = KeySlot::seal(&credentials, &file_key, algorithm, &costlier).unwrap();
+        assert_ne!(base_of(&slot).0, first_base);
+        let mut changed = credentials.clone();
+        changed.password = Some("changed".into());
+        let slot = KeySlot::seal(&changed, &file_key, algorithm, &test_params()).unwrap();
+        assert_ne!(base_of(&slot).0, first_base);
+        assert!(slot.open(&Credentials::password("changed"), algorithm).unwrap().is_some());
+
+        // Opening the batch derives the shared key once
+        let fresh = Credentials::password("batch");
+        assert_eq!(*first.open(&fresh, algorithm).unwrap().unwrap(), file_key);
+        assert_eq!(*second.open(&fresh, algorithm).unwrap().unwrap(), file_key);
+        assert_eq!(fresh.base_keys.0.lock().unwrap().len(), 1);
+        assert!(first.open(&Credentials::password("wrong"), algorithm).unwrap().is_none());
+
+        // Only the most recent keys are kept
+        for byte in 0..=MAX_BASE_KEYS as u8 {
+            let kdf = KdfDescriptor::Argon2id {
+                params: test_params(),
+                salt: [byte; SALT_LENGTH],
+            };
+            fresh.base_key(&kdf).unwrap();
+        }
+        let keys = fresh.base_keys.0.lock().unwrap();
+        assert_eq!(keys.len(), MAX_BASE_KEYS);
+        assert!(keys.iter().all(|k| k.kdf != first_base));
+    }
+
+    #[test]
+    fn test_add_and_remove_slots() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("shared.txt");
+        let encrypted_path = temp_dir.path().join("shared.txt.crat");
+        let decrypted_path = temp_dir.path().join("out.txt");
+        fs::write(&input_path, b"Shared secret").unwrap();
+
+        let alice = Credentials::password("alice");
+        let bob = Credentials::new(Some("bob".into()), Some([7u8; 32].into()));
+        let options = test_options();
+        encrypt_file_with_options(&input_path, &encrypted_path, &alice, &options).unwrap();
+
+        // Adding a slot needs working credentials
+        assert!(add_key_slot(&encrypted_path, &bob, &bob, &test_params()).is_err());
+        assert_eq!(add_key_slot(&encrypted_path, &alice, &bob, &test_params()).unwrap(), 1);
+
+        let slots = list_key_slots(&encrypted_path).unwrap();
+        assert_eq!(slots.len(), 2);
+        assert_eq!(slots[1].kind, SlotKind::PasswordKeyfile);
+
+        decrypt_file_with_credentials(&encrypted_path, &decrypted_path, &bob).unwrap();
+        assert_eq!(fs::read(&decrypted_path).unwrap(), b"Shared secret");
+
+        // Bob removes Alice's slot; only Bob can decrypt now
+        remove_key_slot(&encrypted_path, &bob, 0).unwrap();
+        assert!(decrypt_file_with_credentials(&encrypted_path, &decrypted_path, &alice).is_err());
+        decrypt_file_with_credentials(&encrypted_path, &decrypted_path, &bob).unwrap();
+
+        // The last slot stays
+        assert!(remove_key_slot(&encrypted_path, &bob, 0).is_err());
+    }
+
+    #[test]
+    fn test_rekey() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("notes.txt");
+        let encrypted_path = temp_dir.path().join("notes.txt.crat");
+        let decrypted_path = temp_dir.path().join("out.txt");
+        fs::write(&input_path, b"Rotate me").unwrap();
+
+        let old = Credentials::password("old password");
+        let new = Credentials::new(Some("new password".into()), Some([3u8; 32].into()));
+        let other = Credentials::password("other");
+        let options = test_options();
+        encrypt_file_with_options(&input_path, &encrypted_path, &old, &options).unwrap();
+        add_key_slot(&encrypted_path, &old, &other, &test_params()).unwrap();
+        let payload_len = fs::metadata(&encrypted_path).unwrap().len();
+
+        // Discarding a staged rekey leaves the file as it was
+        stage_rekey(&encrypted_path, &old, &new, &test_params()).unwrap().discard();
+        decrypt_file_with_credentials(&encrypted_path, &decrypted_path, &old).unwrap();
+
+        rekey_file(&encrypted_path, &old, &new, &test_params()).unwrap();
+        assert!(decrypt_file_with_credentials(&encrypted_path, &decrypted_path, &old).is_err());
+        decrypt_file_with_credentials(&encrypted_path, &decrypted_path, &new).unwrap();
+        assert_eq!(fs::read(&decrypted_path).unwrap(), b"Rotate me");
+
+        // Only the opened slot changes
+        let slots = list_key_slots(&encrypted_path).unwrap();
+        assert_eq!(slots.len(), 2);
+        assert_eq!(slots[0].kind, SlotKind::PasswordKeyfile);
+        decrypt_file_with_credentials(&encrypted_path, &decrypted_path, &other).unwrap();
+
+        // No stray temporary files, and the payload size is unchanged
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 3);
+        assert_eq!(fs::metadata(&encrypted_path).unwrap().len(), payload_len);
+    }
+
+    #[test]
+    fn test_slot_updates_leave_other_files_alone() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("notes.txt");
+        let encrypted_path = temp_dir.path().join("notes.txt.crat");
+        let decrypted_path = temp_dir.path().join("out.txt");
+        fs::write(&input_path, b"Keep my neighbours").unwrap();
+
+        let owner = Credentials::password("owner");
+        let options = test_options();
+        encrypt_file_with_options(&input_path, &encrypted_path, &owner, &options).unwrap();
+
+        // A file that happens to look like an old temporary name is not ours to delete
+        let lookalike = temp_dir.path().join(".notes.txt.crat.tmp");
+        fs::write(&lookalike, b"user data").unwrap();
+
+        let other = Credentials::password("other");
+        add_key_slot(&encrypted_path, &owner, &other, &test_params()).unwrap();
+        assert_eq!(fs::read(&lookalike).unwrap(), b"user data");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 3);
+
+        decrypt_file_with_credentials(&encrypted_path, &decrypted_path, &other).unwrap();
+        assert_eq!(fs::read(&decrypted_path).unwrap(), b"Keep my neighbours");
+    }
+
+    #[test]
+    fn test_multiple_recipients() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("artefact.bin");
+        let encrypted_path = temp_dir.path().join("artefact.bin.crat");
+        let decrypted_path = temp_dir.path().join("out.bin");
+        fs::write(&input_path, b"build output").unwrap();
+
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let options = EncryptOptions {
+            recipients: vec![alice.recipient(), bob.recipient()],
+            ..EncryptOptions::default()
+        };
+        encrypt_file_with_options(&input_path, &encrypted_path, &Credentials::default(), &options)
+            .unwrap();
+
+        let slots = list_key_slots(&encrypted_path).unwrap();
+        assert_eq!(slots.len(), 2);
+        assert!(slots.iter().all(|slot| slot.kind == SlotKind::Recipient));
+
+        for identity in [alice, bob] {
+            let credentials = Credentials::identity(identity);
+            decrypt_file_with_credentials(&encrypted_path, &decrypted_path, &credentials).unwrap();
+            assert_eq!(fs::read(&decrypted_path).unwrap(), b"build output");
+        }
+
+        let stranger = Credentials::identity(Identity::generate());
+        let result = decrypt_file_with_credentials(&encrypted_path, &decrypted_path, &stranger);
+        assert!(matches!(result, Err(CrateError::InvalidPassword)));
+    }
+}
@@ -1,7 +1,14 @@
 //! Cryptography module
 
+pub mod cipher;
 pub mod encryption;
 pub mod key_derivation;
+pub mod keyslot;
 
-pub use encryption::{decrypt_file, encrypt_file};
-pub use key_derivation::derive_key;
+pub use cipher::{Cipher, CipherAlgorithm};
+pub use encryption::{
+    decrypt_file, decrypt_file_with_credentials, encrypt_file, encrypt_file_with_options,
+    EncryptOptions,
+};
+pub use key_derivation::{derive_key, Argon2Params};
+pub use keyslot::{Credentials, KeySlot, SlotKind, StagedUpdate};
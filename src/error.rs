@@ -1,11 +1,12 @@
 //! Error types for CryptoCrate
 
+use std::io;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum CrateError {
     #[error("IO error: {0}")]
-    Io(#[from] std::io::Error),
+    Io(#[source] io::Error),
 
     #[error("Encryption error: {0}")]
     Encryption(String),
@@ -16,9 +17,12 @@ pub enum CrateError {
     #[error("Invalid file format: {0}")]
     InvalidFormat(String),
 
-    #[error("Invalid password")]
+    #[error("Invalid password, or the file has been tampered with")]
     InvalidPassword,
 
+    #[error("Authentication failed: {0}")]
+    AuthenticationFailed(String),
+
     #[error("Key derivation error: {0}")]
     KeyDerivation(String),
 
@@ -30,3 +34,23 @@ pub enum CrateError {
 }
 
 pub type Result<T> = std::result::Result<T, CrateError>;
+
+impl From<io::Error> for CrateError {
+    fn from(error: io::Error) -> Self {
+        // Errors that travelled through `Read`/`Write` (e.g. from `CrateReader`) are unwrapped again
+        if error.get_ref().is_some_and(|inner| inner.is::<CrateError>()) {
+            let inner = error.into_inner().expect("checked above");
+            return *inner.downcast::<CrateError>().expect("checked above");
+        }
+        CrateError::Io(error)
+    }
+}
+
+impl From<CrateError> for io::Error {
+    fn from(error: CrateError) -> Self {
+        match error {
+            CrateError::Io(error) => error,
+            error => io::Error::new(io::ErrorKind::InvalidData, error),
+        }
+    }
+}
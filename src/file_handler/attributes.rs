@@ -0,0 +1,270 @@
+//! Unix permissions, ownership, extended attributes and symlinks
+//!
+//! Capture is opt-in (`EncryptOptions::preserve_attributes`). On other
+//! platforms nothing is captured and restoring is a no-op, except for
+//! symbolic links, which cannot be recreated there.
+
+#[cfg(unix)]
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::error::{CrateError, Result};
+
+/// Attributes recorded alongside a file's contents
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileAttributes {
+    /// Permission bits, including setuid/setgid/sticky
+    pub mode: Option<u32>,
+    /// Numeric user and group id
+    pub owner: Option<(u32, u32)>,
+    /// Extended attributes as (name, value) pairs
+    pub xattrs: Vec<(String, Vec<u8>)>,
+    /// Where the link pointed, if the file was a symbolic link
+    pub symlink_target: Option<String>,
+    /// Extended attributes found but not recorded: names that are not UTF-8
+    /// or values too large for a metadata record (never stored in a file)
+    pub skipped_xattrs: Vec<String>,
+}
+
+impl FileAttributes {
+    /// Whether the file was a symbolic link (its contents are empty)
+    pub fn is_symlink(&self) -> bool {
+        self.symlink_target.is_some()
+    }
+}
+
+/// When to restore the recorded owner
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OwnerPolicy {
+    /// Only when running as root, like `tar`
+    #[default]
+    Auto,
+    /// Always; fails for other users' ids unless running as root
+    Always,
+    /// Never; files belong to whoever decrypts them
+    Never,
+}
+
+impl OwnerPolicy {
+    fn applies(self) -> bool {
+        match self {
+            OwnerPolicy::Auto => is_root(),
+            OwnerPolicy::Always => true,
+            OwnerPolicy::Never => false,
+        }
+    }
+}
+
+/// Extended attributes larger than this are not captured (they would not fit a metadata record)
+const MAX_XATTR_LENGTH: usize = u16::MAX as usize - 256;
+
+/// Read the attributes of `path`, without following a final symlink
+#[cfg(unix)]
+pub fn capture_attributes<P: AsRef<Path>>(path: P) -> Result<FileAttributes> {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = path.as_ref();
+    let metadata = fs::symlink_metadata(path)?;
+    let owner = Some((metadata.uid(), metadata.gid()));
+
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path)?
+            .into_os_string()
+            .into_string()
+            .map_err(|_| {
+                CrateError::InvalidFormat(format!(
+                    "Symlink target is not valid UTF-8: {}",
+                    path.display()
+                ))
+            })?;
+        return Ok(FileAttributes {
+            mode: None,
+            owner,
+            xattrs: Vec::new(),
+            symlink_target: Some(target),
+            skipped_xattrs: Vec::new(),
+        });
+    }
+
+    let (xattrs, skipped_xattrs) = read_xattrs(path)?;
+    Ok(FileAttributes {
+        mode: Some(metadata.mode() & 0o7777),
+        owner,
+        xattrs,
+        symlink_target: None,
+        skipped_xattrs,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn capture_attributes<P: AsRef<Path>>(_path: P) -> Result<FileAttributes> {
+    Ok(FileAttributes::default())
+}
+
+/// Extended attributes as (name, value) pairs
+#[cfg(unix)]
+type Xattrs = Vec<(String, Vec<u8>)>;
+
+/// Extended attributes of `path`, and the names of those that cannot be recorded
+#[cfg(unix)]
+fn read_xattrs(path: &Path) -> Result<(Xattrs, Vec<String>)> {
+    let names = match xattr::list(path) {
+        Ok(names) => names,
+        // The file system has no extended attributes
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => return Ok(Default::default()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut xattrs = Vec::new();
+    let mut skipped = Vec::new();
+    for name in names {
+        let name = match name.into_string() {
+            Ok(name) => name,
+            Err(name) => {
+                skipped.push(name.to_string_lossy().into_owned());
+                continue;
+            }
+        };
+        if let Some(value) = xattr::get(path, &name)? {
+            if value.len() <= MAX_XATTR_LENGTH {
+                xattrs.push((name, value));
+            } else {
+                skipped.push(name);
+            }
+        }
+    }
+    xattrs.sort();
+    skipped.sort();
+    Ok((xattrs, skipped))
+}
+
+/// Apply recorded attributes to `path`
+///
+/// Everything is attempted; the first failure is returned.
+#[cfg(unix)]
+pub fn restore_attributes<P: AsRef<Path>>(
+    path: P,
+    attributes: &FileAttributes,
+    owner: OwnerPolicy,
+) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = path.as_ref();
+    let mut first_error = None;
+    let mut record = |result: io::Result<()>| {
+        if let Err(e) = result {
+            first_error.get_or_insert(e);
+        }
+    };
+
+    // Ownership first: chown clears the setuid and setgid bits
+    if let Some((uid, gid)) = attributes.owner {
+        if owner.applies() {
+            record(std::os::unix::fs::lchown(path, Some(uid), Some(gid)));
+        }
+    }
+
+    if !attributes.is_symlink() {
+        if let Some(mode) = attributes.mode {
+            record(fs::set_permissions(path, fs::Permissions::from_mode(mode)));
+        }
+        for (name, value) in &attributes.xattrs {
+            record(xattr::set(path, name, value));
+        }
+    }
+
+    match first_error {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn restore_attributes<P: AsRef<Path>>(
+    _path: P,
+    _attributes: &FileAttributes,
+    _owner: OwnerPolicy,
+) -> Result<()> {
+    Ok(())
+}
+
+/// Create a symbolic link at `path` pointing to `target`
+#[cfg(unix)]
+pub fn create_symlink<P: AsRef<Path>>(target: &str, path: P) -> Result<()> {
+    Ok(std::os::unix::fs::symlink(target, path)?)
+}
+
+#[cfg(not(unix))]
+pub fn create_symlink<P: AsRef<Path>>(_target: &str, _path: P) -> Result<()> {
+    Err(CrateError::Io(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symbolic links can only be restored on Unix",
+    )))
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    // SAFETY: plain system call without arguments
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_capture_and_restore() {
+        let temp_dir = TempDir::new().unwrap();
+        let script = temp_dir.path().join("run.sh");
+        fs::write(&script, b"#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o750)).unwrap();
+        let link = temp_dir.path().join("latest");
+        std::os::unix::fs::symlink("run.sh", &link).unwrap();
+
+        let attributes = capture_attributes(&script).unwrap();
+        assert_eq!(attributes.mode, Some(0o750));
+        assert!(attributes.owner.is_some());
+        assert!(!attributes.is_symlink());
+
+        let link_attributes = capture_attributes(&link).unwrap();
+        assert_eq!(link_attributes.symlink_target.as_deref(), Some("run.sh"));
+
+        let restored = temp_dir.path().join("restored.sh");
+        fs::write(&restored, b"#!/bin/sh\n").unwrap();
+        restore_attributes(&restored, &attributes, OwnerPolicy::Never).unwrap();
+        let mode = fs::metadata(&restored).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o750);
+
+        let restored_link = temp_dir.path().join("restored-link");
+        create_symlink("run.sh", &restored_link).unwrap();
+        restore_attributes(&restored_link, &link_attributes, OwnerPolicy::Never).unwrap();
+        assert_eq!(fs::read_link(&restored_link).unwrap(), Path::new("run.sh"));
+    }
+
+    #[test]
+    fn test_unrecordable_xattrs_are_reported() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("tagged.txt");
+        fs::write(&path, b"content").unwrap();
+        // Not every file system takes user attributes
+        if xattr::set(&path, "user.origin", b"web").is_err() {
+            return;
+        }
+        xattr::set(&path, OsStr::from_bytes(b"user.\xff"), b"bad name").unwrap();
+
+        let attributes = capture_attributes(&path).unwrap();
+        assert_eq!(attributes.xattrs, vec![("user.origin".to_string(), b"web".to_vec())]);
+        assert_eq!(attributes.skipped_xattrs, vec!["user.\u{fffd}".to_string()]);
+    }
+}
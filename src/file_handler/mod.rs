@@ -1,5 +1,9 @@
 //! File handling module
 
+pub mod attributes;
 pub mod walker;
 
-pub use walker::{collect_files, FileEntry};
+pub use attributes::{
+    capture_attributes, create_symlink, restore_attributes, FileAttributes, OwnerPolicy,
+};
+pub use walker::{collect_files, collect_files_and_symlinks, join_relative, FileEntry};
@@ -1,10 +1,10 @@
 //! Directory traversal utilities
 
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use walkdir::WalkDir;
 
-use crate::error::Result;
+use crate::error::{CrateError, Result};
 
 /// Represents a file entry with metadata
 #[derive(Debug, Clone)]
@@ -15,6 +15,69 @@ pub struct FileEntry {
     pub size: u64,
 }
 
+impl FileEntry {
+    /// `relative_path` as a `/`-separated string, the form stored in crates
+    pub fn portable_path(&self) -> Result<String> {
+        let mut parts = Vec::new();
+        for component in self.relative_path.components() {
+            match component {
+                Component::Normal(part) => parts.push(part.to_str().ok_or_else(|| {
+                    CrateError::InvalidFormat(format!(
+                        "Path is not valid UTF-8: {}",
+                        self.path.display()
+                    ))
+                })?),
+                Component::ParentDir => {
+                    return Err(CrateError::InvalidFormat(format!(
+                        "Path leads outside the folder being encrypted: {}",
+                        self.relative_path.display()
+                    )))
+                }
+                Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            }
+        }
+        Ok(parts.join("/"))
+    }
+}
+
+/// Join a stored relative path onto `dir`
+///
+/// Paths read from a crate are untrusted: absolute paths, `..` and other
+/// components that could escape `dir` are refused, and so are paths that
+/// pass through a symlink inside `dir` (which an earlier entry may have
+/// created).
+pub fn join_relative<P: AsRef<Path>>(dir: P, relative: &str) -> Result<PathBuf> {
+    let relative_path = Path::new(relative);
+    let is_safe = !relative.is_empty()
+        && relative_path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    if !is_safe {
+        return Err(CrateError::InvalidFormat(format!(
+            "Unsafe path: {}",
+            relative
+        )));
+    }
+
+    // Only the parents matter: a final symlink is replaced, not followed
+    let mut ancestor = dir.as_ref().to_path_buf();
+    let mut components = relative_path.components().peekable();
+    while let Some(component) = components.next() {
+        if components.peek().is_none() {
+            break;
+        }
+        ancestor.push(component);
+        if fs::symlink_metadata(&ancestor).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(CrateError::InvalidFormat(format!(
+                "Path leads through a symlink: {}",
+                relative
+            )));
+        }
+    }
+
+    Ok(dir.as_ref().join(relative_path))
+}
+
 /// Collect all files from a path (file or directory)
 ///
 /// # Arguments
@@ -24,6 +87,21 @@ pub struct FileEntry {
 /// # Returns
 /// Vector of FileEntry objects
 pub fn collect_files<P: AsRef<Path>>(path: P, base_path: Option<P>) -> Result<Vec<FileEntry>> {
+    collect(path, base_path, false)
+}
+
+/// Like `collect_files`, but symbolic links are returned as entries of their own
+///
+/// `collect_files` follows links to files and skips links to folders; here
+/// every link is kept (with size 0) so it can be recreated later.
+pub fn collect_files_and_symlinks<P: AsRef<Path>>(
+    path: P,
+    base_path: Option<P>,
+) -> Result<Vec<FileEntry>> {
+    collect(path, base_path, true)
+}
+
+fn collect<P: AsRef<Path>>(path: P, base_path: Option<P>, symlinks: bool) -> Result<Vec<FileEntry>> {
     let path = path.as_ref();
     let base = base_path
         .map(|p| p.as_ref().to_path_buf())
@@ -35,7 +113,14 @@ pub fn collect_files<P: AsRef<Path>>(path: P, base_path: Option<P>) -> Result<Ve
 
     let mut entries = Vec::new();
 
-    if path.is_file() {
+    if symlinks && path.is_symlink() {
+        entries.push(FileEntry {
+            path: path.to_path_buf(),
+            relative_path: path.strip_prefix(&base).unwrap_or(path).to_path_buf(),
+            is_dir: false,
+            size: 0,
+        });
+    } else if path.is_file() {
         // Single file
         let metadata = fs::metadata(path)?;
         let relative_path = path
@@ -57,8 +142,8 @@ pub fn collect_files<P: AsRef<Path>>(path: P, base_path: Option<P>) -> Result<Ve
             .filter_map(|e| e.ok())
         {
             let entry_path = entry.path();
-            if entry_path.is_file() {
-                let metadata = fs::metadata(entry_path)?;
+            let is_symlink = symlinks && entry.path_is_symlink();
+            if is_symlink || entry_path.is_file() {
                 let relative_path = entry_path
                     .strip_prefix(&base)
                     .unwrap_or(entry_path)
@@ -68,7 +153,7 @@ pub fn collect_files<P: AsRef<Path>>(path: P, base_path: Option<P>) -> Result<Ve
                     path: entry_path.to_path_buf(),
                     relative_path,
                     is_dir: false,
-                    size: metadata.len(),
+                    size: if is_symlink { 0 } else { fs::metadata(entry_path)?.len() },
                 });
             }
         }
@@ -106,4 +191,63 @@ mod tests {
         let files = collect_files(&dir_path, None).unwrap();
         assert_eq!(files.len(), 2);
     }
+
+    #[test]
+    fn test_relative_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path().join("docs");
+        fs::create_dir_all(dir_path.join("a/b")).unwrap();
+        fs::write(dir_path.join("a/b/README.md"), b"nested").unwrap();
+
+        let files = collect_files(&dir_path, None).unwrap();
+        assert_eq!(files[0].portable_path().unwrap(), "docs/a/b/README.md");
+        assert_eq!(
+            join_relative("out", "docs/a/b/README.md").unwrap(),
+            Path::new("out/docs/a/b/README.md")
+        );
+
+        for unsafe_path in ["../escape.txt", "/etc/passwd", "a/../../b", "./a", ""] {
+            assert!(join_relative("out", unsafe_path).is_err(), "{:?} was accepted", unsafe_path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_relative_paths_through_symlinks() {
+        let temp_dir = TempDir::new().unwrap();
+        let out = temp_dir.path().join("out");
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir_all(&out).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+
+        // A crate restores the symlink `a -> ../outside`, then a file `a/x`
+        let link = join_relative(&out, "a").unwrap();
+        std::os::unix::fs::symlink("../outside", &link).unwrap();
+        assert!(join_relative(&out, "a/x").is_err());
+        assert!(join_relative(&out, "a/b/x").is_err());
+
+        // The symlink itself can still be replaced
+        assert_eq!(join_relative(&out, "a").unwrap(), link);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_collect_symlinks() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path().join("dir");
+        fs::create_dir_all(dir_path.join("sub")).unwrap();
+        fs::write(dir_path.join("file.txt"), b"content").unwrap();
+        std::os::unix::fs::symlink("file.txt", dir_path.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink("sub", dir_path.join("link-dir")).unwrap();
+        std::os::unix::fs::symlink("missing", dir_path.join("dangling")).unwrap();
+
+        // Followed links to files only
+        assert_eq!(collect_files(&dir_path, None).unwrap().len(), 2);
+
+        let mut files = collect_files_and_symlinks(&dir_path, None).unwrap();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let names: Vec<_> = files.iter().map(|f| f.portable_path().unwrap()).collect();
+        assert_eq!(names, ["dir/dangling", "dir/file.txt", "dir/link-dir", "dir/link.txt"]);
+        assert_eq!(files[3].size, 0);
+    }
 }
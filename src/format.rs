@@ -1,47 +1,749 @@
 //! File format constants and structures
+//!
+//! Two header layouts exist on disk:
+//!
+//! - Version 1: fixed 54-byte header (magic, version, algorithm, salt,
+//!   nonce, metadata length).
+//! - Version 2: self-describing header with flags, a chunk size, a KDF
+//!   descriptor and optional extension records.
+//!
+//! `FileHeader::read_from` parses both, so every reader goes through the
+//! same code path regardless of which version wrote the file.
+//!
+//! In version 2 the metadata block following the header may be encrypted
+//! (`FLAG_ENCRYPTED_METADATA`). It is then stored as a random nonce followed
+//! by the AEAD ciphertext, sealed with the serialized header as associated
+//! data.
+//!
+//! Newly written files encrypt the payload with a random file key that is
+//! wrapped by one or more key slots (`EXT_KEY_SLOTS`, see
+//! `crypto::keyslot`). The key slot record is left out of the associated
+//! data so slots can be added or removed without touching the payload.
+//!
+//! Password slots written by a batch share one Argon2id derivation: each
+//! slot records the shared descriptor plus its own HKDF salt
+//! (`KdfDescriptor::Subkey`).
+
+use std::io::{Read, Write};
+
+use crate::crypto::cipher::CipherAlgorithm;
+use crate::crypto::key_derivation::Argon2Params;
+use crate::error::{CrateError, Result};
 
 /// Magic bytes identifying a CryptoCrate file: "CRAT"
 pub const MAGIC_BYTES: &[u8; 4] = b"CRAT";
 
-/// Current file format version
-pub const VERSION: u8 = 1;
+/// Original fixed-layout format version
+pub const VERSION_1: u8 = 1;
+
+/// Extensible format version
+pub const VERSION_2: u8 = 2;
+
+/// Current file format version (used for all newly written files)
+pub const VERSION: u8 = VERSION_2;
 
 /// Algorithm identifier for AES-256-GCM
 pub const ALGORITHM_AES256_GCM: u8 = 1;
 
+/// Algorithm identifier for AES-256-GCM in segmented STREAM mode
+/// (version 1 only; version 2 records chunking in the chunk size field)
+pub const ALGORITHM_AES256_GCM_STREAM: u8 = 2;
+
+/// Algorithm identifier for XChaCha20-Poly1305 (version 2 only)
+pub const ALGORITHM_XCHACHA20_POLY1305: u8 = 3;
+
+/// KDF identifier for Argon2id
+pub const KDF_ARGON2ID: u8 = 1;
+
+/// KDF identifier for a random file key stored in key slots
+pub const KDF_KEY_SLOTS: u8 = 2;
+
+/// KDF identifier for X25519 + HKDF-SHA256 (recipient key slots only)
+pub const KDF_X25519: u8 = 3;
+
+/// KDF identifier for Argon2id over the password, keyed with the key file
+pub const KDF_ARGON2ID_KEYFILE: u8 = 4;
+
+/// KDF identifier for HKDF-SHA256 over a key file (key file slots only)
+pub const KDF_HKDF_SHA256: u8 = 5;
+
+/// KDF identifier for an HKDF-SHA256 subkey of a shared Argon2id key
+pub const KDF_SUBKEY: u8 = 6;
+
+/// Extension record holding the key slots
+pub const EXT_KEY_SLOTS: u16 = 1;
+
+/// Header flag: the metadata block is encrypted under the file key
+pub const FLAG_ENCRYPTED_METADATA: u16 = 0x0001;
+
+/// Header flag: the payload is an archive of several files (see `archive`)
+pub const FLAG_ARCHIVE: u16 = 0x0002;
+
+/// Header flag: the chunked payload is a compressed stream (algorithm in the metadata)
+pub const FLAG_COMPRESSED_STREAM: u16 = 0x0004;
+
+/// Flags understood by this implementation
+pub const KNOWN_FLAGS: u16 = FLAG_ENCRYPTED_METADATA | FLAG_ARCHIVE | FLAG_COMPRESSED_STREAM;
+
 /// Salt length for Argon2 (32 bytes)
 pub const SALT_LENGTH: usize = 32;
 
 /// Nonce length for AES-GCM (12 bytes)
 pub const NONCE_LENGTH: usize = 12;
 
+/// Nonce length for XChaCha20-Poly1305 (24 bytes)
+pub const XNONCE_LENGTH: usize = 24;
+
 /// AES-256 key length (32 bytes)
 pub const KEY_LENGTH: usize = 32;
 
-/// GCM authentication tag length (16 bytes)
+/// Authentication tag length (16 bytes for both GCM and Poly1305)
 pub const TAG_LENGTH: usize = 16;
 
-/// Total header size (without metadata)
+/// Chunk size used by version 1 streaming files (1 MB)
+pub const V1_CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// Largest chunk size a reader will accept (64 MB)
+pub const MAX_CHUNK_SIZE: u32 = 64 * 1024 * 1024;
+
+/// Total version 1 header size (without metadata)
 pub const HEADER_SIZE: usize = 4 + 1 + 1 + SALT_LENGTH + NONCE_LENGTH + 4; // 54 bytes
 
+/// Length of the Argon2id descriptor: three cost parameters and the salt
+const ARGON2ID_DESCRIPTOR_LENGTH: usize = 4 + 4 + 4 + SALT_LENGTH;
+
+/// Key derivation function and its inputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KdfDescriptor {
+    Argon2id {
+        params: Argon2Params,
+        salt: [u8; SALT_LENGTH],
+    },
+    /// Random file key, wrapped by the key slots in the header
+    KeySlots,
+    /// Ephemeral X25519 public key; the key is agreed with a recipient's identity
+    X25519 { ephemeral: [u8; 32] },
+    /// Argon2id of the password with the key file hash as its secret input
+    Argon2idKeyfile {
+        params: Argon2Params,
+        salt: [u8; SALT_LENGTH],
+    },
+    /// HKDF-SHA256 of the key file hash
+    HkdfSha256 { salt: [u8; SALT_LENGTH] },
+    /// HKDF-SHA256 subkey of the key `base` derives
+    ///
+    /// `base` is an Argon2id descriptor shared by a batch of files, so the
+    /// expensive derivation runs once per batch instead of once per file.
+    Subkey {
+        base: Box<KdfDescriptor>,
+        salt: [u8; SALT_LENGTH],
+    },
+}
+
+impl KdfDescriptor {
+    /// Identifier stored in the header
+    pub fn id(&self) -> u8 {
+        match self {
+            KdfDescriptor::Argon2id { .. } => KDF_ARGON2ID,
+            KdfDescriptor::KeySlots => KDF_KEY_SLOTS,
+            KdfDescriptor::X25519 { .. } => KDF_X25519,
+            KdfDescriptor::Argon2idKeyfile { .. } => KDF_ARGON2ID_KEYFILE,
+            KdfDescriptor::HkdfSha256 { .. } => KDF_HKDF_SHA256,
+            KdfDescriptor::Subkey { .. } => KDF_SUBKEY,
+        }
+    }
+
+    /// Human-readable name
+    pub fn name(&self) -> &'static str {
+        match self {
+            KdfDescriptor::Argon2id { .. } => "Argon2id",
+            KdfDescriptor::KeySlots => "Key slots",
+            KdfDescriptor::X25519 { .. } => "X25519",
+            KdfDescriptor::Argon2idKeyfile { .. } => "Argon2id (keyed)",
+            KdfDescriptor::HkdfSha256 { .. } => "HKDF-SHA256",
+            KdfDescriptor::Subkey { base, .. } => base.name(),
+        }
+    }
+
+    /// Human-readable name including cost parameters
+    pub fn description(&self) -> String {
+        match self {
+            KdfDescriptor::Argon2id { params, .. } => format!(
+                "Argon2id ({} KB memory, {} iterations, {} lanes)",
+                params.memory_kb, params.time_cost, params.parallelism
+            ),
+            KdfDescriptor::KeySlots => "Random file key in key slots".to_string(),
+            KdfDescriptor::X25519 { .. } => "X25519 + HKDF-SHA256".to_string(),
+            KdfDescriptor::Argon2idKeyfile { params, .. } => format!(
+                "Argon2id keyed with key file ({} KB memory, {} iterations, {} lanes)",
+                params.memory_kb, params.time_cost, params.parallelism
+            ),
+            KdfDescriptor::HkdfSha256 { .. } => "HKDF-SHA256".to_string(),
+            KdfDescriptor::Subkey { base, .. } => format!("{}, shared by a batch", base.description()),
+        }
+    }
+
+    /// Append the descriptor (id, length, data) to `bytes`
+    pub(crate) fn encode(&self, bytes: &mut Vec<u8>) {
+        let data = self.data();
+        bytes.push(self.id());
+        bytes.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&data);
+    }
+
+    /// Read a descriptor written by `encode`
+    pub(crate) fn decode<R: Read>(reader: &mut R) -> Result<Self> {
+        let id = read_u8(reader)?;
+        let len = read_u16(reader)? as usize;
+        let data = read_vec(reader, len)?;
+        Self::parse(id, &data)
+    }
+
+    fn data(&self) -> Vec<u8> {
+        match self {
+            KdfDescriptor::Argon2id { params, salt }
+            | KdfDescriptor::Argon2idKeyfile { params, salt } => {
+                let mut data = Vec::with_capacity(ARGON2ID_DESCRIPTOR_LENGTH);
+                data.extend_from_slice(&params.memory_kb.to_le_bytes());
+                data.extend_from_slice(&params.time_cost.to_le_bytes());
+                data.extend_from_slice(&params.parallelism.to_le_bytes());
+                data.extend_from_slice(salt);
+                data
+            }
+            KdfDescriptor::KeySlots => Vec::new(),
+            KdfDescriptor::X25519 { ephemeral } => ephemeral.to_vec(),
+            KdfDescriptor::HkdfSha256 { salt } => salt.to_vec(),
+            KdfDescriptor::Subkey { base, salt } => {
+                let mut data = Vec::new();
+                base.encode(&mut data);
+                data.extend_from_slice(salt);
+                data
+            }
+        }
+    }
+
+    fn parse(id: u8, data: &[u8]) -> Result<Self> {
+        match id {
+            KDF_ARGON2ID | KDF_ARGON2ID_KEYFILE => {
+                if data.len() != ARGON2ID_DESCRIPTOR_LENGTH {
+                    return Err(CrateError::InvalidFormat(
+                        "Invalid Argon2id descriptor".to_string(),
+                    ));
+                }
+                let word = |i: usize| u32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+                let params = Argon2Params {
+                    memory_kb: word(0),
+                    time_cost: word(1),
+                    parallelism: word(2),
+                };
+                params.validate()?;
+
+                let mut salt = [0u8; SALT_LENGTH];
+                salt.copy_from_slice(&data[12..]);
+                if id == KDF_ARGON2ID {
+                    Ok(KdfDescriptor::Argon2id { params, salt })
+                } else {
+                    Ok(KdfDescriptor::Argon2idKeyfile { params, salt })
+                }
+            }
+            KDF_KEY_SLOTS if data.is_empty() => Ok(KdfDescriptor::KeySlots),
+            KDF_X25519 => {
+                let ephemeral = data.try_into().map_err(|_| {
+                    CrateError::InvalidFormat("Invalid X25519 descriptor".to_string())
+                })?;
+                Ok(KdfDescriptor::X25519 { ephemeral })
+            }
+            KDF_HKDF_SHA256 => {
+                let salt = data.try_into().map_err(|_| {
+                    CrateError::InvalidFormat("Invalid HKDF descriptor".to_string())
+                })?;
+                Ok(KdfDescriptor::HkdfSha256 { salt })
+            }
+            KDF_SUBKEY => {
+                // Check the base type before decoding it: key slots are not
+                // authenticated, and nested subkeys would recurse without end
+                if !matches!(data.first(), Some(&(KDF_ARGON2ID | KDF_ARGON2ID_KEYFILE))) {
+                    return Err(CrateError::InvalidFormat(
+                        "Subkeys must be derived from an Argon2id key".to_string(),
+                    ));
+                }
+                let mut reader = data;
+                let base = Self::decode(&mut reader)?;
+                let salt = reader.try_into().map_err(|_| {
+                    CrateError::InvalidFormat("Invalid subkey descriptor".to_string())
+                })?;
+                Ok(KdfDescriptor::Subkey {
+                    base: Box::new(base),
+                    salt,
+                })
+            }
+            _ => Err(CrateError::InvalidFormat(format!(
+                "Unsupported key derivation function: {}",
+                id
+            ))),
+        }
+    }
+}
+
+/// Optional typed record carried in a version 2 header
+///
+/// Readers skip record types they do not understand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderExtension {
+    pub kind: u16,
+    pub data: Vec<u8>,
+}
+
 /// File header structure
 #[derive(Debug, Clone)]
 pub struct FileHeader {
     pub version: u8,
-    pub algorithm: u8,
-    pub salt: [u8; SALT_LENGTH],
-    pub nonce: [u8; NONCE_LENGTH],
+    pub algorithm: CipherAlgorithm,
+    pub flags: u16,
+    /// Plaintext bytes per encrypted chunk (0 = single AEAD message)
+    pub chunk_size: u32,
+    pub kdf: KdfDescriptor,
+    /// Base nonce, `algorithm.nonce_length()` bytes
+    pub nonce: Vec<u8>,
+    pub extensions: Vec<HeaderExtension>,
     pub metadata_length: u32,
 }
 
 impl FileHeader {
-    pub fn new(salt: [u8; SALT_LENGTH], nonce: [u8; NONCE_LENGTH], metadata_length: u32) -> Self {
+    pub fn new(
+        algorithm: CipherAlgorithm,
+        kdf: KdfDescriptor,
+        nonce: Vec<u8>,
+        metadata_length: u32,
+    ) -> Self {
+        Self::chunked(algorithm, kdf, nonce, 0, metadata_length)
+    }
+
+    /// Header for a payload split into `chunk_size`-byte chunks
+    pub fn chunked(
+        algorithm: CipherAlgorithm,
+        kdf: KdfDescriptor,
+        nonce: Vec<u8>,
+        chunk_size: u32,
+        metadata_length: u32,
+    ) -> Self {
         Self {
             version: VERSION,
-            algorithm: ALGORITHM_AES256_GCM,
-            salt,
+            algorithm,
+            flags: 0,
+            chunk_size,
+            kdf,
             nonce,
+            extensions: Vec::new(),
             metadata_length,
         }
     }
+
+    /// Whether the payload is split into independently sealed chunks
+    pub fn is_chunked(&self) -> bool {
+        self.chunk_size > 0
+    }
+
+    /// Whether the metadata block is encrypted
+    pub fn has_encrypted_metadata(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED_METADATA != 0
+    }
+
+    /// Whether the payload is an archive rather than a single file
+    pub fn is_archive(&self) -> bool {
+        self.flags & FLAG_ARCHIVE != 0
+    }
+
+    /// Whether the chunks carry a compressed stream rather than the plaintext itself
+    pub fn is_compressed_stream(&self) -> bool {
+        self.flags & FLAG_COMPRESSED_STREAM != 0
+    }
+
+    /// Size of the metadata block on disk for `metadata_len` bytes of metadata
+    pub fn metadata_block_length(&self, metadata_len: usize) -> usize {
+        if self.has_encrypted_metadata() {
+            // Random nonce in front, authentication tag at the end
+            self.algorithm.nonce_length() + metadata_len + TAG_LENGTH
+        } else {
+            metadata_len
+        }
+    }
+
+    /// Size of the header on disk, not counting the metadata block
+    pub fn encoded_length(&self) -> usize {
+        if self.version == VERSION_1 {
+            HEADER_SIZE
+        } else {
+            self.to_bytes().len()
+        }
+    }
+
+    /// Find the extension record of type `kind`
+    pub fn extension(&self, kind: u16) -> Option<&HeaderExtension> {
+        self.extensions.iter().find(|e| e.kind == kind)
+    }
+
+    /// Replace (or add) the extension record of type `kind`
+    ///
+    /// A replaced record keeps its position in the header.
+    pub fn set_extension(&mut self, kind: u16, data: Vec<u8>) {
+        match self.extensions.iter_mut().find(|e| e.kind == kind) {
+            Some(extension) => extension.data = data,
+            None => self.extensions.push(HeaderExtension { kind, data }),
+        }
+    }
+
+    /// Serialize the header in the current (version 2) layout
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.serialize(true)
+    }
+
+    /// Serialized header without the key slots
+    ///
+    /// This is what the metadata and payload authenticate, so rewriting the
+    /// key slots leaves every existing tag valid.
+    pub fn authenticated_bytes(&self) -> Vec<u8> {
+        self.serialize(false)
+    }
+
+    fn serialize(&self, include_key_slots: bool) -> Vec<u8> {
+        let extensions: Vec<&HeaderExtension> = self
+            .extensions
+            .iter()
+            .filter(|e| include_key_slots || e.kind != EXT_KEY_SLOTS)
+            .collect();
+
+        let mut bytes = Vec::new();
+
+        bytes.extend_from_slice(MAGIC_BYTES);
+        bytes.push(VERSION_2);
+        bytes.push(self.algorithm.id());
+        bytes.extend_from_slice(&self.flags.to_le_bytes());
+        bytes.extend_from_slice(&self.chunk_size.to_le_bytes());
+
+        // KDF descriptor: id, length, parameters
+        self.kdf.encode(&mut bytes);
+
+        bytes.push(self.nonce.len() as u8);
+        bytes.extend_from_slice(&self.nonce);
+
+        // Extension records: type, length, data
+        bytes.extend_from_slice(&(extensions.len() as u16).to_le_bytes());
+        for extension in extensions {
+            bytes.extend_from_slice(&extension.kind.to_le_bytes());
+            bytes.extend_from_slice(&(extension.data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&extension.data);
+        }
+
+        bytes.extend_from_slice(&self.metadata_length.to_le_bytes());
+
+        bytes
+    }
+
+    /// Bytes bound into every AEAD tag as associated data
+    ///
+    /// Version 2 authenticates the serialized header (minus key slots)
+    /// followed by the metadata block. Version 1 files predate this and use
+    /// none.
+    pub fn associated_data(&self, metadata_bytes: &[u8]) -> Vec<u8> {
+        if self.version == VERSION_1 {
+            return Vec::new();
+        }
+        let mut aad = self.authenticated_bytes();
+        aad.extend_from_slice(metadata_bytes);
+        aad
+    }
+
+    /// Write the serialized header
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Parse a version 1 or version 2 header
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        // Read and verify magic bytes
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC_BYTES {
+            return Err(CrateError::InvalidFormat(
+                "Not a valid CryptoCrate file".to_string(),
+            ));
+        }
+
+        let version = read_u8(reader)?;
+        match version {
+            VERSION_1 => Self::read_v1(reader),
+            VERSION_2 => Self::read_v2(reader),
+            _ => Err(CrateError::UnsupportedVersion(version)),
+        }
+    }
+
+    fn read_v1<R: Read>(reader: &mut R) -> Result<Self> {
+        let algorithm = read_u8(reader)?;
+        let chunk_size = match algorithm {
+            ALGORITHM_AES256_GCM => 0,
+            ALGORITHM_AES256_GCM_STREAM => V1_CHUNK_SIZE,
+            _ => {
+                return Err(CrateError::InvalidFormat(
+                    "Unsupported encryption algorithm".to_string(),
+                ))
+            }
+        };
+
+        let mut salt = [0u8; SALT_LENGTH];
+        reader.read_exact(&mut salt)?;
+
+        let nonce = read_vec(reader, NONCE_LENGTH)?;
+
+        let metadata_length = read_u32(reader)?;
+
+        Ok(Self {
+            version: VERSION_1,
+            algorithm: CipherAlgorithm::Aes256Gcm,
+            flags: 0,
+            chunk_size,
+            kdf: KdfDescriptor::Argon2id {
+                params: Argon2Params::V1,
+                salt,
+            },
+            nonce,
+            extensions: Vec::new(),
+            metadata_length,
+        })
+    }
+
+    fn read_v2<R: Read>(reader: &mut R) -> Result<Self> {
+        let algorithm = CipherAlgorithm::from_id(read_u8(reader)?)?;
+
+        let flags = read_u16(reader)?;
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(CrateError::InvalidFormat(format!(
+                "Unsupported header flags: {:#06x}",
+                flags
+            )));
+        }
+
+        let chunk_size = read_u32(reader)?;
+        if chunk_size > MAX_CHUNK_SIZE {
+            return Err(CrateError::InvalidFormat(format!(
+                "Chunk size too large: {} bytes",
+                chunk_size
+            )));
+        }
+
+        let kdf = KdfDescriptor::decode(reader)?;
+
+        let nonce_len = read_u8(reader)? as usize;
+        if nonce_len != algorithm.nonce_length() {
+            return Err(CrateError::InvalidFormat(
+                "Invalid nonce length".to_string(),
+            ));
+        }
+        let nonce = read_vec(reader, nonce_len)?;
+
+        let extension_count = read_u16(reader)?;
+        let mut extensions = Vec::with_capacity(extension_count as usize);
+        for _ in 0..extension_count {
+            let kind = read_u16(reader)?;
+            let len = read_u32(reader)? as usize;
+            let data = read_vec(reader, len)?;
+            extensions.push(HeaderExtension { kind, data });
+        }
+
+        let metadata_length = read_u32(reader)?;
+
+        Ok(Self {
+            version: VERSION_2,
+            algorithm,
+            flags,
+            chunk_size,
+            kdf,
+            nonce,
+            extensions,
+            metadata_length,
+        })
+    }
+}
+
+/// Read a file header followed by its raw metadata block
+///
+/// The block may be encrypted (see `FileHeader::has_encrypted_metadata`);
+/// it is also part of the associated data the payload was sealed with.
+pub fn read_header<R: Read>(reader: &mut R) -> Result<(FileHeader, Vec<u8>)> {
+    let header = FileHeader::read_from(reader)?;
+    let metadata_block = read_vec(reader, header.metadata_length as usize)?;
+    Ok((header, metadata_block))
+}
+
+pub(crate) fn read_u8<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(crate) fn read_u16<R: Read>(reader: &mut R) -> Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Read exactly `len` bytes without trusting `len` for the up-front allocation
+pub(crate) fn read_vec<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(CrateError::InvalidFormat(
+            "Unexpected end of header".to_string(),
+        ));
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v1_header_bytes(algorithm: u8) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC_BYTES);
+        bytes.push(VERSION_1);
+        bytes.push(algorithm);
+        bytes.extend_from_slice(&[7u8; SALT_LENGTH]);
+        bytes.extend_from_slice(&[9u8; NONCE_LENGTH]);
+        bytes.extend_from_slice(&42u32.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_v2_header_roundtrip() {
+        let kdf = KdfDescriptor::Argon2id {
+            params: Argon2Params {
+                memory_kb: 131072,
+                time_cost: 4,
+                parallelism: 2,
+            },
+            salt: [1u8; SALT_LENGTH],
+        };
+        let mut header = FileHeader::chunked(
+            CipherAlgorithm::XChaCha20Poly1305,
+            kdf.clone(),
+            vec![2u8; XNONCE_LENGTH],
+            4096,
+            17,
+        );
+        header.extensions.push(HeaderExtension {
+            kind: 0x7f00,
+            data: vec![1, 2, 3],
+        });
+
+        let bytes = header.to_bytes();
+        let parsed = FileHeader::read_from(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(parsed.version, VERSION_2);
+        assert_eq!(parsed.algorithm, CipherAlgorithm::XChaCha20Poly1305);
+        assert_eq!(parsed.chunk_size, 4096);
+        assert_eq!(parsed.kdf, kdf);
+        assert_eq!(parsed.nonce, vec![2u8; XNONCE_LENGTH]);
+        assert_eq!(parsed.extensions, header.extensions);
+        assert_eq!(parsed.metadata_length, 17);
+    }
+
+    #[test]
+    fn test_subkey_descriptor() {
+        let kdf = KdfDescriptor::Subkey {
+            base: Box::new(KdfDescriptor::Argon2idKeyfile {
+                params: Argon2Params::V1,
+                salt: [4u8; SALT_LENGTH],
+            }),
+            salt: [5u8; SALT_LENGTH],
+        };
+        let mut bytes = Vec::new();
+        kdf.encode(&mut bytes);
+        assert_eq!(KdfDescriptor::decode(&mut bytes.as_slice()).unwrap(), kdf);
+
+        // Only Argon2id keys are worth sharing
+        let nested = KdfDescriptor::Subkey {
+            base: Box::new(KdfDescriptor::HkdfSha256 {
+                salt: [4u8; SALT_LENGTH],
+            }),
+            salt: [5u8; SALT_LENGTH],
+        };
+        let mut bytes = Vec::new();
+        nested.encode(&mut bytes);
+        assert!(KdfDescriptor::decode(&mut bytes.as_slice()).is_err());
+
+        // Subkeys of subkeys are rejected without decoding the whole chain
+        let mut bytes = Vec::new();
+        kdf.encode(&mut bytes);
+        for _ in 0..1000 {
+            let mut data = std::mem::take(&mut bytes);
+            data.extend_from_slice(&[5u8; SALT_LENGTH]);
+            bytes.push(KDF_SUBKEY);
+            bytes.extend_from_slice(&(data.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&data);
+        }
+        let result = KdfDescriptor::decode(&mut bytes.as_slice());
+        assert!(matches!(result, Err(CrateError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_key_slots_not_authenticated() {
+        let mut header = FileHeader::new(
+            CipherAlgorithm::Aes256Gcm,
+            KdfDescriptor::KeySlots,
+            vec![3u8; NONCE_LENGTH],
+            0,
+        );
+        let before = header.authenticated_bytes();
+
+        header.set_extension(EXT_KEY_SLOTS, vec![1, 2, 3]);
+        assert_eq!(header.authenticated_bytes(), before);
+        assert_ne!(header.to_bytes(), before);
+
+        let parsed = FileHeader::read_from(&mut header.to_bytes().as_slice()).unwrap();
+        assert_eq!(parsed.kdf, KdfDescriptor::KeySlots);
+        assert_eq!(parsed.extension(EXT_KEY_SLOTS).unwrap().data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_v1_header_compatibility() {
+        let bytes = v1_header_bytes(ALGORITHM_AES256_GCM);
+        assert_eq!(bytes.len(), HEADER_SIZE);
+        let header = FileHeader::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(header.version, VERSION_1);
+        assert!(!header.is_chunked());
+        assert_eq!(
+            header.kdf,
+            KdfDescriptor::Argon2id {
+                params: Argon2Params::V1,
+                salt: [7u8; SALT_LENGTH]
+            }
+        );
+        assert_eq!(header.metadata_length, 42);
+
+        let bytes = v1_header_bytes(ALGORITHM_AES256_GCM_STREAM);
+        let header = FileHeader::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(header.chunk_size, V1_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut bytes = v1_header_bytes(ALGORITHM_AES256_GCM);
+        bytes[4] = 99;
+        let result = FileHeader::read_from(&mut bytes.as_slice());
+        assert!(matches!(result, Err(CrateError::UnsupportedVersion(99))));
+    }
 }
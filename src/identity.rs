@@ -0,0 +1,224 @@
+//! Public-key recipients using X25519
+//!
+//! An identity is an X25519 secret key kept in a file. Its public half, the
+//! recipient, can be handed out freely: anyone holding it can encrypt files
+//! that only the identity can decrypt, without sharing a password.
+//!
+//! The file key is wrapped for a recipient with an ephemeral X25519 key
+//! exchange. The key-encryption key is HKDF-SHA256 of the shared secret,
+//! salted with both public keys.
+
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret, StaticSecret};
+use zeroize::Zeroize;
+
+use crate::error::{CrateError, Result};
+use crate::secret::{SecretKey, Zeroizing};
+
+/// Prefix of a recipient (public key) string
+pub const RECIPIENT_PREFIX: &str = "crate-pk-";
+
+/// Prefix of an identity (secret key) string
+pub const IDENTITY_PREFIX: &str = "CRATE-SK-";
+
+/// HKDF info label for key slot wrapping keys
+const KEK_INFO: &[u8] = b"cryptocrate x25519 key slot v1";
+
+/// A public key that files can be encrypted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recipient([u8; 32]);
+
+impl Recipient {
+    /// Raw X25519 public key
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Derive a fresh key-encryption key for this recipient
+    ///
+    /// Returns the ephemeral public key to store in the key slot and the
+    /// derived key.
+    pub(crate) fn derive_kek(&self) -> Result<([u8; 32], SecretKey)> {
+        let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral).to_bytes();
+        let shared = ephemeral.diffie_hellman(&PublicKey::from(self.0));
+        let kek = hkdf_kek(shared, &ephemeral_public, &self.0)?;
+        Ok((ephemeral_public, kek))
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", RECIPIENT_PREFIX, hex::encode(self.0))
+    }
+}
+
+impl FromStr for Recipient {
+    type Err = CrateError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let key = s
+            .trim()
+            .strip_prefix(RECIPIENT_PREFIX)
+            .and_then(decode_key)
+            .ok_or_else(|| {
+                CrateError::InvalidFormat(format!(
+                    "Invalid recipient '{}' (expected {}<64 hex digits>)",
+                    s, RECIPIENT_PREFIX
+                ))
+            })?;
+        Ok(Recipient(key))
+    }
+}
+
+/// An X25519 secret key that can decrypt files sent to its recipient
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+impl Identity {
+    /// Generate a new random identity
+    pub fn generate() -> Self {
+        Identity(StaticSecret::random_from_rng(OsRng))
+    }
+
+    /// The public recipient for this identity
+    pub fn recipient(&self) -> Recipient {
+        Recipient(PublicKey::from(&self.0).to_bytes())
+    }
+
+    /// Re-derive the key-encryption key from a key slot's ephemeral public key
+    pub(crate) fn derive_kek(&self, ephemeral_public: &[u8; 32]) -> Result<SecretKey> {
+        let shared = self.0.diffie_hellman(&PublicKey::from(*ephemeral_public));
+        hkdf_kek(shared, ephemeral_public, self.recipient().as_bytes())
+    }
+
+    /// Secret key string as stored in identity files
+    fn encode(&self) -> Zeroizing<String> {
+        let key = Zeroizing::new(self.0.to_bytes());
+        let hex = Zeroizing::new(hex::encode_upper(key));
+        Zeroizing::new(format!("{}{}", IDENTITY_PREFIX, *hex))
+    }
+}
+
+impl FromStr for Identity {
+    type Err = CrateError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut key = s
+            .trim()
+            .strip_prefix(IDENTITY_PREFIX)
+            .and_then(decode_key)
+            .ok_or_else(|| CrateError::InvalidFormat("Invalid identity".to_string()))?;
+        let secret = StaticSecret::from(key);
+        key.zeroize();
+        Ok(Identity(secret))
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Identity").field(&self.recipient().to_string()).finish()
+    }
+}
+
+fn decode_key(hex_str: &str) -> Option<[u8; 32]> {
+    let bytes = Zeroizing::new(hex::decode(hex_str).ok()?);
+    bytes.as_slice().try_into().ok()
+}
+
+fn hkdf_kek(shared: SharedSecret, ephemeral: &[u8; 32], recipient: &[u8; 32]) -> Result<SecretKey> {
+    // Low-order public keys give an all-zero shared secret
+    if !shared.was_contributory() {
+        return Err(CrateError::KeyDerivation(
+            "invalid X25519 public key".to_string(),
+        ));
+    }
+
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral);
+    salt[32..].copy_from_slice(recipient);
+
+    let mut kek = SecretKey::zeroed();
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(KEK_INFO, &mut *kek)
+        .map_err(|e| CrateError::KeyDerivation(e.to_string()))?;
+    Ok(kek)
+}
+
+/// Generate a new identity file, returning its recipient
+pub fn generate_identity<P: AsRef<Path>>(path: P) -> Result<Recipient> {
+    let identity = Identity::generate();
+    let recipient = identity.recipient();
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path.as_ref())?;
+    writeln!(file, "# CryptoCrate identity - keep this file secret")?;
+    writeln!(file, "# recipient: {}", recipient)?;
+    writeln!(file, "{}", *identity.encode())?;
+    file.sync_all()?;
+
+    Ok(recipient)
+}
+
+/// Read an identity file (blank lines and `#` comments are ignored)
+pub fn read_identity<P: AsRef<Path>>(path: P) -> Result<Identity> {
+    let path = path.as_ref();
+    let content = Zeroizing::new(
+        fs::read_to_string(path)
+            .map_err(|e| CrateError::FileNotFound(format!("Identity file: {}", e)))?,
+    );
+
+    content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or_else(|| CrateError::InvalidFormat("Identity file is empty".to_string()))?
+        .parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_identity_file_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("me.identity");
+
+        let recipient = generate_identity(&path).unwrap();
+        let identity = read_identity(&path).unwrap();
+        assert_eq!(identity.recipient(), recipient);
+
+        let parsed: Recipient = recipient.to_string().parse().unwrap();
+        assert_eq!(parsed, recipient);
+        assert!("crate-pk-1234".parse::<Recipient>().is_err());
+    }
+
+    #[test]
+    fn test_kek_agreement() {
+        let identity = Identity::generate();
+        let (ephemeral, kek) = identity.recipient().derive_kek().unwrap();
+        assert_eq!(*identity.derive_kek(&ephemeral).unwrap(), *kek);
+
+        let other = Identity::generate();
+        assert_ne!(*other.derive_kek(&ephemeral).unwrap(), *kek);
+
+        // The all-zero point is rejected
+        assert!(identity.derive_kek(&[0u8; 32]).is_err());
+    }
+}
@@ -1,10 +1,16 @@
 //! File inspection utilities - view encrypted file metadata without decrypting
+//!
+//! Public header fields are always available. When the metadata block is
+//! encrypted, the filename, size and timestamps are only shown if a password
+//! (or key file secret) is supplied.
 
 use std::fs::File;
-use std::io::Read;
 use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::crypto::cipher::Cipher;
+use crate::crypto::encryption::open_metadata;
+use crate::crypto::keyslot::{read_key_slots, unlock, Credentials};
 use crate::error::{CrateError, Result};
 use crate::format::*;
 use crate::metadata::FileMetadata;
@@ -14,7 +20,15 @@ use crate::metadata::FileMetadata;
 pub struct FileInfo {
     pub version: u8,
     pub algorithm: String,
-    pub metadata: FileMetadata,
+    pub kdf: String,
+    /// What each key slot is unlocked with (empty for files without slots)
+    pub key_slots: Vec<String>,
+    pub chunk_size: u32,
+    pub metadata_encrypted: bool,
+    /// Whether the file is an archive of several files
+    pub is_archive: bool,
+    /// Decoded metadata, or `None` if it is encrypted and no password was given
+    pub metadata: Option<FileMetadata>,
     pub encrypted_size: u64,
 }
 
@@ -25,11 +39,55 @@ impl FileInfo {
         
         output.push_str(&format!("📦 File Format: CryptoCrate v{}\n", self.version));
         output.push_str(&format!("🔐 Algorithm: {}\n", self.algorithm));
-        output.push_str(&format!("📄 Original Filename: {}\n", self.metadata.filename));
-        output.push_str(&format!("📏 Original Size: {}\n", format_size(self.metadata.original_size)));
+        output.push_str(&format!("🔑 Key Derivation: {}\n", self.kdf));
+        if !self.key_slots.is_empty() {
+            output.push_str(&format!(
+                "🗝️  Key Slots: {} ({})\n",
+                self.key_slots.len(),
+                self.key_slots.join(", ")
+            ));
+        }
+        if self.chunk_size > 0 {
+            output.push_str(&format!("🧩 Chunk Size: {}\n", format_size(self.chunk_size as u64)));
+        }
+        if self.is_archive {
+            output.push_str("🗃️  Type: Archive (see `cryptocrate list`)\n");
+        }
+        let metadata = match &self.metadata {
+            Some(metadata) => metadata,
+            None => {
+                output.push_str(&format!("📦 Encrypted Size: {}\n", format_size(self.encrypted_size)));
+                output.push_str("🔒 Metadata: encrypted (use --password or --keyfile to reveal)\n");
+                return output;
+            }
+        };
+
+        if self.metadata_encrypted {
+            output.push_str("🔒 Metadata: encrypted\n");
+        }
+        output.push_str(&format!("📄 Original Filename: {}\n", metadata.filename));
+        if let Some(ref relative_path) = metadata.relative_path {
+            output.push_str(&format!("📁 Original Path: {}\n", relative_path));
+        }
+        if let Some(ref target) = metadata.attributes.symlink_target {
+            output.push_str(&format!("🔗 Symlink: → {}\n", target));
+        }
+        if let Some(mode) = metadata.attributes.mode {
+            output.push_str(&format!("🔐 Permissions: {:04o}\n", mode));
+        }
+        if let Some((uid, gid)) = metadata.attributes.owner {
+            output.push_str(&format!("👤 Owner: {}:{}\n", uid, gid));
+        }
+        if !metadata.attributes.xattrs.is_empty() {
+            output.push_str(&format!(
+                "🏷️  Extended Attributes: {}\n",
+                metadata.attributes.xattrs.len()
+            ));
+        }
+        output.push_str(&format!("📏 Original Size: {}\n", format_size(metadata.original_size)));
         output.push_str(&format!("📦 Encrypted Size: {}\n", format_size(self.encrypted_size)));
         
-        if let Some(modified) = self.metadata.modified_time {
+        if let Some(modified) = metadata.modified_time {
             let datetime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(modified);
             if let Ok(duration) = datetime.duration_since(UNIX_EPOCH) {
                 output.push_str(&format!("📅 Modified: {} (Unix: {})\n", 
@@ -37,11 +95,11 @@ impl FileInfo {
             }
         }
         
-        output.push_str(&format!("🗜️  Compressed: {}\n", 
-            if self.metadata.is_compressed { "Yes" } else { "No" }));
+        let compressed = if metadata.is_compressed() { metadata.compression.name() } else { "No" };
+        output.push_str(&format!("🗜️  Compressed: {}\n", compressed));
         
-        if self.metadata.is_compressed {
-            let ratio = ((self.encrypted_size as f64 / self.metadata.original_size as f64) * 100.0);
+        if metadata.is_compressed() {
+            let ratio = (self.encrypted_size as f64 / metadata.original_size as f64) * 100.0;
             output.push_str(&format!("📊 Compression Ratio: {:.1}% of original\n", ratio));
         }
         
@@ -50,9 +108,28 @@ impl FileInfo {
 }
 
 /// Inspect an encrypted file without decrypting it
+///
+/// Encrypted metadata is left hidden; see `inspect_file_with_credentials`.
 pub fn inspect_file<P: AsRef<Path>>(path: P) -> Result<FileInfo> {
-    let path = path.as_ref();
-    
+    inspect(path.as_ref(), None)
+}
+
+/// Inspect an encrypted file, decrypting its metadata block if necessary
+///
+/// Only the metadata is decrypted; the payload is never touched.
+pub fn inspect_file_with_password<P: AsRef<Path>>(path: P, password: &str) -> Result<FileInfo> {
+    inspect(path.as_ref(), Some(&Credentials::password(password)))
+}
+
+/// Inspect an encrypted file using a password, a key file, or both
+pub fn inspect_file_with_credentials<P: AsRef<Path>>(
+    path: P,
+    credentials: &Credentials,
+) -> Result<FileInfo> {
+    inspect(path.as_ref(), Some(credentials))
+}
+
+fn inspect(path: &Path, credentials: Option<&Credentials>) -> Result<FileInfo> {
     // Get file size
     let encrypted_size = std::fs::metadata(path)?.len();
     
@@ -60,47 +137,30 @@ pub fn inspect_file<P: AsRef<Path>>(path: P) -> Result<FileInfo> {
     let mut input_file = File::open(path)
         .map_err(|e| CrateError::FileNotFound(format!("{}: {}", path.display(), e)))?;
 
-    // Read and verify magic bytes
-    let mut magic = [0u8; 4];
-    input_file.read_exact(&mut magic)?;
-    if &magic != MAGIC_BYTES {
-        return Err(CrateError::InvalidFormat(
-            "Not a valid CryptoCrate file".to_string(),
-        ));
-    }
+    // Read header and metadata block
+    let (header, metadata_block) = read_header(&mut input_file)?;
 
-    // Read version
-    let mut version = [0u8; 1];
-    input_file.read_exact(&mut version)?;
-    if version[0] != VERSION {
-        return Err(CrateError::UnsupportedVersion(version[0]));
-    }
-
-    // Read algorithm
-    let mut algorithm = [0u8; 1];
-    input_file.read_exact(&mut algorithm)?;
-    let algorithm_name = match algorithm[0] {
-        ALGORITHM_AES256_GCM => "AES-256-GCM",
-        _ => "Unknown",
+    let metadata = match credentials {
+        _ if !header.has_encrypted_metadata() => Some(FileMetadata::from_bytes(&metadata_block)?),
+        Some(credentials) => {
+            let key = unlock(&header, credentials)?;
+            let cipher = Cipher::new(header.algorithm, &key)?;
+            Some(open_metadata(&cipher, &header, &metadata_block)?)
+        }
+        None => None,
     };
 
-    // Skip salt and nonce (we don't need them for inspection)
-    let mut skip_buffer = vec![0u8; SALT_LENGTH + NONCE_LENGTH];
-    input_file.read_exact(&mut skip_buffer)?;
-
-    // Read metadata length
-    let mut metadata_len_bytes = [0u8; 4];
-    input_file.read_exact(&mut metadata_len_bytes)?;
-    let metadata_len = u32::from_le_bytes(metadata_len_bytes) as usize;
-
-    // Read metadata
-    let mut metadata_bytes = vec![0u8; metadata_len];
-    input_file.read_exact(&mut metadata_bytes)?;
-    let metadata = FileMetadata::from_bytes(&metadata_bytes)?;
-
     Ok(FileInfo {
-        version: version[0],
-        algorithm: algorithm_name.to_string(),
+        version: header.version,
+        algorithm: header.algorithm.name().to_string(),
+        kdf: header.kdf.description(),
+        key_slots: read_key_slots(&header)?
+            .iter()
+            .map(|slot| slot.kind.to_string())
+            .collect(),
+        chunk_size: header.chunk_size,
+        metadata_encrypted: header.has_encrypted_metadata(),
+        is_archive: header.is_archive(),
         metadata,
         encrypted_size,
     })
@@ -132,11 +192,7 @@ fn format_timestamp(timestamp: u64) -> String {
         .unwrap_or(Duration::from_secs(0))
         .as_secs();
     
-    let diff = if now > timestamp {
-        now - timestamp
-    } else {
-        timestamp - now
-    };
+    let diff = now.abs_diff(timestamp);
     
     const MINUTE: u64 = 60;
     const HOUR: u64 = MINUTE * 60;
@@ -175,4 +231,30 @@ mod tests {
         assert_eq!(format_size(1024 * 1024), "1.00 MB");
         assert_eq!(format_size(1024 * 1024 * 1024), "1.00 GB");
     }
+
+    #[test]
+    fn test_encrypted_metadata_needs_password() {
+        use crate::crypto::encryption::{encrypt_file_with_options, test_options};
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("report.txt");
+        let encrypted_path = temp_dir.path().join("report.txt.crat");
+        std::fs::write(&input_path, b"quarterly numbers").unwrap();
+        let credentials = Credentials::password("password");
+        encrypt_file_with_options(&input_path, &encrypted_path, &credentials, &test_options())
+            .unwrap();
+
+        let info = inspect_file(&encrypted_path).unwrap();
+        assert!(info.metadata_encrypted);
+        assert!(info.metadata.is_none());
+        assert!(!info.display().contains("report.txt"));
+        assert_eq!(info.key_slots, vec!["password".to_string()]);
+
+        let info = inspect_file_with_password(&encrypted_path, "password").unwrap();
+        assert_eq!(info.metadata.unwrap().filename, "report.txt");
+
+        let result = inspect_file_with_password(&encrypted_path, "wrong");
+        assert!(matches!(result, Err(CrateError::InvalidPassword)));
+    }
 }
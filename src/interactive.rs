@@ -1,14 +1,17 @@
 //! Interactive prompts and confirmations
+//!
+//! Prompts go to stderr: stdout may be carrying decrypted data.
 
 use std::io::{self, Write};
 
-use crate::error::Result;
+use cryptocrate::secret::SecretString;
+use cryptocrate::Result;
 
 /// Prompt for yes/no confirmation
 pub fn confirm(prompt: &str, default_yes: bool) -> Result<bool> {
     let default_str = if default_yes { "Y/n" } else { "y/N" };
-    print!("{} [{}]: ", prompt, default_str);
-    io::stdout().flush()?;
+    eprint!("{} [{}]: ", prompt, default_str);
+    io::stderr().flush()?;
 
     let mut input = String::new();
     io::stdin().read_line(&mut input)?;
@@ -21,43 +24,33 @@ pub fn confirm(prompt: &str, default_yes: bool) -> Result<bool> {
     Ok(input == "y" || input == "yes")
 }
 
-/// Prompt for text input
-pub fn prompt(message: &str) -> Result<String> {
-    print!("{}: ", message);
-    io::stdout().flush()?;
-
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    Ok(input.trim().to_string())
-}
-
 /// Prompt for password with confirmation
-pub fn prompt_password_with_confirm(prompt: &str) -> Result<String> {
+pub fn prompt_password_with_confirm(prompt: &str) -> Result<SecretString> {
     loop {
-        print!("{}: ", prompt);
-        io::stdout().flush()?;
-        let pass1 = rpassword::read_password()?;
+        eprint!("{}: ", prompt);
+        io::stderr().flush()?;
+        let pass1 = SecretString::new(rpassword::read_password()?);
 
-        print!("Confirm password: ");
-        io::stdout().flush()?;
-        let pass2 = rpassword::read_password()?;
+        eprint!("Confirm password: ");
+        io::stderr().flush()?;
+        let pass2 = SecretString::new(rpassword::read_password()?);
 
-        if pass1 == pass2 {
+        if *pass1 == *pass2 {
             if pass1.is_empty() {
-                println!("⚠️  Password cannot be empty. Please try again.");
+                eprintln!("⚠️  Password cannot be empty. Please try again.");
                 continue;
             }
             return Ok(pass1);
         } else {
-            println!("⚠️  Passwords do not match. Please try again.");
+            eprintln!("⚠️  Passwords do not match. Please try again.");
         }
     }
 }
 
 /// Prompt for password
-pub fn prompt_password(prompt: &str) -> Result<String> {
-    print!("{}: ", prompt);
-    io::stdout().flush()?;
-    let password = rpassword::read_password()?;
+pub fn prompt_password(prompt: &str) -> Result<SecretString> {
+    eprint!("{}: ", prompt);
+    io::stderr().flush()?;
+    let password = SecretString::new(rpassword::read_password()?);
     Ok(password)
 }
@@ -8,8 +8,10 @@ use sha2::{Digest, Sha256};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
+use zeroize::Zeroize;
 
 use crate::error::{CrateError, Result};
+use crate::secret::{SecretKey, Zeroizing};
 
 /// Default key file size in bytes (4 KB)
 pub const DEFAULT_KEYFILE_SIZE: usize = 4096;
@@ -41,7 +43,7 @@ pub fn generate_keyfile<P: AsRef<Path>>(path: P, size: Option<usize>) -> Result<
 }
 
 /// Read and hash a key file to produce a 32-byte key component
-pub fn read_keyfile<P: AsRef<Path>>(path: P) -> Result<[u8; 32]> {
+pub fn read_keyfile<P: AsRef<Path>>(path: P) -> Result<SecretKey> {
     let path = path.as_ref();
     
     // Check file size
@@ -62,27 +64,33 @@ pub fn read_keyfile<P: AsRef<Path>>(path: P) -> Result<[u8; 32]> {
     let mut file = File::open(path)
         .map_err(|e| CrateError::FileNotFound(format!("Key file: {}", e)))?;
     
-    let mut key_data = Vec::new();
+    let mut key_data = Zeroizing::new(Vec::new());
     file.read_to_end(&mut key_data)?;
     
     // Hash the key file content to get a consistent 32-byte key
     let mut hasher = Sha256::new();
     hasher.update(&key_data);
-    let hash = hasher.finalize();
+    let mut hash = hasher.finalize();
     
-    let mut result = [0u8; 32];
+    let mut result = SecretKey::zeroed();
     result.copy_from_slice(&hash);
+    hash.as_mut_slice().zeroize();
     Ok(result)
 }
 
 /// Combine password and key file into a single key material
-/// Uses HKDF-like approach: hash(password || keyfile_hash)
-pub fn combine_password_and_keyfile(password: &str, keyfile_hash: &[u8; 32]) -> Vec<u8> {
+/// Uses hash(password || keyfile_hash)
+///
+/// Legacy construction, only used to open files written before key files
+/// became the Argon2 secret (see `crypto::key_derivation`).
+pub fn combine_password_and_keyfile(password: &str, keyfile_hash: &[u8; 32]) -> Zeroizing<Vec<u8>> {
     let mut hasher = Sha256::new();
     hasher.update(password.as_bytes());
     hasher.update(keyfile_hash);
-    let combined = hasher.finalize();
-    combined.to_vec()
+    let mut combined = hasher.finalize();
+    let result = Zeroizing::new(combined.to_vec());
+    combined.as_mut_slice().zeroize();
+    result
 }
 
 #[cfg(test)]
@@ -105,7 +113,7 @@ mod tests {
 
         // Reading same file should give same hash
         let key2 = read_keyfile(&keyfile_path).unwrap();
-        assert_eq!(key1, key2);
+        assert_eq!(*key1, *key2);
     }
 
     #[test]
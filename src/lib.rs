@@ -0,0 +1,75 @@
+//! CryptoCrate - A fast, user-friendly file and folder encryption library
+//!
+//! Files are encrypted with AES-256-GCM or XChaCha20-Poly1305 under a random
+//! file key. The key is stored in key slots opened by a password (Argon2id),
+//! a key file, both, or an X25519 identity.
+//!
+//! Most users want the `Encryptor` / `Decryptor` builders:
+//!
+//! ```no_run
+//! use cryptocrate::{Decryptor, Encryptor};
+//!
+//! # fn main() -> cryptocrate::Result<()> {
+//! Encryptor::with_password("correct horse battery staple")
+//!     .compress(true)
+//!     .encrypt_file("notes.txt", "notes.txt.crat")?;
+//!
+//! let metadata = Decryptor::with_password("correct horse battery staple")
+//!     .decrypt_file("notes.txt.crat", "notes.txt")?;
+//! assert_eq!(metadata.filename, "notes.txt");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Data that does not live in a file (a socket, a database blob, a buffer)
+//! can be encrypted with `CrateWriter` and read back with `CrateReader`,
+//! which implement `std::io::Write` and `std::io::Read`. With the `tokio`
+//! feature, `AsyncCrateWriter` and `AsyncCrateReader` do the same for
+//! tokio's `AsyncWrite` and `AsyncRead`.
+//!
+//! Whole directories can also be packed into a single crate, hiding the
+//! file count and sizes; see `archive`.
+//!
+//! The functions re-exported at the crate root (`encrypt_file`,
+//! `decrypt_file`, the streaming variants and `inspect_file`) are the same
+//! building blocks the `cryptocrate` binary uses.
+
+pub mod archive;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod compression;
+pub mod config;
+pub mod crypto;
+pub mod error;
+pub mod file_handler;
+pub mod format;
+pub mod identity;
+pub mod inspect;
+pub mod keyfile;
+pub mod metadata;
+pub mod parallel;
+pub mod secret;
+pub mod secure_delete;
+pub mod streaming;
+
+mod builder;
+
+pub use archive::{ArchiveEntry, ArchiveReader};
+#[cfg(feature = "tokio")]
+pub use async_io::{AsyncCrateReader, AsyncCrateWriter};
+pub use builder::{Decryptor, Encryptor};
+pub use compression::{Compression, CompressionAlgorithm};
+pub use config::Config;
+pub use crypto::{
+    decrypt_file, decrypt_file_with_credentials, encrypt_file, encrypt_file_with_options,
+    Argon2Params, CipherAlgorithm, Credentials, EncryptOptions,
+};
+pub use error::{CrateError, Result};
+pub use identity::{Identity, Recipient};
+pub use inspect::{inspect_file, inspect_file_with_credentials, FileInfo};
+pub use metadata::{FileMetadata, Timestamps};
+pub use streaming::{
+    decrypt_file_streaming, decrypt_file_streaming_with_credentials,
+    decrypt_file_streaming_with_threads, decrypt_range_with_credentials, encrypt_file_streaming,
+    encrypt_file_streaming_with_options, CrateReader, CrateWriter,
+};
@@ -2,37 +2,43 @@
 //!
 //! This tool uses AES-256-GCM for encryption and Argon2id for key derivation,
 //! providing strong security while remaining easy to use for beginners.
+//! All of the cryptography lives in the `cryptocrate` library; this binary
+//! only handles arguments, prompts and progress output.
 
-mod compression;
-mod config;
-mod crypto;
-mod error;
-mod file_handler;
-mod format;
-mod inspect;
 mod interactive;
-mod keyfile;
-mod metadata;
-mod secure_delete;
-mod streaming;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use std::fs;
-use std::io::{self, Write};
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 use std::time::Instant;
 
-use compression::compression_ratio;
-use config::Config;
-use crypto::{decrypt_file, encrypt_file};
-use file_handler::collect_files;
-use inspect::inspect_file;
+use cryptocrate::compression::compression_ratio;
+use cryptocrate::crypto::keyslot::{
+    add_key_slot, add_recipient_slot, list_key_slots, remove_key_slot, stage_rekey,
+};
+use cryptocrate::crypto::{SlotKind, StagedUpdate};
+use cryptocrate::file_handler::{
+    collect_files, collect_files_and_symlinks, create_symlink, join_relative, restore_attributes,
+    FileEntry, OwnerPolicy,
+};
+use cryptocrate::format::read_header;
+use cryptocrate::identity::{generate_identity, read_identity};
+use cryptocrate::keyfile::{generate_keyfile, read_keyfile, DEFAULT_KEYFILE_SIZE};
+use cryptocrate::parallel::resolve_threads;
+use cryptocrate::secret::{disable_core_dumps, SecretString};
+use cryptocrate::secure_delete::{secure_delete, SecureDeleteMode};
+use cryptocrate::streaming;
+use cryptocrate::{
+    inspect_file, CipherAlgorithm, Compression, Config, Credentials, Decryptor, Encryptor,
+    FileMetadata, Recipient,
+};
 use interactive::{confirm, prompt_password, prompt_password_with_confirm};
-use keyfile::{combine_password_and_keyfile, generate_keyfile, read_keyfile, DEFAULT_KEYFILE_SIZE};
-use secure_delete::{secure_delete, SecureDeleteMode};
-use streaming::{decrypt_file_streaming, encrypt_file_streaming, should_use_streaming};
 
 #[derive(Parser)]
 #[command(name = "cryptocrate")]
@@ -60,7 +66,13 @@ enum Commands {
         #[arg(short, long)]
         compress: bool,
 
-        /// Output directory (default: same as input or from config)
+        /// Compression algorithm and level: zstd[:1-22], lz4, xz[:0-9], brotli[:0-11]
+        /// or none (implies --compress; default: from config). Prefix with auto: (or
+        /// use just auto) to store files that look compressed already as is
+        #[arg(long, value_name = "ALGO[:LEVEL]")]
+        compression: Option<String>,
+
+        /// Output directory, mirroring the folder structure (default: same as input or from config)
         #[arg(short, long)]
         output: Option<PathBuf>,
 
@@ -72,6 +84,19 @@ enum Commands {
         #[arg(short, long)]
         keyfile: Option<PathBuf>,
 
+        /// Store filename, size and timestamps unencrypted (visible to `inspect`)
+        #[arg(long)]
+        plain_metadata: bool,
+
+        /// Encryption algorithm: aes-256-gcm or xchacha20-poly1305 (default: from config)
+        #[arg(short, long)]
+        algorithm: Option<String>,
+
+        /// Encrypt to a recipient public key (crate-pk-...); repeat for several.
+        /// No password is asked for unless --password or --keyfile is also given
+        #[arg(short, long = "recipient", value_name = "PUBKEY")]
+        recipients: Vec<Recipient>,
+
         /// Securely delete original files after encryption
         #[arg(long)]
         delete: bool,
@@ -80,20 +105,40 @@ enum Commands {
         #[arg(long, default_value = "standard")]
         delete_mode: String,
 
+        /// Number of files to encrypt at the same time (0 = one per CPU; default: from config)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+
+        /// Pack everything into a single encrypted archive, hiding file names,
+        /// count and sizes (read it back with `list` / `extract`)
+        #[arg(long, conflicts_with = "jobs")]
+        archive: bool,
+
+        /// Record Unix permissions, owner, extended attributes and symlinks
+        /// so `decrypt` can restore them (default: from config)
+        #[arg(long, conflicts_with = "archive")]
+        preserve: bool,
+
         /// Skip confirmation prompts
         #[arg(short = 'y', long)]
         yes: bool,
     },
     /// Decrypt files or folders
     Decrypt {
-        /// Paths to encrypted files (.crat)
+        /// Paths to encrypted files (.crat) or folders containing them
         #[arg(value_name = "PATH", required = true)]
         paths: Vec<PathBuf>,
 
-        /// Output directory (default: same as input or from config)
+        /// Output directory, recreating the original folder structure
+        /// (default: same as input or from config);
+        /// with --range, the output file (default: stdout)
         #[arg(short, long)]
         output: Option<PathBuf>,
 
+        /// Only decrypt LEN bytes starting at OFFSET (e.g. 4096:4096)
+        #[arg(long, value_name = "OFFSET:LEN", value_parser = parse_range)]
+        range: Option<(u64, u64)>,
+
         /// Password for decryption (will prompt if not provided)
         #[arg(short, long)]
         password: Option<String>,
@@ -102,6 +147,22 @@ enum Commands {
         #[arg(short, long)]
         keyfile: Option<PathBuf>,
 
+        /// Identity file for files encrypted to your recipient key
+        #[arg(short, long)]
+        identity: Option<PathBuf>,
+
+        /// Number of files to decrypt at the same time (0 = one per CPU; default: from config)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+
+        /// Restore the recorded owner and group even when not running as root
+        #[arg(long, conflicts_with = "no_same_owner")]
+        same_owner: bool,
+
+        /// Never restore the recorded owner and group, even as root
+        #[arg(long)]
+        no_same_owner: bool,
+
         /// Skip confirmation prompts
         #[arg(short = 'y', long)]
         yes: bool,
@@ -111,8 +172,64 @@ enum Commands {
         /// Paths to encrypted files (.crat) to inspect
         #[arg(value_name = "PATH", required = true)]
         paths: Vec<PathBuf>,
+
+        /// Password to reveal encrypted metadata
+        #[arg(short, long)]
+        password: Option<String>,
+
+        /// Key file to reveal encrypted metadata
+        #[arg(short, long)]
+        keyfile: Option<PathBuf>,
+
+        /// Identity file to reveal encrypted metadata
+        #[arg(short, long)]
+        identity: Option<PathBuf>,
+    },
+    /// List the files in an archive created with `encrypt --archive`
+    List {
+        /// Encrypted archive (.crat)
+        #[arg(value_name = "PATH")]
+        path: PathBuf,
+
+        /// Password for the archive (will prompt if not provided)
+        #[arg(short, long)]
+        password: Option<String>,
+
+        /// Key file for the archive
+        #[arg(short, long)]
+        keyfile: Option<PathBuf>,
+
+        /// Identity file for the archive
+        #[arg(short, long)]
+        identity: Option<PathBuf>,
+    },
+    /// Extract an archive created with `encrypt --archive`, restoring its folders
+    Extract {
+        /// Encrypted archive (.crat)
+        #[arg(value_name = "PATH")]
+        path: PathBuf,
+
+        /// Output directory (default: next to the archive or from config)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Password for the archive (will prompt if not provided)
+        #[arg(short, long)]
+        password: Option<String>,
+
+        /// Key file for the archive
+        #[arg(short, long)]
+        keyfile: Option<PathBuf>,
+
+        /// Identity file for the archive
+        #[arg(short, long)]
+        identity: Option<PathBuf>,
+
+        /// Skip confirmation prompts
+        #[arg(short = 'y', long)]
+        yes: bool,
     },
-    /// Generate a new key file
+    /// Generate a new key file or identity
     Keygen {
         /// Output path for the key file
         #[arg(value_name = "PATH")]
@@ -121,6 +238,45 @@ enum Commands {
         /// Key file size in bytes (default: 4096)
         #[arg(short, long)]
         size: Option<usize>,
+
+        /// Generate an identity (X25519 key pair) for --recipient encryption instead
+        #[arg(long, conflicts_with = "size")]
+        identity: bool,
+    },
+    /// Change the password / key file of encrypted files without re-encrypting them
+    Rekey {
+        /// Encrypted files (.crat) or folders containing them
+        #[arg(value_name = "PATH", required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Current password (will prompt if not provided)
+        #[arg(short, long)]
+        password: Option<String>,
+
+        /// Current key file
+        #[arg(short, long)]
+        keyfile: Option<PathBuf>,
+
+        /// Current identity file
+        #[arg(short, long)]
+        identity: Option<PathBuf>,
+
+        /// New password (will prompt if not provided)
+        #[arg(long)]
+        new_password: Option<String>,
+
+        /// New key file
+        #[arg(long)]
+        new_keyfile: Option<PathBuf>,
+
+        /// Skip confirmation prompts
+        #[arg(short = 'y', long)]
+        yes: bool,
+    },
+    /// Manage the key slots (passwords / key files) that open a file
+    Slot {
+        #[command(subcommand)]
+        action: SlotAction,
     },
     /// Manage configuration
     Config {
@@ -129,6 +285,68 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum SlotAction {
+    /// List the key slots of an encrypted file
+    List {
+        /// Encrypted file (.crat)
+        #[arg(value_name = "PATH")]
+        path: PathBuf,
+    },
+    /// Add a password and/or key file that can open the file
+    Add {
+        /// Encrypted file (.crat)
+        #[arg(value_name = "PATH")]
+        path: PathBuf,
+
+        /// Current password (will prompt if not provided)
+        #[arg(short, long)]
+        password: Option<String>,
+
+        /// Current key file
+        #[arg(short, long)]
+        keyfile: Option<PathBuf>,
+
+        /// Current identity file
+        #[arg(short, long)]
+        identity: Option<PathBuf>,
+
+        /// Password for the new slot (will prompt if not provided)
+        #[arg(long)]
+        new_password: Option<String>,
+
+        /// Key file for the new slot
+        #[arg(long)]
+        new_keyfile: Option<PathBuf>,
+
+        /// Recipient public key for the new slot (instead of a password)
+        #[arg(long, value_name = "PUBKEY", conflicts_with_all = ["new_password", "new_keyfile"])]
+        recipient: Option<Recipient>,
+    },
+    /// Remove a key slot
+    Remove {
+        /// Encrypted file (.crat)
+        #[arg(value_name = "PATH")]
+        path: PathBuf,
+
+        /// Slot number, as shown by `slot list`
+        #[arg(value_name = "SLOT")]
+        slot: usize,
+
+        /// Password for any remaining slot (will prompt if not provided)
+        #[arg(short, long)]
+        password: Option<String>,
+
+        /// Key file for any remaining slot
+        #[arg(short, long)]
+        keyfile: Option<PathBuf>,
+
+        /// Identity file for any remaining slot
+        #[arg(short, long)]
+        identity: Option<PathBuf>,
+    },
+}
+
 #[derive(Subcommand)]
 enum ConfigAction {
     /// Show current configuration
@@ -172,25 +390,57 @@ fn parse_delete_mode(mode_str: &str) -> SecureDeleteMode {
     }
 }
 
-/// Get password with optional keyfile
-fn get_password_with_keyfile(
+/// Parse a decrypt range given as OFFSET:LEN
+fn parse_range(range: &str) -> std::result::Result<(u64, u64), String> {
+    let (offset, length) = range
+        .split_once(':')
+        .ok_or_else(|| "expected OFFSET:LEN, e.g. 4096:4096".to_string())?;
+    let offset = offset
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid offset: {}", e))?;
+    let length = length
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid length: {}", e))?;
+    Ok((offset, length))
+}
+
+/// Get credentials: a password, a key file, or both (or an identity)
+fn get_credentials(
     password_opt: Option<String>,
     keyfile_opt: Option<PathBuf>,
+    identity_opt: Option<PathBuf>,
     for_encryption: bool,
-) -> Result<String> {
+) -> Result<Credentials> {
+    let identity = if let Some(identity_path) = identity_opt {
+        eprintln!("🪪 Reading identity: {}", identity_path.display());
+        Some(read_identity(&identity_path)?)
+    } else {
+        None
+    };
+
+    // An identity on its own is enough - don't prompt for a password
+    if let Some(identity) = identity.clone() {
+        if password_opt.is_none() && keyfile_opt.is_none() {
+            return Ok(Credentials::identity(identity));
+        }
+    }
+
     let keyfile_hash = if let Some(keyfile_path) = keyfile_opt {
-        println!("🔑 Reading key file: {}", keyfile_path.display());
+        eprintln!("🔑 Reading key file: {}", keyfile_path.display());
         Some(read_keyfile(&keyfile_path)?)
     } else {
         None
     };
 
+    // Passwords from the command line move straight into locked memory
     let password = match password_opt {
-        Some(p) => p,
+        Some(p) => SecretString::new(p),
         None => {
             if keyfile_hash.is_some() {
-                println!("\n💡 Tip: Key file detected. You can optionally add a password for two-factor security.");
-                println!("   Press Enter to skip password (key file only).");
+                eprintln!("\n💡 Tip: Key file detected. You can optionally add a password for two-factor security.");
+                eprintln!("   Press Enter to skip password (key file only).");
             }
 
             if for_encryption {
@@ -201,23 +451,14 @@ fn get_password_with_keyfile(
         }
     };
 
-    // Combine password and keyfile if both are provided
-    if let Some(kf_hash) = keyfile_hash {
-        if password.is_empty() {
-            // Key file only - use keyfile hash as "password"
-            Ok(hex::encode(kf_hash))
-        } else {
-            // Both password and keyfile - combine them
-            let combined = combine_password_and_keyfile(&password, &kf_hash);
-            Ok(hex::encode(combined))
-        }
-    } else {
-        // Password only
-        if password.is_empty() {
-            anyhow::bail!("Password cannot be empty!\n\n💡 Tip: Provide either a password, a key file, or both.");
-        }
-        Ok(password)
+    if keyfile_hash.is_none() && password.is_empty() {
+        anyhow::bail!(
+            "Password cannot be empty!\n\n💡 Tip: Provide either a password, a key file, or both."
+        );
     }
+    let mut credentials = Credentials::new(Some(password), keyfile_hash);
+    credentials.identities.extend(identity);
+    Ok(credentials)
 }
 
 /// Enhanced error messages with suggestions
@@ -230,12 +471,21 @@ fn handle_error(err: anyhow::Error) {
     if err_str.contains("Password") || err_str.contains("password") {
         eprintln!("\n💡 Tip: Make sure you're using the correct password and/or key file.");
         eprintln!("   If you used a key file during encryption, you must use the same file for decryption.");
+    } else if err_str.contains("Authentication failed") {
+        eprintln!(
+            "\n💡 Tip: The file failed its integrity check and may be corrupted or tampered with."
+        );
+        eprintln!("   Restore it from a backup copy if you have one.");
     } else if err_str.contains("not found") || err_str.contains("No such file") {
         eprintln!("\n💡 Tip: Check that the file path is correct and the file exists.");
         eprintln!("   Use 'ls' or 'dir' to list files in the current directory.");
     } else if err_str.contains("Permission denied") {
         eprintln!("\n💡 Tip: You don't have permission to access this file.");
         eprintln!("   Try running with appropriate permissions or check file ownership.");
+    } else if err_str.contains("Compressed files can only be read from the start") {
+        eprintln!("\n💡 Tip: Decrypt the whole file instead, then read the part you need.");
+    } else if err_str.contains("Unknown compression") || err_str.contains("levels") {
+        eprintln!("\n💡 Tip: Use --compression zstd[:1-22], lz4, xz[:0-9], brotli[:0-11] or none.");
     } else if err_str.contains("Invalid") || err_str.contains("Not a valid") {
         eprintln!("\n💡 Tip: This doesn't appear to be a valid CryptoCrate file.");
         eprintln!("   Make sure you're trying to decrypt a .crat file created by CryptoCrate.");
@@ -246,14 +496,22 @@ fn handle_error(err: anyhow::Error) {
 }
 
 /// Handle encryption command
+#[allow(clippy::too_many_arguments)]
 fn handle_encrypt(
     paths: Vec<PathBuf>,
     compress: bool,
+    compression: Option<String>,
     output_dir: Option<PathBuf>,
     password: Option<String>,
     keyfile: Option<PathBuf>,
+    plain_metadata: bool,
+    algorithm: Option<String>,
+    recipients: Vec<Recipient>,
     delete_originals: bool,
     delete_mode_str: String,
+    jobs: Option<usize>,
+    archive: bool,
+    preserve: bool,
     yes: bool,
     config: &Config,
 ) -> Result<()> {
@@ -267,18 +525,36 @@ fn handle_encrypt(
         }
     }
 
-    // Warning about compression with streaming
-    if compress {
-        println!("💡 Note: Large files (>100 MB) cannot use compression due to streaming mode.");
-    }
-
     // Use compression from config if not specified
-    let compress = compress || config.compress_by_default;
+    let compression = match compression {
+        // A bare `auto` keeps the configured algorithm
+        Some(spec) if spec.eq_ignore_ascii_case("auto") => Compression {
+            auto: true,
+            ..config.compression()?
+        },
+        Some(spec) => spec.parse::<Compression>()?,
+        None if compress || config.compress_by_default => config.compression()?,
+        None => Compression::NONE,
+    };
+    let encrypt_metadata = config.encrypt_metadata && !plain_metadata;
+    let preserve = (preserve || config.preserve_attributes) && !archive;
+    let mut encryptor = Encryptor::from_config(config)?
+        .compression(compression)
+        .encrypt_metadata(encrypt_metadata)
+        .preserve_attributes(preserve);
+    if let Some(name) = algorithm {
+        encryptor = encryptor.algorithm(name.parse::<CipherAlgorithm>()?);
+    }
+    let algorithm = encryptor.options().algorithm;
 
     // Collect all files from all paths
     let mut all_files = Vec::new();
     for path in &paths {
-        let files = collect_files(path, None)?;
+        let files = if preserve {
+            collect_files_and_symlinks(path, None)?
+        } else {
+            collect_files(path, None)?
+        };
         all_files.extend(files);
     }
 
@@ -292,9 +568,9 @@ fn handle_encrypt(
         .iter()
         .filter(|f| f.size > streaming::STREAMING_THRESHOLD)
         .count();
-    if large_file_count > 0 {
+    if large_file_count > 0 && !archive {
         println!(
-            "📦 {} large file(s) detected - will use streaming mode (no compression)",
+            "📦 {} large file(s) detected - will use streaming mode",
             large_file_count
         );
     }
@@ -302,39 +578,82 @@ fn handle_encrypt(
     // Calculate total size
     let total_size: u64 = all_files.iter().map(|f| f.size).sum();
     let file_count = all_files.len();
+    let jobs = resolve_threads(jobs.unwrap_or(config.jobs)).min(file_count);
 
     println!("\n📊 Encryption Summary:");
     println!("   Files: {}", file_count);
     println!("   Total size: {}", format_size(total_size));
     println!(
         "   Compression: {}",
-        if compress { "✅ enabled" } else { "❌ disabled" }
+        if compression.is_enabled() {
+            format!("✅ {}", compression)
+        } else {
+            "❌ disabled".to_string()
+        }
+    );
+    println!("   Algorithm: {}", algorithm);
+    println!(
+        "   Metadata: {}",
+        if encrypt_metadata {
+            "🔒 encrypted"
+        } else {
+            "👁️  visible"
+        }
     );
+    if preserve {
+        println!("   Attributes: ✅ permissions, owner, xattrs and symlinks");
+    }
     if keyfile.is_some() {
         println!("   Key file: ✅ will be used");
     }
+    if !recipients.is_empty() {
+        println!("   Recipients: {}", recipients.len());
+    }
     if delete_originals {
         let mode = parse_delete_mode(&delete_mode_str);
         println!("   Secure delete: ✅ enabled ({:?} mode)", mode);
     }
+    if archive {
+        println!("   Archive: ✅ single file");
+    } else if jobs > 1 {
+        println!("   Parallel jobs: {}", jobs);
+    }
     println!();
 
     // Confirm deletion if enabled
-    if delete_originals && !yes {
-        if !confirm(
+    if delete_originals
+        && !yes
+        && !confirm(
             "⚠️  Original files will be PERMANENTLY deleted after encryption. Continue?",
             false,
-        )? {
-            println!("Operation cancelled.");
-            return Ok(());
-        }
+        )?
+    {
+        println!("Operation cancelled.");
+        return Ok(());
     }
 
-    // Get password (possibly combined with keyfile)
-    let password = get_password_with_keyfile(password, keyfile, true)?;
+    // Get password and/or key file (recipients alone need neither)
+    if recipients.is_empty() || password.is_some() || keyfile.is_some() {
+        encryptor = encryptor.credentials(get_credentials(password, keyfile, None, true)?);
+    }
+    for recipient in recipients {
+        encryptor = encryptor.recipient(recipient);
+    }
 
     // Determine output directory
     let output_dir = output_dir.or_else(|| config.default_output_dir.as_ref().map(PathBuf::from));
+    let delete_mode = parse_delete_mode(&delete_mode_str);
+
+    if archive {
+        let archive_path = archive_path(&paths, output_dir)?;
+        return handle_archive(
+            &all_files,
+            &archive_path,
+            &encryptor,
+            delete_originals,
+            delete_mode,
+        );
+    }
 
     // Setup progress
     let multi_progress = MultiProgress::new();
@@ -347,14 +666,19 @@ fn handle_encrypt(
     );
 
     let start_time = Instant::now();
-    let mut total_original_size = 0u64;
-    let mut total_encrypted_size = 0u64;
-    let mut success_count = 0;
-    let mut error_count = 0;
-    let delete_mode = parse_delete_mode(&delete_mode_str);
+    let total_original_size = AtomicU64::new(0);
+    let total_encrypted_size = AtomicU64::new(0);
+    let success_count = AtomicUsize::new(0);
+    let error_count = AtomicUsize::new(0);
+    let compressed_count = AtomicUsize::new(0);
+    let skipped_count = AtomicUsize::new(0);
 
-    // Encrypt each file
-    for (idx, file_entry) in all_files.iter().enumerate() {
+    if let Some(ref out_dir) = output_dir {
+        fs::create_dir_all(out_dir)?;
+    }
+
+    // Encrypt each file, `jobs` at a time
+    run_jobs(&all_files, jobs, |_, file_entry| {
         let file_pb = if config.show_detailed_progress {
             let pb = multi_progress.add(ProgressBar::new_spinner());
             pb.set_style(
@@ -378,29 +702,36 @@ fn handle_encrypt(
             pb.enable_steady_tick(std::time::Duration::from_millis(100));
         }
 
-        // Determine output path
-        let output_path = if let Some(ref out_dir) = output_dir {
-            fs::create_dir_all(out_dir)?;
-            out_dir.join(format!("{}.crat", filename))
-        } else {
-            file_entry.path.with_extension("crat")
-        };
-
-        // Encrypt the file (use streaming for large files)
-        let use_streaming = should_use_streaming(&file_entry.path)?;
-        let should_compress = compress && !use_streaming;
-
-        let encrypt_result = if use_streaming {
-            encrypt_file_streaming(&file_entry.path, &output_path, &password)
-        } else {
-            encrypt_file(&file_entry.path, &output_path, &password, should_compress)
-        };
+        // Encrypt the file (large files are streamed), mirroring the
+        // source tree inside the output directory
+        let encrypt_result =
+            encrypted_path(file_entry, output_dir.as_deref()).and_then(|output_path| {
+                let stored = encryptor.encrypt_entry(file_entry, &output_path)?;
+                Ok((output_path, stored))
+            });
 
         match encrypt_result {
-            Ok(_) => {
-                total_original_size += file_entry.size;
+            Ok((output_path, stored)) => {
+                let skipped = &stored.attributes.skipped_xattrs;
+                if !skipped.is_empty() {
+                    multi_progress.suspend(|| {
+                        eprintln!(
+                            "⚠️  {}: extended attributes not preserved: {}",
+                            file_entry.path.display(),
+                            skipped.join(", ")
+                        )
+                    });
+                }
+
+                // Record what `auto` compression decided for this file
+                if stored.is_compressed() {
+                    compressed_count.fetch_add(1, Ordering::Relaxed);
+                } else if compression.auto && file_entry.size > 0 {
+                    skipped_count.fetch_add(1, Ordering::Relaxed);
+                }
+                total_original_size.fetch_add(file_entry.size, Ordering::Relaxed);
                 if let Ok(metadata) = fs::metadata(&output_path) {
-                    total_encrypted_size += metadata.len();
+                    total_encrypted_size.fetch_add(metadata.len(), Ordering::Relaxed);
                 }
 
                 // Securely delete original if requested
@@ -430,21 +761,28 @@ fn handle_encrypt(
                         ));
                     }
                 }
-                success_count += 1;
+                success_count.fetch_add(1, Ordering::Relaxed);
             }
             Err(e) => {
                 if let Some(ref pb) = file_pb {
                     pb.finish_with_message(format!("❌ {} - Error: {}", filename, e));
                 }
-                error_count += 1;
+                error_count.fetch_add(1, Ordering::Relaxed);
             }
         }
 
-        overall_pb.set_position((idx + 1) as u64);
-        overall_pb.set_message(format!("✅ {} / ❌ {}", success_count, error_count));
-    }
+        overall_pb.inc(1);
+        overall_pb.set_message(format_counts(&success_count, &error_count));
+        Ok(())
+    })?;
 
     let duration = start_time.elapsed();
+    let total_original_size = total_original_size.into_inner();
+    let total_encrypted_size = total_encrypted_size.into_inner();
+    let success_count = success_count.into_inner();
+    let error_count = error_count.into_inner();
+    let compressed_count = compressed_count.into_inner();
+    let skipped_count = skipped_count.into_inner();
     overall_pb.finish_with_message(format!("Done! ✅ {} / ❌ {}", success_count, error_count));
 
     // Print summary
@@ -455,11 +793,17 @@ fn handle_encrypt(
     }
     println!("   Time: {:.2}s", duration.as_secs_f64());
 
-    if compress && total_original_size > 0 {
-        let ratio = compression_ratio(
-            total_original_size as usize,
-            total_encrypted_size as usize,
+    if compression.is_enabled() {
+        println!("   Compressed: {} files", compressed_count);
+    }
+    if skipped_count > 0 {
+        println!(
+            "   Stored as is: {} files (looked compressed already)",
+            skipped_count
         );
+    }
+    if compression.is_enabled() && total_original_size > 0 {
+        let ratio = compression_ratio(total_original_size as usize, total_encrypted_size as usize);
         println!("   Original size: {}", format_size(total_original_size));
         println!("   Encrypted size: {}", format_size(total_encrypted_size));
         println!("   Space saved: {:.1}%", ratio);
@@ -472,38 +816,310 @@ fn handle_encrypt(
     Ok(())
 }
 
-/// Handle decryption command
-fn handle_decrypt(
-    paths: Vec<PathBuf>,
+/// Where `encrypt --archive` writes the archive for `paths`
+///
+/// A single folder `photos` becomes `photos.crat`; several paths become
+/// `archive.crat`. It goes next to the (first) input unless an output
+/// directory is given.
+fn archive_path(paths: &[PathBuf], output_dir: Option<PathBuf>) -> Result<PathBuf> {
+    let first = paths[0].canonicalize()?;
+    let name = match paths {
+        [_] => first
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("archive"),
+        _ => "archive",
+    };
+
+    let dir = match output_dir {
+        Some(dir) => {
+            fs::create_dir_all(&dir)?;
+            dir
+        }
+        None => first
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from(".")),
+    };
+    Ok(dir.join(format!("{}.crat", name)))
+}
+
+/// Pack all files into one archive (encrypt --archive)
+fn handle_archive(
+    files: &[FileEntry],
+    archive_path: &Path,
+    encryptor: &Encryptor,
+    delete_originals: bool,
+    delete_mode: SecureDeleteMode,
+) -> Result<()> {
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} {msg}")
+            .unwrap(),
+    );
+    pb.set_message(format!(
+        "Packing {} files into {}",
+        files.len(),
+        archive_path.display()
+    ));
+    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+
+    let start_time = Instant::now();
+    let entries = match encryptor.encrypt_archive(files, archive_path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            pb.finish_with_message(format!("❌ Error: {}", e));
+            let _ = fs::remove_file(archive_path);
+            return Err(e.into());
+        }
+    };
+    let duration = start_time.elapsed();
+    pb.finish_with_message(format!(
+        "✅ {} ({} files)",
+        archive_path.display(),
+        entries.len()
+    ));
+
+    // Securely delete originals only once the archive is complete
+    let mut delete_errors = 0;
+    if delete_originals {
+        for file in files {
+            if let Err(e) = secure_delete(&file.path, delete_mode) {
+                eprintln!(
+                    "⚠️  {} - Archived but failed to delete: {}",
+                    file.path.display(),
+                    e
+                );
+                delete_errors += 1;
+            }
+        }
+    }
+
+    let total_size: u64 = entries.iter().map(|entry| entry.size).sum();
+    println!("\n🎉 Archive Created!");
+    println!("   Archive: {}", archive_path.display());
+    println!("   Files: {}", entries.len());
+    println!("   Original size: {}", format_size(total_size));
+    println!(
+        "   Archive size: {}",
+        format_size(fs::metadata(archive_path)?.len())
+    );
+    let compression = encryptor.options().compression;
+    if compression.is_enabled() {
+        // With `auto`, the header tells whether the payload ended up compressed
+        let (header, _) = read_header(&mut File::open(archive_path)?)?;
+        println!(
+            "   Compression: {}",
+            if header.is_compressed_stream() {
+                format!("✅ {}", compression)
+            } else {
+                "⏭️  skipped (contents look compressed already)".to_string()
+            }
+        );
+    }
+    println!("   Time: {:.2}s", duration.as_secs_f64());
+    if delete_originals && delete_errors == 0 {
+        println!("   🗜️  Original files securely deleted");
+    }
+
+    Ok(())
+}
+
+/// Handle list command
+fn handle_list(
+    path: PathBuf,
+    password: Option<String>,
+    keyfile: Option<PathBuf>,
+    identity: Option<PathBuf>,
+) -> Result<()> {
+    let decryptor =
+        Decryptor::new().credentials(get_credentials(password, keyfile, identity, false)?);
+    let archive = decryptor.open_archive(&path)?;
+    let entries = archive.entries();
+
+    let total_size: u64 = entries.iter().map(|entry| entry.size).sum();
+    println!(
+        "\n🗃️  {} ({} files, {})\n",
+        archive.metadata().filename,
+        entries.len(),
+        format_size(total_size)
+    );
+    for entry in entries {
+        println!("   {:>12}  {}", format_size(entry.size), entry.path);
+    }
+
+    Ok(())
+}
+
+/// Handle extract command
+fn handle_extract(
+    path: PathBuf,
     output_dir: Option<PathBuf>,
     password: Option<String>,
     keyfile: Option<PathBuf>,
+    identity: Option<PathBuf>,
     yes: bool,
     config: &Config,
 ) -> Result<()> {
-    // Validate all paths exist
-    for path in &paths {
+    let decryptor =
+        Decryptor::new().credentials(get_credentials(password, keyfile, identity, false)?);
+    let archive = decryptor.open_archive(&path)?;
+    let file_count = archive.entries().len();
+
+    // Determine output directory
+    let output_dir = output_dir
+        .or_else(|| config.default_output_dir.as_ref().map(PathBuf::from))
+        .or_else(|| path.parent().map(Path::to_path_buf))
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    println!("\n📊 Extraction Summary:");
+    println!("   Archive: {}", archive.metadata().filename);
+    println!("   Files: {}", file_count);
+    println!("   Output: {}", output_dir.display());
+    println!();
+
+    // Ask once about all files that would be replaced
+    let existing = archive
+        .entries()
+        .iter()
+        .filter(|entry| {
+            entry
+                .target(&output_dir)
+                .is_ok_and(|target| fs::symlink_metadata(target).is_ok())
+        })
+        .count();
+    let overwrite = existing == 0
+        || yes
+        || !config.confirm_overwrite
+        || confirm(
+            &format!("{} file(s) already exist. Overwrite them?", existing),
+            false,
+        )?;
+
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} {msg}")
+            .unwrap(),
+    );
+    pb.set_message(format!("Extracting {} files", file_count));
+    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+
+    let start_time = Instant::now();
+    let extracted = match archive.extract_to(&output_dir, overwrite) {
+        Ok(extracted) => extracted,
+        Err(e) => {
+            pb.finish_with_message(format!("❌ Error: {}", e));
+            return Err(e.into());
+        }
+    };
+    let duration = start_time.elapsed();
+    pb.finish_with_message(format!("✅ {} files extracted", extracted));
+
+    println!("\n🎉 Extraction Complete!");
+    println!("   Extracted: {} files", extracted);
+    if extracted < file_count {
+        println!("   ⏭️  Skipped: {} existing files", file_count - extracted);
+    }
+    println!("   Time: {:.2}s", duration.as_secs_f64());
+
+    Ok(())
+}
+
+/// Where `file_entry` is encrypted to: next to it, or at the same relative path in `output_dir`
+fn encrypted_path(file_entry: &FileEntry, output_dir: Option<&Path>) -> Result<PathBuf> {
+    let Some(out_dir) = output_dir else {
+        return Ok(file_entry.path.with_extension("crat"));
+    };
+
+    let output_path = join_relative(out_dir, &format!("{}.crat", file_entry.portable_path()?))?;
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(output_path)
+}
+
+/// Move a decrypted file to its final name, or recreate it as a symlink
+fn move_into_place(temp_output: &Path, final_output: &Path, metadata: &FileMetadata) -> Result<()> {
+    let Some(ref target) = metadata.attributes.symlink_target else {
+        fs::rename(temp_output, final_output)?;
+        return Ok(());
+    };
+
+    fs::remove_file(temp_output)?;
+    if final_output.symlink_metadata().is_ok() {
+        fs::remove_file(final_output)?;
+    }
+    create_symlink(target, final_output)?;
+    Ok(())
+}
+
+/// The encrypted files to work on: files as given, .crat files from folders
+fn collect_crates(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut crates = Vec::new();
+    for path in paths {
         if !path.exists() {
             anyhow::bail!(
                 "File not found: {}\n\n💡 Tip: Make sure the .crat file exists.",
                 path.display()
             );
         }
-        if !path.is_file() {
-            anyhow::bail!("Not a file: {}\n\n💡 Tip: Decrypt command only works with files, not directories.", path.display());
+        if path.is_file() {
+            crates.push(path.clone());
+            continue;
         }
+        crates.extend(
+            collect_files(path, None)?
+                .into_iter()
+                .filter(|entry| {
+                    !entry.is_dir && entry.path.extension().is_some_and(|ext| ext == "crat")
+                })
+                .map(|entry| entry.path),
+        );
+    }
+    Ok(crates)
+}
+
+/// Handle decryption command
+#[allow(clippy::too_many_arguments)]
+fn handle_decrypt(
+    paths: Vec<PathBuf>,
+    output_dir: Option<PathBuf>,
+    password: Option<String>,
+    keyfile: Option<PathBuf>,
+    identity: Option<PathBuf>,
+    jobs: Option<usize>,
+    owner: OwnerPolicy,
+    yes: bool,
+    config: &Config,
+) -> Result<()> {
+    let paths = collect_crates(&paths)?;
+    if paths.is_empty() {
+        println!("⚠️  No encrypted files found to decrypt!");
+        return Ok(());
     }
 
     let file_count = paths.len();
+    let jobs = resolve_threads(jobs.unwrap_or(config.jobs)).min(file_count);
     println!("\n📊 Decryption Summary:");
     println!("   Files: {}", file_count);
     if keyfile.is_some() {
         println!("   Key file: ✅ will be used");
     }
+    if identity.is_some() {
+        println!("   Identity: ✅ will be used");
+    }
+    if jobs > 1 {
+        println!("   Parallel jobs: {}", jobs);
+    }
     println!();
 
-    // Get password (possibly combined with keyfile)
-    let password = get_password_with_keyfile(password, keyfile, false)?;
+    // Get password, key file and/or identity
+    let decryptor = Decryptor::new()
+        .credentials(get_credentials(password, keyfile, identity, false)?)
+        .threads(config.threads);
 
     // Determine output directory
     let output_dir = output_dir.or_else(|| config.default_output_dir.as_ref().map(PathBuf::from));
@@ -524,11 +1140,14 @@ fn handle_decrypt(
     );
 
     let start_time = Instant::now();
-    let mut success_count = 0;
-    let mut error_count = 0;
-
-    // Decrypt each file
-    for (idx, path) in paths.iter().enumerate() {
+    let success_count = AtomicUsize::new(0);
+    let error_count = AtomicUsize::new(0);
+    // Held while resolving, checking for and moving to the final path, so two jobs
+    // never prompt at once or race for the same output name
+    let finalize_lock = Mutex::new(());
+
+    // Decrypt each file, `jobs` at a time
+    run_jobs(&paths, jobs, |idx, path| {
         let file_pb = if config.show_detailed_progress {
             let pb = multi_progress.add(ProgressBar::new_spinner());
             pb.set_style(
@@ -541,89 +1160,132 @@ fn handle_decrypt(
             None
         };
 
-        let filename = path.file_name().and_then(|s| s.to_str()).unwrap_or("unknown");
+        let filename = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("unknown");
 
         if let Some(ref pb) = file_pb {
             pb.set_message(filename.to_string());
             pb.enable_steady_tick(std::time::Duration::from_millis(100));
         }
 
-        // Determine output path (temporary)
+        // Determine output path (temporary, unique per file)
+        let temp_name = format!("temp_decrypt_{}", idx);
         let temp_output = if let Some(ref out_dir) = output_dir {
-            out_dir.join("temp_decrypt")
+            out_dir.join(&temp_name)
         } else {
-            path.with_file_name("temp_decrypt")
+            path.with_file_name(&temp_name)
         };
 
-        // Check if we should use streaming
-        let file_size = fs::metadata(path)?.len();
-        let use_streaming = file_size > streaming::STREAMING_THRESHOLD;
-
-        // Decrypt the file
-        let decrypt_result = if use_streaming {
-            decrypt_file_streaming(path, &temp_output, &password)
-        } else {
-            decrypt_file(path, &temp_output, &password)
-        };
+        // Decrypt the file (large files are streamed)
+        let decrypt_result = decryptor.decrypt_file(path, &temp_output);
 
         match decrypt_result {
             Ok(metadata) => {
-                // Move to final location with original filename
-                let final_output = if let Some(ref out_dir) = output_dir {
-                    out_dir.join(&metadata.filename)
-                } else {
-                    path.with_file_name(&metadata.filename)
+                // Taken before resolving the final path: another job may be
+                // restoring a symlink that path would otherwise pass through
+                let _finalizing = finalize_lock.lock().unwrap();
+
+                // Move to final location with original filename, recreating
+                // the encrypted folder's structure inside the output directory
+                let final_output = match (&output_dir, &metadata.relative_path) {
+                    (Some(out_dir), Some(relative_path)) => join_relative(out_dir, relative_path)
+                        .and_then(|final_output| {
+                            if let Some(parent) = final_output.parent() {
+                                fs::create_dir_all(parent)?;
+                            }
+                            Ok(final_output)
+                        }),
+                    (Some(out_dir), None) => join_relative(out_dir, &metadata.filename),
+                    (None, _) => join_relative(
+                        path.parent().unwrap_or(Path::new("")),
+                        &metadata.filename,
+                    ),
                 };
-
-                // Check if file exists
-                if final_output.exists() && !yes && config.confirm_overwrite {
-                    if !confirm(
-                        &format!("Overwrite existing file '{}'?", metadata.filename),
-                        false,
-                    )? {
-                        let _ = fs::remove_file(&temp_output);
+                let final_output = match final_output {
+                    Ok(final_output) => final_output,
+                    Err(e) => {
                         if let Some(ref pb) = file_pb {
-                            pb.finish_with_message(format!("⏭️  {} - Skipped", filename));
+                            pb.finish_with_message(format!("❌ {} - Error: {}", filename, e));
                         }
-                        continue;
+                        error_count.fetch_add(1, Ordering::Relaxed);
+                        let _ = fs::remove_file(&temp_output);
+                        overall_pb.inc(1);
+                        overall_pb.set_message(format_counts(&success_count, &error_count));
+                        return Ok(());
                     }
+                };
+
+                // Check if file exists
+                if final_output.exists()
+                    && !yes
+                    && config.confirm_overwrite
+                    && !multi_progress.suspend(|| {
+                        confirm(
+                            &format!("Overwrite existing file '{}'?", metadata.filename),
+                            false,
+                        )
+                    })?
+                {
+                    let _ = fs::remove_file(&temp_output);
+                    if let Some(ref pb) = file_pb {
+                        pb.finish_with_message(format!("⏭️  {} - Skipped", filename));
+                    }
+                    overall_pb.inc(1);
+                    return Ok(());
                 }
 
-                if let Err(e) = fs::rename(&temp_output, &final_output) {
+                if let Err(e) = move_into_place(&temp_output, &final_output, &metadata) {
                     if let Some(ref pb) = file_pb {
                         pb.finish_with_message(format!("❌ {} - Error: {}", filename, e));
                     }
-                    error_count += 1;
+                    error_count.fetch_add(1, Ordering::Relaxed);
                 } else {
-                    let compressed_msg = if metadata.is_compressed {
-                        " (was compressed)"
+                    let compressed_msg = if metadata.is_compressed() {
+                        format!(" (was {}-compressed)", metadata.compression)
+                    } else {
+                        String::new()
+                    };
+                    // Times go first: restoring the mode may make the file read-only.
+                    // The contents are safe either way, so failures are only warnings
+                    let times = if metadata.attributes.is_symlink() {
+                        Ok(())
                     } else {
-                        ""
+                        metadata.timestamps.apply_to(&final_output)
+                    };
+                    let attributes = restore_attributes(&final_output, &metadata.attributes, owner);
+                    let attributes_msg = match times.and(attributes) {
+                        Ok(()) => String::new(),
+                        Err(e) => format!(" ⚠️  attributes not restored: {}", e),
                     };
                     if let Some(ref pb) = file_pb {
                         pb.finish_with_message(format!(
-                            "✅ {} → {}{}",
-                            filename, metadata.filename, compressed_msg
+                            "✅ {} → {}{}{}",
+                            filename, metadata.filename, compressed_msg, attributes_msg
                         ));
                     }
-                    success_count += 1;
+                    success_count.fetch_add(1, Ordering::Relaxed);
                 }
             }
             Err(e) => {
                 if let Some(ref pb) = file_pb {
                     pb.finish_with_message(format!("❌ {} - Error: {}", filename, e));
                 }
-                error_count += 1;
+                error_count.fetch_add(1, Ordering::Relaxed);
                 // Clean up temp file if it exists
                 let _ = fs::remove_file(&temp_output);
             }
         }
 
-        overall_pb.set_position((idx + 1) as u64);
-        overall_pb.set_message(format!("✅ {} / ❌ {}", success_count, error_count));
-    }
+        overall_pb.inc(1);
+        overall_pb.set_message(format_counts(&success_count, &error_count));
+        Ok(())
+    })?;
 
     let duration = start_time.elapsed();
+    let success_count = success_count.into_inner();
+    let error_count = error_count.into_inner();
     overall_pb.finish_with_message(format!("Done! ✅ {} / ❌ {}", success_count, error_count));
 
     // Print summary
@@ -637,8 +1299,128 @@ fn handle_decrypt(
     Ok(())
 }
 
+/// Run `task` on every item, at most `jobs` at a time
+///
+/// After the first error no new items are started; it is returned once the
+/// running ones have finished.
+fn run_jobs<T: Sync>(
+    items: &[T],
+    jobs: usize,
+    task: impl Fn(usize, &T) -> Result<()> + Sync,
+) -> Result<()> {
+    let next = AtomicUsize::new(0);
+    let first_error = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(idx) else {
+                    return;
+                };
+                if let Err(e) = task(idx, item) {
+                    next.store(items.len(), Ordering::Relaxed);
+                    first_error.lock().unwrap().get_or_insert(e);
+                    return;
+                }
+            });
+        }
+    });
+
+    match first_error.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Progress bar message for the success/error counters
+fn format_counts(success_count: &AtomicUsize, error_count: &AtomicUsize) -> String {
+    format!(
+        "✅ {} / ❌ {}",
+        success_count.load(Ordering::Relaxed),
+        error_count.load(Ordering::Relaxed)
+    )
+}
+
+/// Handle decrypt --range: decrypt a slice of one file to a file or stdout
+fn handle_decrypt_range(
+    paths: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    (offset, length): (u64, u64),
+    password: Option<String>,
+    keyfile: Option<PathBuf>,
+    identity: Option<PathBuf>,
+) -> Result<()> {
+    let [path] = paths.as_slice() else {
+        anyhow::bail!("--range works on a single file\n\n💡 Tip: Run decrypt once per file you need a range from.");
+    };
+    if !path.is_file() {
+        anyhow::bail!(
+            "File not found: {}\n\n💡 Tip: Make sure the .crat file exists.",
+            path.display()
+        );
+    }
+
+    let decryptor =
+        Decryptor::new().credentials(get_credentials(password, keyfile, identity, false)?);
+
+    // Only the chunks covering the range are read and authenticated
+    let start_time = Instant::now();
+    let written = match output {
+        Some(ref output_path) => {
+            // Decrypt to a temporary file first, so a wrong password or a
+            // tampered chunk leaves any existing output untouched
+            let file_name = output_path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or("range");
+            let temp_output = output_path.with_file_name(format!("temp_decrypt_{}", file_name));
+            let result = (|| -> Result<u64> {
+                let mut writer = BufWriter::new(File::create(&temp_output)?);
+                let written = decryptor.decrypt_range(path, offset, length, &mut writer)?;
+                writer.into_inner().map_err(|e| e.into_error())?;
+                fs::rename(&temp_output, output_path)?;
+                Ok(written)
+            })();
+            if result.is_err() {
+                let _ = fs::remove_file(&temp_output);
+            }
+            result?
+        }
+        None => decryptor.decrypt_range(path, offset, length, &mut io::stdout().lock())?,
+    };
+
+    // Status goes to stderr so stdout carries only the decrypted bytes
+    eprintln!(
+        "✅ Decrypted {} at offset {} in {:.2}s",
+        format_size(written),
+        offset,
+        start_time.elapsed().as_secs_f64()
+    );
+    if written < length {
+        eprintln!(
+            "   ⚠️  The range runs past the end of the file ({} bytes short)",
+            length - written
+        );
+    }
+
+    Ok(())
+}
+
 /// Handle inspect command
-fn handle_inspect(paths: Vec<PathBuf>) -> Result<()> {
+fn handle_inspect(
+    paths: Vec<PathBuf>,
+    password: Option<String>,
+    keyfile: Option<PathBuf>,
+    identity: Option<PathBuf>,
+) -> Result<()> {
+    // Credentials are optional: without them only public header fields are shown
+    let decryptor = if password.is_some() || keyfile.is_some() || identity.is_some() {
+        Some(Decryptor::new().credentials(get_credentials(password, keyfile, identity, false)?))
+    } else {
+        None
+    };
+
     for (idx, path) in paths.iter().enumerate() {
         if idx > 0 {
             println!("\n{}", "=".repeat(60));
@@ -646,7 +1428,12 @@ fn handle_inspect(paths: Vec<PathBuf>) -> Result<()> {
 
         println!("\n🔍 Inspecting: {}\n", path.display());
 
-        match inspect_file(path) {
+        let result = match decryptor {
+            Some(ref decryptor) => decryptor.inspect(path),
+            None => inspect_file(path),
+        };
+
+        match result {
             Ok(info) => {
                 print!("{}", info.display());
             }
@@ -663,21 +1450,25 @@ fn handle_inspect(paths: Vec<PathBuf>) -> Result<()> {
 }
 
 /// Handle keygen command
-fn handle_keygen(output: PathBuf, size: Option<usize>) -> Result<()> {
+fn handle_keygen(output: PathBuf, size: Option<usize>, identity: bool) -> Result<()> {
+    if identity {
+        return handle_identity_keygen(output);
+    }
+
     let size = size.unwrap_or(DEFAULT_KEYFILE_SIZE);
 
     println!("\n🔑 Generating key file...");
     println!("   Path: {}", output.display());
     println!("   Size: {} bytes ({} KB)", size, size / 1024);
 
-    if output.exists() {
-        if !confirm(
+    if output.exists()
+        && !confirm(
             &format!("Key file already exists at {:?}. Overwrite?", output),
             false,
-        )? {
-            println!("Operation cancelled.");
-            return Ok(());
-        }
+        )?
+    {
+        println!("Operation cancelled.");
+        return Ok(());
     }
 
     generate_keyfile(&output, Some(size))?;
@@ -689,12 +1480,223 @@ fn handle_keygen(output: PathBuf, size: Option<usize>) -> Result<()> {
     println!("   - Anyone with this file can decrypt your data");
     println!("   - If you lose it, you CANNOT decrypt your files");
     println!("\n💡 Usage:");
-    println!("   cryptocrate encrypt file.txt --keyfile {}", output.display());
-    println!("   cryptocrate decrypt file.txt.crat --keyfile {}", output.display());
+    println!(
+        "   cryptocrate encrypt file.txt --keyfile {}",
+        output.display()
+    );
+    println!(
+        "   cryptocrate decrypt file.txt.crat --keyfile {}",
+        output.display()
+    );
+
+    Ok(())
+}
+
+/// Generate an identity file and print its recipient
+fn handle_identity_keygen(output: PathBuf) -> Result<()> {
+    println!("\n🪪 Generating identity...");
+    println!("   Path: {}", output.display());
+
+    if output.exists()
+        && !confirm(
+            &format!("Identity file already exists at {:?}. Overwrite?", output),
+            false,
+        )?
+    {
+        println!("Operation cancelled.");
+        return Ok(());
+    }
+
+    let recipient = generate_identity(&output)?;
+
+    println!("\n✅ Identity generated successfully!");
+    println!("\n📬 Public key (share this freely):");
+    println!("   {}", recipient);
+    println!("\n⚠️  IMPORTANT:");
+    println!("   - The identity file is your SECRET key - never share it");
+    println!("   - Anyone with this file can decrypt files sent to your public key");
+    println!("   - If you lose it, you CANNOT decrypt those files");
+    println!("\n💡 Usage:");
+    println!("   cryptocrate encrypt file.txt --recipient {}", recipient);
+    println!(
+        "   cryptocrate decrypt file.txt.crat --identity {}",
+        output.display()
+    );
 
     Ok(())
 }
 
+/// Handle rekey command
+#[allow(clippy::too_many_arguments)]
+fn handle_rekey(
+    paths: Vec<PathBuf>,
+    password: Option<String>,
+    keyfile: Option<PathBuf>,
+    identity: Option<PathBuf>,
+    new_password: Option<String>,
+    new_keyfile: Option<PathBuf>,
+    yes: bool,
+    config: &Config,
+) -> Result<()> {
+    let argon2 = config.argon2_params();
+    argon2.validate()?;
+
+    let crates = collect_crates(&paths)?;
+
+    if crates.is_empty() {
+        println!("⚠️  No encrypted files found to rekey!");
+        return Ok(());
+    }
+
+    println!("\n📊 Rekey Summary:");
+    println!("   Files: {}", crates.len());
+    if keyfile.is_some() {
+        println!("   Current key file: ✅ will be used");
+    }
+    if new_keyfile.is_some() {
+        println!("   New key file: ✅ will be used");
+    }
+    println!();
+
+    if !yes
+        && !confirm(
+            "⚠️  The current password / key file will no longer open these files. Continue?",
+            true,
+        )?
+    {
+        println!("Operation cancelled.");
+        return Ok(());
+    }
+
+    println!("\n🔓 Current credentials");
+    let old = get_credentials(password, keyfile, identity, false)?;
+    println!("\n🔑 New credentials");
+    let new = get_credentials(new_password, new_keyfile, None, true)?;
+
+    let pb = ProgressBar::new(crates.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{bar:40.cyan/blue}] {pos}/{len} files ({msg})")
+            .unwrap()
+            .progress_chars("=>-"),
+    );
+
+    // Stage every file before replacing any, so a wrong password or a
+    // damaged file leaves the whole set untouched
+    let start_time = Instant::now();
+    let mut staged = Vec::with_capacity(crates.len());
+    for path in &crates {
+        pb.set_message(path.display().to_string());
+        match stage_rekey(path, &old, &new, &argon2) {
+            Ok(update) => staged.push(update),
+            Err(e) => {
+                pb.abandon();
+                staged.into_iter().for_each(StagedUpdate::discard);
+                anyhow::bail!("{}: {}\n\n💡 No files were changed.", path.display(), e);
+            }
+        }
+        pb.inc(1);
+    }
+
+    let mut committed = Vec::with_capacity(staged.len());
+    let mut updates = staged.into_iter();
+    while let Some(update) = updates.next() {
+        let path = update.path().to_path_buf();
+        if let Err(e) = update.commit() {
+            pb.abandon();
+            let skipped: Vec<PathBuf> = updates
+                .map(|update| {
+                    let path = update.path().to_path_buf();
+                    update.discard();
+                    path
+                })
+                .collect();
+            anyhow::bail!(
+                "{}: {}\n\n💡 Rekeyed (new credentials): {}\n   Not rekeyed (old credentials): {}",
+                path.display(),
+                e,
+                list_paths(&committed),
+                list_paths(&skipped),
+            );
+        }
+        committed.push(path);
+    }
+    pb.finish_with_message("done");
+
+    println!(
+        "\n✅ Rekeyed {} file(s) in {:.2}s",
+        crates.len(),
+        start_time.elapsed().as_secs_f64()
+    );
+    println!("   The old credentials no longer open them; other key slots are unchanged.");
+
+    Ok(())
+}
+
+/// Comma-separated paths for an error report
+fn list_paths(paths: &[PathBuf]) -> String {
+    if paths.is_empty() {
+        return "none".to_string();
+    }
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Handle slot command
+fn handle_slot(action: SlotAction, config: &Config) -> Result<()> {
+    match action {
+        SlotAction::List { path } => {
+            let slots = list_key_slots(&path)?;
+            println!("\n🗝️  Key slots of {}:\n", path.display());
+            for (index, slot) in slots.iter().enumerate() {
+                println!("   [{}] {} - {}", index, slot.kind, slot.kdf.description());
+            }
+        }
+        SlotAction::Add {
+            path,
+            password,
+            keyfile,
+            identity,
+            new_password,
+            new_keyfile,
+            recipient,
+        } => {
+            let argon2 = config.argon2_params();
+            argon2.validate()?;
+
+            println!("\n🔓 Current credentials for {}", path.display());
+            let existing = get_credentials(password, keyfile, identity, false)?;
+
+            let (index, kind) = if let Some(recipient) = recipient {
+                (
+                    add_recipient_slot(&path, &existing, &recipient)?,
+                    SlotKind::Recipient,
+                )
+            } else {
+                println!("\n🔑 Credentials for the new slot");
+                let new = get_credentials(new_password, new_keyfile, None, true)?;
+                (add_key_slot(&path, &existing, &new, &argon2)?, new.kind()?)
+            };
+            println!("\n✅ Added key slot [{}] ({})", index, kind);
+        }
+        SlotAction::Remove {
+            path,
+            slot,
+            password,
+            keyfile,
+            identity,
+        } => {
+            let credentials = get_credentials(password, keyfile, identity, false)?;
+            remove_key_slot(&path, &credentials, slot)?;
+            println!("\n✅ Removed key slot [{}] from {}", slot, path.display());
+        }
+    }
+    Ok(())
+}
+
 /// Handle config command
 fn handle_config(action: ConfigAction) -> Result<()> {
     match action {
@@ -712,14 +1714,14 @@ fn handle_config(action: ConfigAction) -> Result<()> {
                     .ok_or_else(|| anyhow::anyhow!("Could not determine user config directory"))?
             };
 
-            if path.exists() {
-                if !confirm(
+            if path.exists()
+                && !confirm(
                     &format!("Config file already exists at {:?}. Overwrite?", path),
                     false,
-                )? {
-                    println!("Operation cancelled.");
-                    return Ok(());
-                }
+                )?
+            {
+                println!("Operation cancelled.");
+                return Ok(());
             }
 
             let sample = Config::sample();
@@ -775,6 +1777,11 @@ fn handle_config(action: ConfigAction) -> Result<()> {
 }
 
 fn main() {
+    // Passwords and keys must never end up in a core dump
+    if let Err(e) = disable_core_dumps() {
+        eprintln!("⚠️  Warning: Could not disable core dumps: {}", e);
+    }
+
     let cli = Cli::parse();
 
     // Load configuration
@@ -792,32 +1799,110 @@ fn main() {
         Commands::Encrypt {
             paths,
             compress,
+            compression,
             output,
             password,
             keyfile,
+            plain_metadata,
+            algorithm,
+            recipients,
             delete,
             delete_mode,
+            jobs,
+            archive,
+            preserve,
             yes,
         } => handle_encrypt(
             paths,
             compress,
+            compression,
             output,
             password,
             keyfile,
+            plain_metadata,
+            algorithm,
+            recipients,
             delete,
             delete_mode,
+            jobs,
+            archive,
+            preserve,
             yes,
             &config,
         ),
         Commands::Decrypt {
             paths,
             output,
+            range,
             password,
             keyfile,
+            identity,
+            jobs,
+            same_owner,
+            no_same_owner,
             yes,
-        } => handle_decrypt(paths, output, password, keyfile, yes, &config),
-        Commands::Inspect { paths } => handle_inspect(paths),
-        Commands::Keygen { output, size } => handle_keygen(output, size),
+        } => {
+            // Like tar: restore ownership only as root unless told otherwise
+            let owner = if same_owner {
+                OwnerPolicy::Always
+            } else if no_same_owner {
+                OwnerPolicy::Never
+            } else {
+                OwnerPolicy::Auto
+            };
+            match range {
+                Some(range) => {
+                    handle_decrypt_range(paths, output, range, password, keyfile, identity)
+                }
+                None => handle_decrypt(
+                    paths, output, password, keyfile, identity, jobs, owner, yes, &config,
+                ),
+            }
+        }
+        Commands::Inspect {
+            paths,
+            password,
+            keyfile,
+            identity,
+        } => handle_inspect(paths, password, keyfile, identity),
+        Commands::List {
+            path,
+            password,
+            keyfile,
+            identity,
+        } => handle_list(path, password, keyfile, identity),
+        Commands::Extract {
+            path,
+            output,
+            password,
+            keyfile,
+            identity,
+            yes,
+        } => handle_extract(path, output, password, keyfile, identity, yes, &config),
+        Commands::Keygen {
+            output,
+            size,
+            identity,
+        } => handle_keygen(output, size, identity),
+        Commands::Rekey {
+            paths,
+            password,
+            keyfile,
+            identity,
+            new_password,
+            new_keyfile,
+            yes,
+        } => handle_rekey(
+            paths,
+            password,
+            keyfile,
+            identity,
+            new_password,
+            new_keyfile,
+            yes,
+            &config,
+        ),
+        Commands::Slot { action } => handle_slot(action, &config),
         Commands::Config { action } => handle_config(action),
     };
 
@@ -1,25 +1,102 @@
 //! File metadata preservation
 
-use std::fs;
+use std::fs::{self, File};
 use std::path::Path;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
-use crate::error::Result;
+use crate::compression::CompressionAlgorithm;
+use crate::error::{CrateError, Result};
+use crate::file_handler::attributes::{capture_attributes, FileAttributes};
+
+/// Record type: path relative to the folder that was encrypted
+const RECORD_RELATIVE_PATH: u8 = 1;
+/// Record type: Unix permission bits (4 bytes)
+const RECORD_MODE: u8 = 2;
+/// Record type: user and group id (4 + 4 bytes)
+const RECORD_OWNER: u8 = 3;
+/// Record type: extended attribute, name length (1 byte) + name + value
+const RECORD_XATTR: u8 = 4;
+/// Record type: symbolic link target
+const RECORD_SYMLINK: u8 = 5;
+/// Record type: modification time, seconds (8 bytes, signed) + nanoseconds (4 bytes)
+const RECORD_MODIFIED: u8 = 6;
+/// Record type: access time, same layout as `RECORD_MODIFIED`
+const RECORD_ACCESSED: u8 = 7;
+
+/// File times at full precision
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timestamps {
+    pub modified: Option<SystemTime>,
+    pub accessed: Option<SystemTime>,
+}
+
+impl Timestamps {
+    /// Set the recorded times on the file at `path`
+    pub fn apply_to<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        if self.modified.is_none() && self.accessed.is_none() {
+            return Ok(());
+        }
+        let mut times = fs::FileTimes::new();
+        if let Some(modified) = self.modified {
+            times = times.set_modified(modified);
+        }
+        if let Some(accessed) = self.accessed {
+            times = times.set_accessed(accessed);
+        }
+        File::options().write(true).open(path)?.set_times(times)?;
+        Ok(())
+    }
+}
 
 /// File metadata to preserve
 #[derive(Debug, Clone)]
 pub struct FileMetadata {
     pub filename: String,
     pub original_size: u64,
+    /// Modification time in whole seconds, as read by older versions
     pub modified_time: Option<u64>,
-    pub is_compressed: bool,
+    /// Algorithm the contents were compressed with
+    pub compression: CompressionAlgorithm,
+    /// `/`-separated path inside the encrypted folder, if any
+    pub relative_path: Option<String>,
+    /// Permissions, ownership, xattrs and symlink target, when captured
+    pub attributes: FileAttributes,
+    /// Modification and access time with nanoseconds
+    pub timestamps: Timestamps,
 }
 
 impl FileMetadata {
     /// Create metadata from a file path
-    pub fn from_file<P: AsRef<Path>>(path: P, is_compressed: bool) -> Result<Self> {
+    pub fn from_file<P: AsRef<Path>>(path: P, compression: CompressionAlgorithm) -> Result<Self> {
+        Self::capture(path, compression, false)
+    }
+
+    /// Whether the contents were compressed
+    pub fn is_compressed(&self) -> bool {
+        self.compression != CompressionAlgorithm::None
+    }
+    
+    /// Create metadata from a file path, optionally with its Unix attributes
+    ///
+    /// With `preserve_attributes`, a symbolic link is recorded as a link
+    /// (size 0) instead of being followed.
+    pub fn capture<P: AsRef<Path>>(
+        path: P,
+        compression: CompressionAlgorithm,
+        preserve_attributes: bool,
+    ) -> Result<Self> {
         let path = path.as_ref();
-        let metadata = fs::metadata(path)?;
+        let metadata = if preserve_attributes {
+            fs::symlink_metadata(path)
+        } else {
+            fs::metadata(path)
+        }
+        .map_err(|e| CrateError::FileNotFound(format!("{}: {}", path.display(), e)))?;
+        let attributes = if preserve_attributes {
+            capture_attributes(path)?
+        } else {
+            FileAttributes::default()
+        };
         
         let filename = path
             .file_name()
@@ -35,9 +112,15 @@ impl FileMetadata {
         
         Ok(Self {
             filename,
-            original_size: metadata.len(),
+            original_size: if attributes.is_symlink() { 0 } else { metadata.len() },
             modified_time,
-            is_compressed,
+            compression,
+            relative_path: None,
+            attributes,
+            timestamps: Timestamps {
+                modified: metadata.modified().ok(),
+                accessed: metadata.accessed().ok(),
+            },
         })
     }
     
@@ -58,8 +141,36 @@ impl FileMetadata {
         // Modified time (8 bytes, 0 if None)
         bytes.extend_from_slice(&self.modified_time.unwrap_or(0).to_le_bytes());
         
-        // Compression flag (1 byte)
-        bytes.push(if self.is_compressed { 1 } else { 0 });
+        // Compression algorithm (1 byte; older versions wrote 0 or 1)
+        bytes.push(self.compression.id());
+        
+        // Optional records: type (1 byte), length (2 bytes), data
+        if let Some(relative_path) = &self.relative_path {
+            push_record(&mut bytes, RECORD_RELATIVE_PATH, relative_path.as_bytes());
+        }
+        if let Some(mode) = self.attributes.mode {
+            push_record(&mut bytes, RECORD_MODE, &mode.to_le_bytes());
+        }
+        if let Some((uid, gid)) = self.attributes.owner {
+            let mut owner = uid.to_le_bytes().to_vec();
+            owner.extend_from_slice(&gid.to_le_bytes());
+            push_record(&mut bytes, RECORD_OWNER, &owner);
+        }
+        for (name, value) in &self.attributes.xattrs {
+            let mut xattr = vec![name.len() as u8];
+            xattr.extend_from_slice(name.as_bytes());
+            xattr.extend_from_slice(value);
+            push_record(&mut bytes, RECORD_XATTR, &xattr);
+        }
+        if let Some(target) = &self.attributes.symlink_target {
+            push_record(&mut bytes, RECORD_SYMLINK, target.as_bytes());
+        }
+        if let Some(modified) = self.timestamps.modified {
+            push_record(&mut bytes, RECORD_MODIFIED, &encode_time(modified));
+        }
+        if let Some(accessed) = self.timestamps.accessed {
+            push_record(&mut bytes, RECORD_ACCESSED, &encode_time(accessed));
+        }
         
         bytes
     }
@@ -67,7 +178,7 @@ impl FileMetadata {
     /// Deserialize metadata from bytes
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
         if bytes.len() < 19 {
-            return Err(crate::error::CrateError::InvalidFormat(
+            return Err(CrateError::InvalidFormat(
                 "Metadata too short".to_string(),
             ));
         }
@@ -79,8 +190,8 @@ impl FileMetadata {
         offset += 2;
         
         // Filename
-        if offset + filename_len > bytes.len() {
-            return Err(crate::error::CrateError::InvalidFormat(
+        if offset + filename_len + 17 > bytes.len() {
+            return Err(CrateError::InvalidFormat(
                 "Invalid filename length".to_string(),
             ));
         }
@@ -114,18 +225,126 @@ impl FileMetadata {
         let modified_time = if time_val == 0 { None } else { Some(time_val) };
         offset += 8;
         
-        // Compression flag
-        let is_compressed = bytes[offset] != 0;
+        // Compression algorithm
+        let compression = CompressionAlgorithm::from_id(bytes[offset])?;
+        offset += 1;
+        
+        // Records; unknown types are skipped so newer writers stay readable
+        let mut relative_path = None;
+        let mut attributes = FileAttributes::default();
+        let mut timestamps = Timestamps::default();
+        while offset < bytes.len() {
+            if offset + 3 > bytes.len() {
+                return Err(CrateError::InvalidFormat(
+                    "Truncated metadata record".to_string(),
+                ));
+            }
+            let record_type = bytes[offset];
+            let len = u16::from_le_bytes([bytes[offset + 1], bytes[offset + 2]]) as usize;
+            offset += 3;
+            if offset + len > bytes.len() {
+                return Err(CrateError::InvalidFormat(
+                    "Truncated metadata record".to_string(),
+                ));
+            }
+            let data = &bytes[offset..offset + len];
+            offset += len;
+            
+            match record_type {
+                RECORD_RELATIVE_PATH => relative_path = Some(record_string(data)?),
+                RECORD_MODE => attributes.mode = Some(u32::from_le_bytes(record_array(data)?)),
+                RECORD_OWNER => {
+                    let owner: [u8; 8] = record_array(data)?;
+                    attributes.owner = Some((
+                        u32::from_le_bytes(owner[..4].try_into().unwrap()),
+                        u32::from_le_bytes(owner[4..].try_into().unwrap()),
+                    ));
+                }
+                RECORD_XATTR => {
+                    let name_len = *data.first().ok_or_else(record_error)? as usize;
+                    if 1 + name_len > data.len() {
+                        return Err(record_error());
+                    }
+                    let name = record_string(&data[1..1 + name_len])?;
+                    attributes.xattrs.push((name, data[1 + name_len..].to_vec()));
+                }
+                RECORD_SYMLINK => attributes.symlink_target = Some(record_string(data)?),
+                RECORD_MODIFIED => timestamps.modified = Some(decode_time(data)?),
+                RECORD_ACCESSED => timestamps.accessed = Some(decode_time(data)?),
+                _ => {}
+            }
+        }
+        
+        // Files from older versions only have whole seconds
+        if timestamps.modified.is_none() {
+            timestamps.modified = modified_time.map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        }
         
         Ok(Self {
             filename,
             original_size,
             modified_time,
-            is_compressed,
+            compression,
+            relative_path,
+            attributes,
+            timestamps,
         })
     }
 }
 
+/// Seconds since the epoch (negative before it) and nanoseconds
+pub(crate) fn encode_time(time: SystemTime) -> [u8; 12] {
+    let (secs, nanos) = match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            match d.subsec_nanos() {
+                0 => (-(d.as_secs() as i64), 0),
+                nanos => (-(d.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    };
+    let mut bytes = [0u8; 12];
+    bytes[..8].copy_from_slice(&secs.to_le_bytes());
+    bytes[8..].copy_from_slice(&nanos.to_le_bytes());
+    bytes
+}
+
+pub(crate) fn decode_time(data: &[u8]) -> Result<SystemTime> {
+    let bytes: [u8; 12] = record_array(data)?;
+    let secs = i64::from_le_bytes(bytes[..8].try_into().unwrap());
+    let nanos = u32::from_le_bytes(bytes[8..].try_into().unwrap());
+    if nanos >= 1_000_000_000 {
+        return Err(record_error());
+    }
+    let whole = if secs >= 0 {
+        SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64))
+    } else {
+        SystemTime::UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+    };
+    whole
+        .and_then(|time| time.checked_add(Duration::from_nanos(nanos.into())))
+        .ok_or_else(record_error)
+}
+
+fn record_error() -> CrateError {
+    CrateError::InvalidFormat("Malformed metadata record".to_string())
+}
+
+fn record_string(data: &[u8]) -> Result<String> {
+    String::from_utf8(data.to_vec()).map_err(|_| record_error())
+}
+
+fn record_array<const N: usize>(data: &[u8]) -> Result<[u8; N]> {
+    data.try_into().map_err(|_| record_error())
+}
+
+fn push_record(bytes: &mut Vec<u8>, record_type: u8, data: &[u8]) {
+    bytes.push(record_type);
+    bytes.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(data);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -136,7 +355,10 @@ mod tests {
             filename: "test.txt".to_string(),
             original_size: 12345,
             modified_time: Some(1234567890),
-            is_compressed: true,
+            compression: CompressionAlgorithm::Zstd,
+            relative_path: None,
+            attributes: FileAttributes::default(),
+            timestamps: Timestamps::default(),
         };
         
         let bytes = metadata.to_bytes();
@@ -145,6 +367,65 @@ mod tests {
         assert_eq!(deserialized.filename, metadata.filename);
         assert_eq!(deserialized.original_size, metadata.original_size);
         assert_eq!(deserialized.modified_time, metadata.modified_time);
-        assert_eq!(deserialized.is_compressed, metadata.is_compressed);
+        assert_eq!(deserialized.compression, metadata.compression);
+        assert_eq!(deserialized.relative_path, None);
+        // Without a precise record, the whole seconds are used
+        assert_eq!(
+            deserialized.timestamps.modified,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1234567890))
+        );
+    }
+
+    #[test]
+    fn test_metadata_records() {
+        let metadata = FileMetadata {
+            filename: "README.md".to_string(),
+            original_size: 42,
+            modified_time: None,
+            compression: CompressionAlgorithm::None,
+            relative_path: Some("docs/guide/README.md".to_string()),
+            attributes: FileAttributes {
+                mode: Some(0o4755),
+                owner: Some((1000, 100)),
+                xattrs: vec![("user.origin".to_string(), b"https://example.com".to_vec())],
+                symlink_target: Some("../shared/README.md".to_string()),
+                skipped_xattrs: Vec::new(),
+            },
+            timestamps: Timestamps {
+                modified: Some(SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789)),
+                accessed: Some(SystemTime::UNIX_EPOCH - Duration::new(86_400, 1)),
+            },
+        };
+
+        // An unknown record from a newer writer is skipped
+        let mut bytes = metadata.to_bytes();
+        push_record(&mut bytes, 0xEE, b"future");
+        let deserialized = FileMetadata::from_bytes(&bytes).unwrap();
+        assert_eq!(deserialized.relative_path.as_deref(), Some("docs/guide/README.md"));
+        assert_eq!(deserialized.attributes, metadata.attributes);
+        assert_eq!(deserialized.timestamps, metadata.timestamps);
+
+        // Truncated input is an error, not a panic
+        for len in 0..bytes.len() {
+            let _ = FileMetadata::from_bytes(&bytes[..len]);
+        }
+        assert!(FileMetadata::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_timestamps_applied() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("old.txt");
+        fs::write(&path, b"content").unwrap();
+
+        let timestamps = Timestamps {
+            modified: Some(SystemTime::UNIX_EPOCH + Duration::new(1_000_000_000, 987_654_321)),
+            accessed: Some(SystemTime::UNIX_EPOCH + Duration::new(1_000_000_500, 5)),
+        };
+        timestamps.apply_to(&path).unwrap();
+
+        let captured = FileMetadata::from_file(&path, CompressionAlgorithm::None).unwrap();
+        assert_eq!(captured.timestamps, timestamps);
+        assert_eq!(captured.modified_time, Some(1_000_000_000));
     }
 }
@@ -0,0 +1,248 @@
+//! Multi-threaded chunk pipeline for streaming mode
+//!
+//! A reader thread splits the input into segments, worker threads seal or
+//! open them independently, and the calling thread writes the results back
+//! in order. Every chunk's nonce depends only on its index, so the output is
+//! byte-for-byte what the single-threaded path produces.
+//!
+//! At most `2 * threads` segments are in flight at once: the reader takes a
+//! credit per segment and the writer hands it back once the segment is
+//! written, so memory stays bounded even if the output is slow.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::error::{CrateError, Result};
+use crate::secret::Zeroizing;
+use crate::streaming::{read_segment, ChunkOpener, ChunkSealer};
+
+/// A segment read from the input: index, bytes, and whether it is the last one
+type Segment = (u64, Zeroizing<Vec<u8>>, bool);
+
+/// Number of worker threads to use for a `threads` setting (0 = one per CPU)
+pub fn resolve_threads(threads: usize) -> usize {
+    match threads {
+        0 => thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1),
+        n => n,
+    }
+}
+
+/// Encrypt `reader` into `writer` as `chunk_size`-byte chunks on `threads` workers
+pub(crate) fn seal_chunks<R: BufRead + Send, W: Write>(
+    reader: R,
+    writer: &mut W,
+    sealer: &ChunkSealer,
+    chunk_size: usize,
+    threads: usize,
+) -> Result<()> {
+    run(reader, writer, chunk_size, threads, |index, chunk, last| {
+        let counter = u32::try_from(index)
+            .map_err(|_| CrateError::Encryption("File too large for streaming mode".to_string()))?;
+        sealer.seal_at(counter, chunk, last)
+    })
+}
+
+/// Decrypt and verify `segment_length`-byte segments from `reader` on `threads` workers
+///
+/// Plaintext is written strictly in order, and only once it is authenticated.
+pub(crate) fn open_chunks<R: BufRead + Send, W: Write>(
+    reader: R,
+    writer: &mut W,
+    opener: &ChunkOpener,
+    segment_length: usize,
+    threads: usize,
+) -> Result<()> {
+    run(
+        reader,
+        writer,
+        segment_length,
+        threads,
+        |index, segment, last| {
+            let counter = u32::try_from(index)
+                .map_err(|_| CrateError::Decryption("Too many chunks".to_string()))?;
+            opener.open_at(counter, segment, last)
+        },
+    )
+}
+
+fn run<R, W, T, F>(
+    mut reader: R,
+    writer: &mut W,
+    segment_length: usize,
+    threads: usize,
+    process: F,
+) -> Result<()>
+where
+    R: BufRead + Send,
+    W: Write,
+    T: AsRef<[u8]> + Send,
+    F: Fn(u64, &[u8], bool) -> Result<T> + Sync,
+{
+    let threads = threads.max(1);
+    let window = 2 * threads;
+
+    let (segment_tx, segment_rx) = mpsc::sync_channel::<Segment>(window);
+    let segment_rx = Mutex::new(segment_rx);
+    let (result_tx, result_rx) = mpsc::channel::<(u64, Result<T>)>();
+    let (credit_tx, credit_rx) = mpsc::sync_channel::<()>(window);
+    for _ in 0..window {
+        credit_tx
+            .send(())
+            .expect("credit channel has room for the window");
+    }
+
+    thread::scope(|scope| {
+        let read_errors = result_tx.clone();
+        scope.spawn(move || {
+            let mut index = 0;
+            // Stops once the last segment is sent or the writer gives up
+            while credit_rx.recv().is_ok() {
+                let mut segment = Zeroizing::new(vec![0u8; segment_length]);
+                match read_segment(&mut reader, &mut segment) {
+                    Ok((len, last)) => {
+                        segment.truncate(len);
+                        if segment_tx.send((index, segment, last)).is_err() || last {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        let _ = read_errors.send((index, Err(e)));
+                        return;
+                    }
+                }
+                index += 1;
+            }
+        });
+
+        for _ in 0..threads {
+            let (segments, results, process) = (&segment_rx, result_tx.clone(), &process);
+            scope.spawn(move || loop {
+                let next = segments.lock().unwrap().recv();
+                let Ok((index, segment, last)) = next else {
+                    return;
+                };
+                if results
+                    .send((index, process(index, &segment, last)))
+                    .is_err()
+                {
+                    return;
+                }
+            });
+        }
+        drop(result_tx);
+
+        write_in_order(writer, result_rx, credit_tx)
+    })
+}
+
+/// Write results as their turn comes, returning a credit for each
+///
+/// Takes the channels by value: returning early drops them, which stops the
+/// reader and the workers.
+fn write_in_order<W: Write, T: AsRef<[u8]>>(
+    writer: &mut W,
+    results: Receiver<(u64, Result<T>)>,
+    credits: SyncSender<()>,
+) -> Result<()> {
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+
+    for (index, result) in results {
+        pending.insert(index, result);
+        while let Some(result) = pending.remove(&next) {
+            writer.write_all(result?.as_ref())?;
+            next += 1;
+            let _ = credits.send(());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::CompressionAlgorithm;
+    use crate::crypto::encryption::{test_options, EncryptOptions};
+    use crate::crypto::keyslot::Credentials;
+    use crate::format::{read_header, TAG_LENGTH};
+    use crate::metadata::FileMetadata;
+    use crate::streaming::{begin_crate, open_crate, CHUNK_SIZE};
+
+    fn fixture() -> (EncryptOptions, Credentials, FileMetadata) {
+        let options = test_options();
+        let metadata = FileMetadata {
+            filename: "big.log".to_string(),
+            original_size: 0,
+            modified_time: None,
+            compression: CompressionAlgorithm::None,
+            relative_path: None,
+            attributes: Default::default(),
+            timestamps: Default::default(),
+        };
+        (options, Credentials::password("parallel"), metadata)
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let (options, credentials, metadata) = fixture();
+
+        for size in [0, CHUNK_SIZE, 5 * CHUNK_SIZE + 77] {
+            let data: Vec<u8> = (0..size).map(|i| (i % 211) as u8).collect();
+            let (preamble, mut sealer) = begin_crate(&credentials, &options, &metadata).unwrap();
+
+            let mut parallel = preamble.clone();
+            seal_chunks(&data[..], &mut parallel, &sealer, CHUNK_SIZE, 4).unwrap();
+
+            let mut sequential = preamble;
+            let mut chunks = data.chunks(CHUNK_SIZE).peekable();
+            if chunks.peek().is_none() {
+                sequential.extend(sealer.seal(&[], true).unwrap());
+            }
+            while let Some(chunk) = chunks.next() {
+                sequential.extend(sealer.seal(chunk, chunks.peek().is_none()).unwrap());
+            }
+            assert_eq!(parallel, sequential);
+
+            let mut input = &parallel[..];
+            let (header, metadata_block) = read_header(&mut input).unwrap();
+            let (_, opener) = open_crate(&header, &metadata_block, &credentials).unwrap();
+            let mut decrypted = Vec::new();
+            let segment_length = CHUNK_SIZE + TAG_LENGTH;
+            open_chunks(input, &mut decrypted, &opener, segment_length, 3).unwrap();
+            assert_eq!(decrypted, data);
+        }
+    }
+
+    #[test]
+    fn test_parallel_reports_first_bad_chunk() {
+        let (options, credentials, metadata) = fixture();
+
+        let data = vec![0x42u8; 6 * CHUNK_SIZE];
+        let (mut encrypted, sealer) = begin_crate(&credentials, &options, &metadata).unwrap();
+        let payload_start = encrypted.len();
+        seal_chunks(&data[..], &mut encrypted, &sealer, CHUNK_SIZE, 4).unwrap();
+
+        // Damage chunks 2 and 4: only the plaintext before chunk 2 may come out
+        let segment_length = CHUNK_SIZE + TAG_LENGTH;
+        encrypted[payload_start + 2 * segment_length + 1] ^= 1;
+        encrypted[payload_start + 4 * segment_length + 1] ^= 1;
+
+        let mut input = &encrypted[..];
+        let (header, metadata_block) = read_header(&mut input).unwrap();
+        let (_, opener) = open_crate(&header, &metadata_block, &credentials).unwrap();
+        let mut decrypted = Vec::new();
+        let result = open_chunks(input, &mut decrypted, &opener, segment_length, 4);
+
+        match result {
+            Err(CrateError::AuthenticationFailed(message)) => assert!(message.contains("chunk 2")),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        assert_eq!(decrypted.len(), 2 * CHUNK_SIZE);
+    }
+}
@@ -0,0 +1,235 @@
+//! Secret-holding buffers
+//!
+//! Keys, passwords and key file hashes are kept in `Secret`: the value lives
+//! on the heap, its memory is locked so it is never written to swap, and it
+//! is zeroed when dropped. Larger buffers such as plaintext use `Zeroizing`,
+//! which zeroes on drop but is not locked (the lockable amount is small).
+//!
+//! Locks cover whole pages, and small secrets often share one, so locked
+//! pages are reference-counted: a page is only unlocked once no live secret
+//! is left on it.
+
+use std::fmt;
+use std::io;
+use std::ops::{Deref, DerefMut};
+#[cfg(unix)]
+use std::collections::BTreeMap;
+#[cfg(unix)]
+use std::ops::Range;
+#[cfg(unix)]
+use std::sync::{Mutex, OnceLock};
+use zeroize::Zeroize;
+
+pub use zeroize::Zeroizing;
+
+use crate::format::KEY_LENGTH;
+
+/// A 256-bit key
+pub type SecretKey = Secret<[u8; KEY_LENGTH]>;
+
+/// A password
+pub type SecretString = Secret<String>;
+
+/// A value that is memory-locked while alive and zeroed on drop
+///
+/// Locking is best effort: if the `RLIMIT_MEMLOCK` budget is exhausted the
+/// value is still zeroed on drop, it just may be swapped out meanwhile.
+pub struct Secret<T: Zeroize + AsRef<[u8]>> {
+    value: Box<T>,
+    locked: bool,
+}
+
+impl<T: Zeroize + AsRef<[u8]>> Secret<T> {
+    /// Move `value` into locked memory
+    pub fn new(value: T) -> Self {
+        let value = Box::new(value);
+        let locked = lock((*value).as_ref());
+        Self { value, locked }
+    }
+}
+
+impl<const N: usize> Secret<[u8; N]> {
+    /// An all-zero buffer, to be filled in place
+    pub fn zeroed() -> Self {
+        Self::new([0u8; N])
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> Drop for Secret<T> {
+    fn drop(&mut self) {
+        // Strings are zeroed up to their capacity, so note the range first
+        let bytes = (*self.value).as_ref();
+        let (ptr, len) = (bytes.as_ptr(), bytes.len());
+        self.value.zeroize();
+        if self.locked {
+            unlock(ptr, len);
+        }
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> Deref for Secret<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+// Only fixed-size buffers are mutable: a growing `String` would move out of
+// the locked pages
+impl<const N: usize> DerefMut for Secret<[u8; N]> {
+    fn deref_mut(&mut self) -> &mut [u8; N] {
+        &mut self.value
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]> + Clone> Clone for Secret<T> {
+    fn clone(&self) -> Self {
+        Self::new((*self.value).clone())
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Self::new(value.to_string())
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Number of live secrets on each locked page, by page number
+#[cfg(unix)]
+static LOCKED_PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    static PAGE_SIZE: OnceLock<usize> = OnceLock::new();
+    *PAGE_SIZE.get_or_init(|| {
+        // SAFETY: plain system call
+        let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        usize::try_from(size).ok().filter(|&size| size > 0).unwrap_or(4096)
+    })
+}
+
+/// Page numbers covering `len` bytes at `ptr`
+#[cfg(unix)]
+fn pages(ptr: *const u8, len: usize) -> Range<usize> {
+    let page_size = page_size();
+    let start = ptr as usize;
+    start / page_size..(start + len).div_ceil(page_size)
+}
+
+#[cfg(unix)]
+fn lock(bytes: &[u8]) -> bool {
+    if bytes.is_empty() {
+        return false;
+    }
+
+    // Held across mlock so a concurrent unlock cannot release the pages in between
+    let mut locked_pages = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    // SAFETY: the range is a live allocation owned by the caller
+    if unsafe { libc::mlock(bytes.as_ptr().cast(), bytes.len()) } != 0 {
+        return false;
+    }
+    for page in pages(bytes.as_ptr(), bytes.len()) {
+        *locked_pages.entry(page).or_insert(0) += 1;
+    }
+    true
+}
+
+#[cfg(unix)]
+fn unlock(ptr: *const u8, len: usize) {
+    let page_size = page_size();
+    let mut locked_pages = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    for page in pages(ptr, len) {
+        let Some(count) = locked_pages.get_mut(&page) else {
+            continue;
+        };
+        *count -= 1;
+        if *count == 0 {
+            locked_pages.remove(&page);
+            // SAFETY: munlock only changes page attributes, and no live
+            // secret is left on this page
+            unsafe {
+                libc::munlock((page * page_size) as *const libc::c_void, page_size);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_bytes: &[u8]) -> bool {
+    false
+}
+
+#[cfg(not(unix))]
+fn unlock(_ptr: *const u8, _len: usize) {}
+
+/// Stop the process from writing core dumps, which would contain any live secrets
+pub fn disable_core_dumps() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: plain system call with a valid pointer
+        if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    // Also blocks ptrace attachment by other processes of the same user
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: plain system call
+        if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_access() {
+        let mut key = SecretKey::zeroed();
+        key[0] = 7;
+        assert_eq!(key[0], 7);
+        assert_eq!(*key.clone(), *key);
+
+        let password = SecretString::from("hunter2");
+        assert_eq!(password.as_str(), "hunter2");
+        assert_eq!(format!("{:?}", password), "Secret(<redacted>)");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shared_pages_stay_locked() {
+        let first = SecretKey::zeroed();
+        let second = SecretKey::zeroed();
+        if !first.locked || !second.locked {
+            // No RLIMIT_MEMLOCK budget in this environment
+            return;
+        }
+
+        let mut second_pages = pages(second.as_ptr(), KEY_LENGTH);
+        drop(first);
+        let locked_pages = LOCKED_PAGES.lock().unwrap();
+        assert!(second_pages.all(|page| locked_pages.contains_key(&page)));
+    }
+}
@@ -28,7 +28,7 @@ impl SecureDeleteMode {
     pub fn passes(&self) -> u32 {
         match self {
             SecureDeleteMode::Quick => 1,
-            SecureDeleteMode::Standard => 3,
+            SecureDeleteMode::Standard => DEFAULT_OVERWRITE_PASSES,
             SecureDeleteMode::Paranoid => 7,
         }
     }
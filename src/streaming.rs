@@ -2,22 +2,43 @@
 //!
 //! Processes files in chunks to avoid loading entire file into memory.
 //! Useful for files larger than 1 GB.
+//!
+//! The payload is split into `CHUNK_SIZE` segments, each sealed separately
+//! with the file's AEAD algorithm (the STREAM construction). Every segment gets its own
+//! nonce, derived from the header nonce, a 32-bit segment counter and a
+//! last-segment flag, so reordered, dropped or truncated segments fail
+//! authentication. Every segment also authenticates the header and metadata
+//! as associated data. Memory use stays bounded by a single chunk.
+//!
+//! `CrateWriter` and `CrateReader` implement this over any `Write`/`Read`;
+//! the file functions below are thin wrappers around them, or hand the
+//! chunks to a pool of worker threads when more than one is configured.
+//!
+//! With compression on, the plaintext is first turned into a single
+//! compressed stream (zstd, lz4, xz or brotli, as recorded in the metadata)
+//! and that stream is what gets chunked (`FLAG_COMPRESSED_STREAM`).
+//! Compressed crates can be read front to back, but not seeked.
 
-use aes_gcm::{
-    aead::{Aead, KeyInit},
-    Aes256Gcm, Nonce,
-};
+use std::borrow::Cow;
 use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::thread;
 
-use crate::crypto::key_derivation::derive_key;
+use crate::compression::{
+    read_sample, CompressingReader, CompressionAlgorithm, Decoder, StreamCompressor,
+};
+use crate::crypto::cipher::Cipher;
+use crate::crypto::encryption::{open_metadata, seal_metadata, EncryptOptions, MAX_DECOMPRESSED_SIZE};
+use crate::crypto::keyslot::{create_file_key, unlock, Credentials};
 use crate::error::{CrateError, Result};
 use crate::format::*;
 use crate::metadata::FileMetadata;
+use crate::parallel::{self, resolve_threads};
+use crate::secret::Zeroizing;
 
 /// Chunk size for streaming (1 MB)
-const CHUNK_SIZE: usize = 1024 * 1024;
+pub const CHUNK_SIZE: usize = 1024 * 1024;
 
 /// Threshold for using streaming mode (100 MB)
 pub const STREAMING_THRESHOLD: u64 = 100 * 1024 * 1024;
@@ -28,46 +49,733 @@ pub fn should_use_streaming<P: AsRef<Path>>(path: P) -> Result<bool> {
     Ok(metadata.len() > STREAMING_THRESHOLD)
 }
 
-/// Encrypt a large file using streaming
+/// Derive the nonce for a single segment
+///
+/// The big-endian counter and the last-segment flag are XORed into the
+/// trailing five bytes of the random header nonce.
+fn chunk_nonce(base: &[u8], counter: u32, last: bool) -> Vec<u8> {
+    let mut nonce = base.to_vec();
+    let end = nonce.len();
+    let counter_bytes = counter.to_be_bytes();
+    for (i, byte) in counter_bytes.iter().enumerate() {
+        nonce[end - 5 + i] ^= byte;
+    }
+    if last {
+        nonce[end - 1] ^= 1;
+    }
+    nonce
+}
+
+/// Fill `buf` from `reader`, stopping early only at end of input
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+/// Read the next segment into `buf`, returning its length and whether it is the last one
+pub(crate) fn read_segment<R: BufRead>(reader: &mut R, buf: &mut [u8]) -> Result<(usize, bool)> {
+    let len = read_full(reader, buf)?;
+    let last = len < buf.len() || reader.fill_buf()?.is_empty();
+    Ok((len, last))
+}
+
+/// Header, metadata block and chunk sealer for a new chunked crate
+///
+/// Runs the key derivation, so async callers move it to a blocking thread.
+pub(crate) fn begin_crate(
+    credentials: &Credentials,
+    options: &EncryptOptions,
+    metadata: &FileMetadata,
+) -> Result<(Vec<u8>, ChunkSealer)> {
+    begin_crate_with_flags(credentials, options, metadata, 0)
+}
+
+/// `begin_crate`, with extra header `flags` (such as `FLAG_ARCHIVE`)
+///
+/// With `options.compression` the header announces a compressed stream; the
+/// caller is responsible for actually compressing what it seals.
+pub(crate) fn begin_crate_with_flags(
+    credentials: &Credentials,
+    options: &EncryptOptions,
+    metadata: &FileMetadata,
+    flags: u16,
+) -> Result<(Vec<u8>, ChunkSealer)> {
+    // Create header with a random nonce and file key
+    let nonce_bytes = options.algorithm.random_nonce();
+    let mut header = FileHeader::chunked(
+        options.algorithm,
+        KdfDescriptor::KeySlots,
+        nonce_bytes,
+        CHUNK_SIZE as u32,
+        0,
+    );
+    header.flags |= flags;
+    if options.encrypt_metadata {
+        header.flags |= FLAG_ENCRYPTED_METADATA;
+    }
+    if options.compression.is_enabled() {
+        header.flags |= FLAG_COMPRESSED_STREAM;
+    }
+    let key = create_file_key(&mut header, credentials, &options.recipients, &options.argon2)?;
+
+    // Create cipher
+    let cipher = Cipher::new(options.algorithm, &key)?;
+
+    // Create the (optionally encrypted) metadata block
+    let metadata = FileMetadata {
+        compression: options.compression.algorithm,
+        ..metadata.clone()
+    };
+    let metadata_block = seal_metadata(&cipher, &mut header, &metadata.to_bytes())?;
+
+    let mut preamble = header.to_bytes();
+    preamble.extend_from_slice(&metadata_block);
+
+    // Bind header and metadata into every chunk
+    let aad = header.associated_data(&metadata_block);
+
+    let sealer = ChunkSealer {
+        cipher,
+        nonce: header.nonce,
+        aad,
+        counter: 0,
+    };
+    Ok((preamble, sealer))
+}
+
+/// Unlock the file key for a parsed header and open its metadata
+///
+/// Runs the key derivation, so async callers move it to a blocking thread.
+pub(crate) fn open_crate(
+    header: &FileHeader,
+    metadata_block: &[u8],
+    credentials: &Credentials,
+) -> Result<(FileMetadata, ChunkOpener)> {
+    // Unwrap (or derive) the decryption key
+    let key = unlock(header, credentials)?;
+
+    // Create cipher
+    let cipher = Cipher::new(header.algorithm, &key)?;
+
+    let metadata = open_metadata(&cipher, header, metadata_block)?;
+
+    let opener = ChunkOpener {
+        cipher,
+        nonce: header.nonce.clone(),
+        aad: header.associated_data(metadata_block),
+        counter: 0,
+        key_verified: header.kdf == KdfDescriptor::KeySlots,
+    };
+    Ok((metadata, opener))
+}
+
+/// Seals the chunks of one crate
+pub(crate) struct ChunkSealer {
+    cipher: Cipher,
+    nonce: Vec<u8>,
+    aad: Vec<u8>,
+    counter: u32,
+}
+
+impl ChunkSealer {
+    /// Encrypt the next chunk
+    pub(crate) fn seal(&mut self, chunk: &[u8], last: bool) -> Result<Vec<u8>> {
+        let ciphertext = self.seal_at(self.counter, chunk, last)?;
+
+        if !last {
+            self.counter = self.counter.checked_add(1).ok_or_else(|| {
+                CrateError::Encryption("File too large for streaming mode".to_string())
+            })?;
+        }
+        Ok(ciphertext)
+    }
+
+    /// Encrypt chunk number `counter`
+    ///
+    /// Chunks only depend on their position, so they can be sealed in any
+    /// order and on any thread.
+    pub(crate) fn seal_at(&self, counter: u32, chunk: &[u8], last: bool) -> Result<Vec<u8>> {
+        let nonce = chunk_nonce(&self.nonce, counter, last);
+        self.cipher.encrypt(&nonce, chunk, &self.aad)
+    }
+}
+
+/// Opens the chunks of one crate
+pub(crate) struct ChunkOpener {
+    cipher: Cipher,
+    nonce: Vec<u8>,
+    aad: Vec<u8>,
+    counter: u32,
+    /// A key slot already proved the credentials right
+    key_verified: bool,
+}
+
+impl ChunkOpener {
+    /// Decrypt and verify the next chunk
+    pub(crate) fn open(&mut self, ciphertext: &[u8], last: bool) -> Result<Zeroizing<Vec<u8>>> {
+        let plaintext = self.open_at(self.counter, ciphertext, last)?;
+
+        if !last {
+            self.counter = self
+                .counter
+                .checked_add(1)
+                .ok_or_else(|| CrateError::Decryption("Too many chunks".to_string()))?;
+        }
+        Ok(plaintext)
+    }
+
+    /// Decrypt and verify chunk number `counter`, on any thread
+    pub(crate) fn open_at(
+        &self,
+        counter: u32,
+        ciphertext: &[u8],
+        last: bool,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        if ciphertext.len() < TAG_LENGTH {
+            return Err(CrateError::AuthenticationFailed(
+                "encrypted data is truncated".to_string(),
+            ));
+        }
+
+        let nonce = chunk_nonce(&self.nonce, counter, last);
+        self.cipher
+            .decrypt(&nonce, ciphertext, &self.aad)
+            .map_err(|_| {
+                if counter == 0 && !self.key_verified {
+                    // Without key slots, the first chunk failing is almost
+                    // always a wrong password
+                    CrateError::InvalidPassword
+                } else {
+                    CrateError::AuthenticationFailed(format!(
+                        "chunk {} was truncated, reordered or tampered with",
+                        counter
+                    ))
+                }
+            })
+    }
+
+    /// Continue with chunk `counter`, for random access
+    pub(crate) fn seek(&mut self, counter: u32) {
+        self.counter = counter;
+    }
+
+    /// Decrypt a single-shot payload: the whole ciphertext carries one tag
+    pub(crate) fn open_single_shot(
+        &self,
+        ciphertext: &[u8],
+        metadata: &FileMetadata,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        let plaintext = self
+            .cipher
+            .decrypt(&self.nonce, ciphertext, &self.aad)
+            .map_err(|_| {
+                if self.key_verified {
+                    CrateError::AuthenticationFailed(
+                        "encrypted data was truncated or tampered with".to_string(),
+                    )
+                } else {
+                    CrateError::InvalidPassword
+                }
+            })?;
+
+        if metadata.is_compressed() {
+            Ok(Zeroizing::new(
+                metadata
+                    .compression
+                    .decompress(&plaintext, MAX_DECOMPRESSED_SIZE)?,
+            ))
+        } else {
+            Ok(plaintext)
+        }
+    }
+}
+
+/// Encrypts a plaintext stream into a `.crat` stream
+///
+/// Plaintext is buffered and sealed one `CHUNK_SIZE` chunk at a time, after
+/// going through a compressed stream if `options.compression` is set. A chunk
+/// is only known to be the last one once the writer is finished, so
+/// `finish` must be called: dropping the writer leaves a truncated stream
+/// that will fail authentication.
 ///
-/// Note: Streaming mode does NOT support compression.
-/// For compressed encryption, the entire file must be loaded into memory.
+/// ```no_run
+/// use std::io::Write;
+/// use cryptocrate::{CompressionAlgorithm, CrateWriter, Credentials, EncryptOptions, FileMetadata};
+///
+/// # fn main() -> cryptocrate::Result<()> {
+/// let metadata = FileMetadata {
+///     filename: "dump.sql".to_string(),
+///     original_size: 0,
+///     modified_time: None,
+///     compression: CompressionAlgorithm::None,
+///     relative_path: None,
+///     attributes: Default::default(),
+///     timestamps: Default::default(),
+/// };
+/// let mut writer = CrateWriter::new(
+///     Vec::new(),
+///     &Credentials::password("secret"),
+///     &EncryptOptions::default(),
+///     &metadata,
+/// )?;
+/// writer.write_all(b"INSERT INTO ...")?;
+/// let encrypted: Vec<u8> = writer.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct CrateWriter<W: Write> {
+    inner: W,
+    sealer: ChunkSealer,
+    chunk: Zeroizing<Vec<u8>>,
+    compressor: Option<StreamCompressor>,
+}
+
+impl<W: Write> CrateWriter<W> {
+    /// Write the header and metadata for a new crate to `inner`
+    ///
+    /// `metadata.compression` is ignored; `options.compression` decides.
+    pub fn new(
+        inner: W,
+        credentials: &Credentials,
+        options: &EncryptOptions,
+        metadata: &FileMetadata,
+    ) -> Result<Self> {
+        Self::with_flags(inner, credentials, options, metadata, 0)
+    }
+
+    /// `new`, with extra header `flags`
+    pub(crate) fn with_flags(
+        mut inner: W,
+        credentials: &Credentials,
+        options: &EncryptOptions,
+        metadata: &FileMetadata,
+        flags: u16,
+    ) -> Result<Self> {
+        let (preamble, sealer) = begin_crate_with_flags(credentials, options, metadata, flags)?;
+        inner.write_all(&preamble)?;
+
+        let compressor = if options.compression.is_enabled() {
+            Some(StreamCompressor::new(options.compression)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            inner,
+            sealer,
+            chunk: Zeroizing::new(Vec::with_capacity(CHUNK_SIZE)),
+            compressor,
+        })
+    }
+
+    /// Seal the final chunk, flush, and return the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        if let Some(compressor) = self.compressor.take() {
+            compressor.finish(|compressed| self.buffer(compressed))?;
+        }
+        self.seal_chunk(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Add `data` to the chunk being filled, sealing full chunks along the way
+    fn buffer(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            // A full chunk is held back until more data shows it is not the last
+            if self.chunk.len() == CHUNK_SIZE {
+                self.seal_chunk(false)?;
+            }
+            let len = data.len().min(CHUNK_SIZE - self.chunk.len());
+            self.chunk.extend_from_slice(&data[..len]);
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// Encrypt and write out the buffered chunk
+    fn seal_chunk(&mut self, last: bool) -> Result<()> {
+        let ciphertext = self.sealer.seal(&self.chunk, last)?;
+        self.inner.write_all(&ciphertext)?;
+        self.chunk.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for CrateWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(mut compressor) = self.compressor.take() {
+            let result = compressor.compress(buf, |compressed| self.buffer(compressed));
+            self.compressor = Some(compressor);
+            result?;
+            return Ok(buf.len());
+        }
+
+        // A full chunk is held back until more data shows it is not the last
+        if self.chunk.len() == CHUNK_SIZE && !buf.is_empty() {
+            self.seal_chunk(false)?;
+        }
+
+        let len = buf.len().min(CHUNK_SIZE - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    /// Flushes sealed chunks only; the chunk being filled stays buffered
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a `.crat` stream, yielding only authenticated plaintext
+///
+/// Chunked payloads are verified one chunk at a time as they are read.
+/// Single-shot payloads carry one tag for the whole file, so they are
+/// decrypted (and decompressed) in memory when the reader is created.
+///
+/// Over a `Seek`able input the reader is seekable too: only the chunks
+/// covering the bytes actually read are fetched and authenticated. Chunked
+/// crates written with compression are decompressed as they are read, and
+/// cannot be seeked.
+///
+/// Errors surface from `read` as `io::Error`s that convert back into the
+/// original `CrateError` with `?`.
+pub struct CrateReader<R: Read> {
+    payload: Payload<R>,
+}
+
+/// Authenticated chunks, decompressed on the way out if the crate is compressed
+enum Payload<R: Read> {
+    Plain(ChunkReader<R>),
+    Compressed(Decoder<ChunkReader<R>>),
+}
+
+impl<R: Read> CrateReader<R> {
+    /// Read the header and metadata from `inner` and unlock the file key
+    pub fn new(inner: R, credentials: &Credentials) -> Result<Self> {
+        let chunks = ChunkReader::new(inner, credentials)?;
+        let payload = if chunks.header.is_compressed_stream() {
+            Payload::Compressed(Decoder::new(chunks.metadata.compression, chunks)?)
+        } else {
+            Payload::Plain(chunks)
+        };
+        Ok(Self { payload })
+    }
+
+    /// The metadata stored in the crate
+    pub fn metadata(&self) -> &FileMetadata {
+        &self.chunks().metadata
+    }
+
+    /// The parsed file header
+    pub fn header(&self) -> &FileHeader {
+        &self.chunks().header
+    }
+
+    fn chunks(&self) -> &ChunkReader<R> {
+        match &self.payload {
+            Payload::Plain(chunks) => chunks,
+            Payload::Compressed(decoder) => decoder.get_ref(),
+        }
+    }
+
+    /// Hand over the unread payload and the chunk opener, with the segment length
+    ///
+    /// Must be called before anything is read.
+    fn into_chunks(self) -> (BufReader<R>, ChunkOpener, usize) {
+        let chunks = match self.payload {
+            Payload::Plain(chunks) => chunks,
+            Payload::Compressed(decoder) => decoder.into_inner(),
+        };
+        let segment_length = chunks.ciphertext.len();
+        (chunks.inner, chunks.opener, segment_length)
+    }
+}
+
+impl<R: Read + Seek> CrateReader<R> {
+    /// Size of the decrypted content
+    ///
+    /// For chunked payloads this is computed from the size of the input, so
+    /// it is only verified once the last chunk has been read.
+    pub fn plaintext_length(&mut self) -> Result<u64> {
+        match &mut self.payload {
+            Payload::Plain(chunks) => chunks.plaintext_length(),
+            Payload::Compressed(_) => Err(not_seekable()),
+        }
+    }
+}
+
+impl<R: Read + Seek> Seek for CrateReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match &mut self.payload {
+            Payload::Plain(chunks) => chunks.seek(pos),
+            Payload::Compressed(_) => Err(not_seekable().into()),
+        }
+    }
+}
+
+impl<R: Read> Read for CrateReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.payload {
+            Payload::Plain(chunks) => chunks.read(buf),
+            Payload::Compressed(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+fn not_seekable() -> CrateError {
+    CrateError::InvalidFormat(
+        "Compressed files can only be read from the start (no seeking or --range)".to_string(),
+    )
+}
+
+/// The decrypted payload of a crate, as stored (possibly compressed)
+struct ChunkReader<R: Read> {
+    inner: BufReader<R>,
+    opener: ChunkOpener,
+    header: FileHeader,
+    metadata: FileMetadata,
+    /// Offset of the payload in `inner`
+    payload_start: u64,
+    payload_length: Option<u64>,
+    ciphertext: Vec<u8>,
+    plaintext: Zeroizing<Vec<u8>>,
+    /// Plaintext offset of the first byte in `plaintext`
+    chunk_start: u64,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    fn new(inner: R, credentials: &Credentials) -> Result<Self> {
+        let mut inner = BufReader::new(inner);
+
+        // Read header and metadata
+        let (header, metadata_block) = read_header(&mut inner)?;
+        let (metadata, opener) = open_crate(&header, &metadata_block, credentials)?;
+        let payload_start = (header.encoded_length() + metadata_block.len()) as u64;
+
+        let mut reader = Self {
+            inner,
+            opener,
+            header,
+            metadata,
+            payload_start,
+            payload_length: None,
+            ciphertext: Vec::new(),
+            plaintext: Zeroizing::new(Vec::new()),
+            chunk_start: 0,
+            position: 0,
+            done: false,
+        };
+
+        if reader.header.is_chunked() {
+            reader.ciphertext = vec![0u8; reader.header.chunk_size as usize + TAG_LENGTH];
+        } else {
+            let mut ciphertext = Vec::new();
+            reader.inner.read_to_end(&mut ciphertext)?;
+            reader.plaintext = reader.opener.open_single_shot(&ciphertext, &reader.metadata)?;
+            reader.done = true;
+        }
+
+        Ok(reader)
+    }
+
+    /// Read and authenticate the next chunk into the plaintext buffer
+    fn open_chunk(&mut self) -> Result<()> {
+        let (len, last) = read_segment(&mut self.inner, &mut self.ciphertext)?;
+        self.chunk_start += self.plaintext.len() as u64;
+        self.plaintext = self.opener.open(&self.ciphertext[..len], last)?;
+        self.position = 0;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> ChunkReader<R> {
+    fn plaintext_length(&mut self) -> Result<u64> {
+        if !self.header.is_chunked() {
+            return Ok(self.plaintext.len() as u64);
+        }
+
+        let payload_length = match self.payload_length {
+            Some(length) => length,
+            None => {
+                let current = self.inner.stream_position()?;
+                let end = self.inner.seek(SeekFrom::End(0))?;
+                self.inner.seek(SeekFrom::Start(current))?;
+                let length = end.saturating_sub(self.payload_start);
+                self.payload_length = Some(length);
+                length
+            }
+        };
+
+        // Every chunk, including an empty last one, carries a tag
+        let segment = self.ciphertext.len() as u64;
+        let chunks = payload_length.div_ceil(segment).max(1);
+        payload_length
+            .checked_sub(chunks * TAG_LENGTH as u64)
+            .ok_or_else(|| CrateError::AuthenticationFailed("encrypted data is truncated".to_string()))
+    }
+
+    /// Position on plaintext offset `target`, authenticating the chunk that holds it
+    fn seek_chunk(&mut self, target: u64) -> Result<()> {
+        let length = self.plaintext_length()?;
+
+        self.plaintext = Zeroizing::new(Vec::new());
+        self.position = 0;
+
+        if target >= length {
+            // Reads return nothing from here, so there is no chunk to open
+            self.chunk_start = target;
+            self.done = true;
+            return Ok(());
+        }
+
+        let chunk_size = self.header.chunk_size as u64;
+        let index = target / chunk_size;
+        let counter = u32::try_from(index)
+            .map_err(|_| CrateError::Decryption("Too many chunks".to_string()))?;
+
+        let segment = self.ciphertext.len() as u64;
+        self.inner.seek(SeekFrom::Start(self.payload_start + index * segment))?;
+        self.opener.seek(counter);
+        self.chunk_start = index * chunk_size;
+        self.open_chunk()?;
+        self.position = (target - self.chunk_start) as usize;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Seek for ChunkReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let current = self.chunk_start + self.position as u64;
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.plaintext_length()?.checked_add_signed(delta),
+            SeekFrom::Current(delta) => current.checked_add_signed(delta),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek position"))?;
+
+        // Single-shot payloads are already in memory, as is the current chunk
+        let end = self.chunk_start + self.plaintext.len() as u64;
+        if !self.header.is_chunked() || (self.chunk_start..end).contains(&target) {
+            self.position = usize::try_from(target - self.chunk_start).unwrap_or(usize::MAX);
+            return Ok(target);
+        }
+
+        self.seek_chunk(target)?;
+        Ok(target)
+    }
+}
+
+impl<R: Read> Read for ChunkReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.plaintext.len() {
+            if self.done || buf.is_empty() {
+                return Ok(0);
+            }
+            self.open_chunk()?;
+        }
+
+        let len = buf.len().min(self.plaintext.len() - self.position);
+        buf[..len].copy_from_slice(&self.plaintext[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl<R: Read> BufRead for ChunkReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.position >= self.plaintext.len() && !self.done {
+            self.open_chunk()?;
+        }
+        Ok(self.plaintext.get(self.position..).unwrap_or_default())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position += amt;
+    }
+}
+
+/// Encrypt a large file using streaming
 pub fn encrypt_file_streaming<P: AsRef<Path>>(
     input_path: P,
     output_path: P,
     password: &str,
 ) -> Result<()> {
-    let input_path = input_path.as_ref();
-    let output_path = output_path.as_ref();
-
-    // Open input file with buffering
-    let input_file = File::open(input_path)
-        .map_err(|e| CrateError::FileNotFound(format!("{}: {}", input_path.display(), e)))?;
-    let mut reader = BufReader::new(input_file);
-
-    // Generate random salt and nonce
-    let mut salt = [0u8; SALT_LENGTH];
-    let mut nonce_bytes = [0u8; NONCE_LENGTH];
-    
-    let mut rng = rand::thread_rng();
-    rand::RngCore::fill_bytes(&mut rng, &mut salt);
-    rand::RngCore::fill_bytes(&mut rng, &mut nonce_bytes);
+    encrypt_file_streaming_with_options(
+        input_path,
+        output_path,
+        &Credentials::password(password),
+        &EncryptOptions::default(),
+    )
+}
 
-    // Derive encryption key from password
-    let key = derive_key(password, &salt)?;
+/// Encrypt a large file using streaming with explicit options
+///
+/// With `options.compression` the file is compressed as one stream on the
+/// way in. With `options.threads` other than 1, chunks are sealed on worker
+/// threads.
+pub fn encrypt_file_streaming_with_options<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    credentials: &Credentials,
+    options: &EncryptOptions,
+) -> Result<()> {
+    encrypt_entry_streaming_with_options(input_path, output_path, None, credentials, options)?;
+    Ok(())
+}
 
-    // Create cipher
-    let cipher = Aes256Gcm::new_from_slice(&key)
-        .map_err(|e| CrateError::Encryption(e.to_string()))?;
+/// Like `encrypt_file_streaming_with_options`, recording `relative_path` in the metadata
+///
+/// Returns the metadata that was stored.
+pub(crate) fn encrypt_entry_streaming_with_options<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    relative_path: Option<String>,
+    credentials: &Credentials,
+    options: &EncryptOptions,
+) -> Result<FileMetadata> {
+    let input_path = input_path.as_ref();
+    let output_path = output_path.as_ref();
 
-    // Create metadata
-    let metadata = FileMetadata::from_file(input_path, false)?; // No compression in streaming mode
-    let metadata_bytes = metadata.to_bytes();
+    let metadata = FileMetadata {
+        relative_path,
+        ..FileMetadata::capture(
+            input_path,
+            CompressionAlgorithm::None,
+            options.preserve_attributes,
+        )?
+    };
+    // A preserved symlink has no contents of its own
+    let mut input_file: Box<dyn Read + Send> = if metadata.attributes.is_symlink() {
+        Box::new(io::empty())
+    } else {
+        Box::new(File::open(input_path).map_err(|e| {
+            CrateError::FileNotFound(format!("{}: {}", input_path.display(), e))
+        })?)
+    };
 
-    // Create header
-    let header = FileHeader::new(salt, nonce_bytes, metadata_bytes.len() as u32);
+    // With `auto`, the first bytes decide whether the file is compressed
+    let mut options = Cow::Borrowed(options);
+    if options.compression.auto {
+        let sample = read_sample(&mut input_file)?;
+        options.to_mut().compression = options.compression.for_sample(&sample);
+        input_file = Box::new(io::Cursor::new(sample).chain(input_file));
+    }
+    let metadata = FileMetadata {
+        compression: options.compression.algorithm,
+        ..metadata
+    };
 
-    // Open output file with buffering
     let output_file = OpenOptions::new()
         .write(true)
         .create(true)
@@ -75,131 +783,189 @@ pub fn encrypt_file_streaming<P: AsRef<Path>>(
         .open(output_path)?;
     let mut writer = BufWriter::new(output_file);
 
-    // Write header
-    writer.write_all(MAGIC_BYTES)?;
-    writer.write_all(&[header.version])?;
-    writer.write_all(&[header.algorithm])?;
-    writer.write_all(&header.salt)?;
-    writer.write_all(&header.nonce)?;
-    writer.write_all(&header.metadata_length.to_le_bytes())?;
-    writer.write_all(&metadata_bytes)?;
-
-    // Encrypt data in chunks
-    // Note: For true streaming, we'd use AES-GCM-SIV or a streaming cipher mode.
-    // For simplicity, we'll encrypt the whole file content (but read it in chunks to save memory)
-    let mut all_data = Vec::new();
-    let mut chunk = vec![0u8; CHUNK_SIZE];
-    
-    loop {
-        let bytes_read = reader.read(&mut chunk)?;
-        if bytes_read == 0 {
-            break;
-        }
-        all_data.extend_from_slice(&chunk[..bytes_read]);
-    }
-
-    let nonce = Nonce::from_slice(&nonce_bytes);
-    let ciphertext = cipher
-        .encrypt(nonce, all_data.as_ref())
-        .map_err(|e| CrateError::Encryption(e.to_string()))?;
-
-    writer.write_all(&ciphertext)?;
-    writer.flush()?;
+    let threads = resolve_threads(options.threads);
+    if threads > 1 {
+        let (preamble, sealer) = begin_crate(credentials, &options, &metadata)?;
+        writer.write_all(&preamble)?;
+        if options.compression.is_enabled() {
+            let input = BufReader::new(CompressingReader::new(input_file, options.compression)?);
+            parallel::seal_chunks(input, &mut writer, &sealer, CHUNK_SIZE, threads)?;
+        } else {
+            let input = BufReader::new(input_file);
+            parallel::seal_chunks(input, &mut writer, &sealer, CHUNK_SIZE, threads)?;
+        }
+        writer.flush()?;
+    } else {
+        let mut writer = CrateWriter::new(writer, credentials, &options, &metadata)?;
+        io::copy(&mut input_file, &mut writer)?;
+        writer.finish()?;
+    }
 
-    Ok(())
+    Ok(metadata)
 }
 
 /// Decrypt a large file using streaming
+///
+/// Each chunk is authenticated before it is written, into a temporary file
+/// that only replaces `output_path` once the whole payload has verified. A
+/// tampered or truncated file leaves `output_path` as it was. Files written
+/// by the single-shot format are still accepted, but have to be decrypted
+/// in memory.
 pub fn decrypt_file_streaming<P: AsRef<Path>>(
     input_path: P,
     output_path: P,
     password: &str,
+) -> Result<FileMetadata> {
+    decrypt_file_streaming_with_credentials(input_path, output_path, &Credentials::password(password))
+}
+
+/// Decrypt a large file using streaming, with a password, a key file, or both
+pub fn decrypt_file_streaming_with_credentials<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    credentials: &Credentials,
+) -> Result<FileMetadata> {
+    decrypt_file_streaming_with_threads(input_path, output_path, credentials, 1)
+}
+
+/// Decrypt a large file using streaming, opening chunks on `threads` worker threads
+///
+/// `threads` follows `EncryptOptions::threads` (0 = one per CPU). Plaintext
+/// is still written in order and only after it is authenticated.
+pub fn decrypt_file_streaming_with_threads<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    credentials: &Credentials,
+    threads: usize,
 ) -> Result<FileMetadata> {
     let input_path = input_path.as_ref();
     let output_path = output_path.as_ref();
 
-    // Open input file with buffering
-    let mut input_file = BufReader::new(File::open(input_path)
-        .map_err(|e| CrateError::FileNotFound(format!("{}: {}", input_path.display(), e)))?);
-
-    // Read and verify magic bytes
-    let mut magic = [0u8; 4];
-    input_file.read_exact(&mut magic)?;
-    if &magic != MAGIC_BYTES {
+    let input_file = File::open(input_path)
+        .map_err(|e| CrateError::FileNotFound(format!("{}: {}", input_path.display(), e)))?;
+    let mut reader = CrateReader::new(input_file, credentials)?;
+    let metadata = reader.metadata().clone();
+    if reader.header().is_archive() {
         return Err(CrateError::InvalidFormat(
-            "Not a valid CryptoCrate file".to_string(),
+            "This file is an archive; extract it instead (`cryptocrate extract`)".to_string(),
         ));
     }
 
-    // Read version
-    let mut version = [0u8; 1];
-    input_file.read_exact(&mut version)?;
-    if version[0] != VERSION {
-        return Err(CrateError::UnsupportedVersion(version[0]));
+    // Same directory as the output, so the final rename is atomic
+    let output_dir = match output_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut builder = tempfile::Builder::new();
+    #[cfg(unix)]
+    {
+        // The mode `File::create` would have used
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(std::fs::Permissions::from_mode(0o666));
     }
+    let mut temp_file = builder.tempfile_in(output_dir)?;
+    let mut writer = BufWriter::new(temp_file.as_file_mut());
 
-    // Read algorithm
-    let mut algorithm = [0u8; 1];
-    input_file.read_exact(&mut algorithm)?;
-    if algorithm[0] != ALGORITHM_AES256_GCM {
-        return Err(CrateError::InvalidFormat(
-            "Unsupported encryption algorithm".to_string(),
-        ));
+    let threads = resolve_threads(threads);
+    if threads > 1 && reader.header().is_chunked() {
+        let compressed = reader.header().is_compressed_stream();
+        let (input, opener, segment_length) = reader.into_chunks();
+        if compressed {
+            let algorithm = metadata.compression;
+            open_compressed_chunks(
+                input,
+                &mut writer,
+                &opener,
+                segment_length,
+                threads,
+                algorithm,
+            )?;
+        } else {
+            parallel::open_chunks(input, &mut writer, &opener, segment_length, threads)?;
+        }
+    } else {
+        io::copy(&mut reader, &mut writer)?;
     }
+    writer.flush()?;
+    drop(writer);
 
-    // Read salt
-    let mut salt = [0u8; SALT_LENGTH];
-    input_file.read_exact(&mut salt)?;
-
-    // Read nonce
-    let mut nonce_bytes = [0u8; NONCE_LENGTH];
-    input_file.read_exact(&mut nonce_bytes)?;
-
-    // Read metadata length
-    let mut metadata_len_bytes = [0u8; 4];
-    input_file.read_exact(&mut metadata_len_bytes)?;
-    let metadata_len = u32::from_le_bytes(metadata_len_bytes) as usize;
-
-    // Read metadata
-    let mut metadata_bytes = vec![0u8; metadata_len];
-    input_file.read_exact(&mut metadata_bytes)?;
-    let metadata = FileMetadata::from_bytes(&metadata_bytes)?;
+    // On error the temporary file is dropped, and so deleted
+    temp_file
+        .persist(output_path)
+        .map_err(|e| CrateError::Io(e.error))?;
+    Ok(metadata)
+}
 
-    // Read encrypted data
-    let mut ciphertext = Vec::new();
-    input_file.read_to_end(&mut ciphertext)?;
+/// `parallel::open_chunks` for a compressed payload
+///
+/// Opened chunks are piped to a thread that decompresses them into `writer`.
+fn open_compressed_chunks<R: BufRead + Send, W: Write + Send>(
+    input: R,
+    writer: &mut W,
+    opener: &ChunkOpener,
+    segment_length: usize,
+    threads: usize,
+    algorithm: CompressionAlgorithm,
+) -> Result<()> {
+    let (pipe_reader, mut pipe_writer) = io::pipe()?;
 
-    // Derive decryption key
-    let key = derive_key(password, &salt)?;
+    thread::scope(|scope| {
+        let decompressing = scope.spawn(move || -> Result<()> {
+            let mut decoder = Decoder::new(algorithm, BufReader::new(pipe_reader))?;
+            io::copy(&mut decoder, writer)?;
+            Ok(())
+        });
 
-    // Create cipher
-    let cipher = Aes256Gcm::new_from_slice(&key)
-        .map_err(|e| CrateError::Decryption(e.to_string()))?;
+        let opened =
+            parallel::open_chunks(input, &mut pipe_writer, opener, segment_length, threads);
+        drop(pipe_writer);
+        let decompressed = decompressing
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
 
-    let nonce = Nonce::from_slice(&nonce_bytes);
+        // A broken pipe only means the decompressor gave up first
+        match (opened, decompressed) {
+            (Err(CrateError::Io(e)), Err(decompress_error))
+                if e.kind() == io::ErrorKind::BrokenPipe =>
+            {
+                Err(decompress_error)
+            }
+            (Err(e), _) | (Ok(()), Err(e)) => Err(e),
+            (Ok(()), Ok(())) => Ok(()),
+        }
+    })
+}
 
-    // Decrypt the data
-    let plaintext = cipher
-        .decrypt(nonce, ciphertext.as_ref())
-        .map_err(|_| CrateError::InvalidPassword)?;
+/// Decrypt `length` bytes starting at plaintext `offset` into `writer`
+///
+/// Only the chunks covering the range are read and authenticated. Returns
+/// the number of bytes written, which falls short if the range runs past
+/// the end of the file.
+pub fn decrypt_range_with_credentials<P: AsRef<Path>, W: Write>(
+    input_path: P,
+    writer: &mut W,
+    credentials: &Credentials,
+    offset: u64,
+    length: u64,
+) -> Result<u64> {
+    let input_path = input_path.as_ref();
 
-    // Write decrypted file with buffering
-    let output_file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(output_path)?;
-    let mut writer = BufWriter::new(output_file);
+    let input_file = File::open(input_path)
+        .map_err(|e| CrateError::FileNotFound(format!("{}: {}", input_path.display(), e)))?;
+    let mut reader = CrateReader::new(input_file, credentials)?;
 
-    writer.write_all(&plaintext)?;
+    reader.seek(SeekFrom::Start(offset))?;
+    let written = io::copy(&mut reader.take(length), writer)?;
     writer.flush()?;
 
-    Ok(metadata)
+    Ok(written)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compression::Compression;
+    use crate::crypto::encryption::test_options;
     use std::fs;
     use tempfile::TempDir;
 
@@ -230,7 +996,13 @@ mod tests {
         let password = "streaming_test_password";
 
         // Encrypt with streaming
-        encrypt_file_streaming(&input_path, &encrypted_path, password).unwrap();
+        encrypt_file_streaming_with_options(
+            &input_path,
+            &encrypted_path,
+            &Credentials::password(password),
+            &test_options(),
+        )
+        .unwrap();
         assert!(encrypted_path.exists());
 
         // Decrypt with streaming
@@ -242,4 +1014,283 @@ mod tests {
         let decrypted_data = fs::read(&decrypted_path).unwrap();
         assert_eq!(decrypted_data, test_data);
     }
+
+    #[test]
+    fn test_streaming_chunk_boundaries() {
+        let temp_dir = TempDir::new().unwrap();
+        let password = "streaming_test_password";
+
+        // Empty file, exact multiple of the chunk size, and a partial last chunk
+        for size in [0, 2 * CHUNK_SIZE, CHUNK_SIZE + 17] {
+            let input_path = temp_dir.path().join("boundary.dat");
+            let encrypted_path = temp_dir.path().join("boundary.dat.crat");
+            let decrypted_path = temp_dir.path().join("boundary_decrypted.dat");
+
+            let test_data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+            fs::write(&input_path, &test_data).unwrap();
+
+            encrypt_file_streaming_with_options(
+                &input_path,
+                &encrypted_path,
+                &Credentials::password(password),
+                &test_options(),
+            )
+            .unwrap();
+            decrypt_file_streaming(&encrypted_path, &decrypted_path, password).unwrap();
+
+            assert_eq!(fs::read(&decrypted_path).unwrap(), test_data);
+        }
+    }
+
+    #[test]
+    fn test_streaming_detects_truncation_and_reordering() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("test.dat");
+        let encrypted_path = temp_dir.path().join("test.dat.crat");
+        let tampered_path = temp_dir.path().join("tampered.dat.crat");
+        let decrypted_path = temp_dir.path().join("test_decrypted.dat");
+
+        fs::write(&input_path, vec![0x5A; 3 * CHUNK_SIZE + 100]).unwrap();
+        let password = "streaming_test_password";
+        encrypt_file_streaming_with_options(
+            &input_path,
+            &encrypted_path,
+            &Credentials::password(password),
+            &test_options(),
+        )
+        .unwrap();
+
+        let encrypted = fs::read(&encrypted_path).unwrap();
+        let segment = CHUNK_SIZE + TAG_LENGTH;
+        let last_start = encrypted.len() - (100 + TAG_LENGTH);
+        let data_start = last_start - 3 * segment;
+
+        // Drop the final chunk: the new last chunk was not sealed as last
+        fs::write(&tampered_path, &encrypted[..last_start]).unwrap();
+        let result = decrypt_file_streaming(&tampered_path, &decrypted_path, password);
+        assert!(matches!(result, Err(CrateError::AuthenticationFailed(_))));
+
+        // Swap the second and third chunks
+        let mut reordered = encrypted.clone();
+        let second = data_start + segment;
+        let third = data_start + 2 * segment;
+        let second_chunk = encrypted[second..third].to_vec();
+        reordered[second..third].copy_from_slice(&encrypted[third..third + segment]);
+        reordered[third..third + segment].copy_from_slice(&second_chunk);
+        fs::write(&tampered_path, &reordered).unwrap();
+        let result = decrypt_file_streaming(&tampered_path, &decrypted_path, password);
+        assert!(matches!(result, Err(CrateError::AuthenticationFailed(_))));
+        assert!(!decrypted_path.exists());
+
+        // A tampered last chunk leaves no partial plaintext, on any thread
+        // count, and an existing output file survives
+        let mut tampered = encrypted.clone();
+        tampered[last_start + 10] ^= 1;
+        fs::write(&tampered_path, &tampered).unwrap();
+        let credentials = Credentials::password(password);
+        for threads in [1, 3] {
+            let result = decrypt_file_streaming_with_threads(
+                &tampered_path,
+                &decrypted_path,
+                &credentials,
+                threads,
+            );
+            assert!(matches!(result, Err(CrateError::AuthenticationFailed(_))));
+            assert!(!decrypted_path.exists());
+        }
+        fs::write(&decrypted_path, b"keep me").unwrap();
+        assert!(decrypt_file_streaming(&tampered_path, &decrypted_path, password).is_err());
+        assert_eq!(fs::read(&decrypted_path).unwrap(), b"keep me");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn test_streaming_xchacha20() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("test.dat");
+        let encrypted_path = temp_dir.path().join("test.dat.crat");
+        let decrypted_path = temp_dir.path().join("test_decrypted.dat");
+
+        let test_data: Vec<u8> = (0..2 * CHUNK_SIZE + 5).map(|i| (i % 253) as u8).collect();
+        fs::write(&input_path, &test_data).unwrap();
+
+        let options = EncryptOptions {
+            algorithm: crate::crypto::cipher::CipherAlgorithm::XChaCha20Poly1305,
+            ..test_options()
+        };
+        let credentials = Credentials::password("password");
+        encrypt_file_streaming_with_options(&input_path, &encrypted_path, &credentials, &options)
+            .unwrap();
+        decrypt_file_streaming(&encrypted_path, &decrypted_path, "password").unwrap();
+
+        assert_eq!(fs::read(&decrypted_path).unwrap(), test_data);
+    }
+
+    #[test]
+    fn test_streaming_compression() {
+        let temp_dir = TempDir::new().unwrap();
+        let input_path = temp_dir.path().join("server.log");
+        let encrypted_path = temp_dir.path().join("server.log.crat");
+        let decrypted_path = temp_dir.path().join("server_decrypted.log");
+
+        let test_data = b"GET /index.html 200 1024\n".repeat(4 * CHUNK_SIZE / 25);
+        fs::write(&input_path, &test_data).unwrap();
+        let credentials = Credentials::password("password");
+
+        // Sequential and parallel encryption, decrypted both ways
+        for (compression, threads) in [
+            ("zstd", 1),
+            ("zstd", 4),
+            ("lz4", 4),
+            ("xz:1", 1),
+            ("brotli:5", 4),
+        ] {
+            let compression: Compression = compression.parse().unwrap();
+            let options = EncryptOptions {
+                compression,
+                threads,
+                ..test_options()
+            };
+            encrypt_file_streaming_with_options(
+                &input_path,
+                &encrypted_path,
+                &credentials,
+                &options,
+            )
+            .unwrap();
+
+            let encrypted = fs::read(&encrypted_path).unwrap();
+            assert!(encrypted.len() < test_data.len() / 10);
+
+            for decrypt_threads in [1, 3] {
+                let metadata = decrypt_file_streaming_with_threads(
+                    &encrypted_path,
+                    &decrypted_path,
+                    &credentials,
+                    decrypt_threads,
+                )
+                .unwrap();
+                assert_eq!(metadata.compression, compression.algorithm);
+                assert_eq!(fs::read(&decrypted_path).unwrap(), test_data);
+            }
+
+            let mut reader =
+                CrateReader::new(std::io::Cursor::new(&encrypted), &credentials).unwrap();
+            assert!(reader.header().is_compressed_stream());
+            let result = reader.seek(SeekFrom::Start(10)).map_err(CrateError::from);
+            assert!(matches!(result, Err(CrateError::InvalidFormat(_))));
+
+            // Tampering surfaces as the cipher's error, not as corrupt compressed
+            // data, even though the whole stream fits in the first chunk
+            let mut tampered = encrypted.clone();
+            let last = tampered.len() - 1;
+            tampered[last] ^= 1;
+            fs::write(&encrypted_path, &tampered).unwrap();
+            for decrypt_threads in [1, 3] {
+                let result = decrypt_file_streaming_with_threads(
+                    &encrypted_path,
+                    &decrypted_path,
+                    &credentials,
+                    decrypt_threads,
+                );
+                assert!(matches!(result, Err(CrateError::AuthenticationFailed(_))));
+            }
+        }
+    }
+
+    #[test]
+    fn test_crate_reader_writer() {
+        let options = test_options();
+        let credentials = Credentials::password("in memory");
+        let metadata = FileMetadata {
+            filename: "socket".to_string(),
+            original_size: 0,
+            modified_time: None,
+            compression: CompressionAlgorithm::Zstd,
+            relative_path: None,
+            attributes: Default::default(),
+            timestamps: Default::default(),
+        };
+
+        // Odd-sized writes and reads that straddle chunk boundaries
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 333).map(|i| (i % 241) as u8).collect();
+        let mut writer = CrateWriter::new(Vec::new(), &credentials, &options, &metadata).unwrap();
+        for piece in data.chunks(7777) {
+            writer.write_all(piece).unwrap();
+        }
+        let encrypted = writer.finish().unwrap();
+
+        let mut reader = CrateReader::new(&encrypted[..], &credentials).unwrap();
+        assert_eq!(reader.metadata().filename, "socket");
+        assert!(!reader.metadata().is_compressed());
+        let mut decrypted = Vec::new();
+        let mut buf = [0u8; 5000];
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            decrypted.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(decrypted, data);
+
+        // Without `finish` the last chunk is never sealed
+        let mut writer = CrateWriter::new(Vec::new(), &credentials, &options, &metadata).unwrap();
+        writer.write_all(&data).unwrap();
+        let truncated = writer.inner.clone();
+        let mut reader = CrateReader::new(&truncated[..], &credentials).unwrap();
+        let result = std::io::copy(&mut reader, &mut std::io::sink()).map_err(CrateError::from);
+        assert!(matches!(result, Err(CrateError::AuthenticationFailed(_))));
+    }
+
+    #[test]
+    fn test_crate_reader_seek() {
+        let options = test_options();
+        let credentials = Credentials::password("seek");
+        let metadata = FileMetadata {
+            filename: "disk.img".to_string(),
+            original_size: 0,
+            modified_time: None,
+            compression: CompressionAlgorithm::None,
+            relative_path: None,
+            attributes: Default::default(),
+            timestamps: Default::default(),
+        };
+
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE + 100).map(|i| (i % 233) as u8).collect();
+        let mut writer = CrateWriter::new(Vec::new(), &credentials, &options, &metadata).unwrap();
+        writer.write_all(&data).unwrap();
+        let mut encrypted = writer.finish().unwrap();
+
+        // Corrupt the first chunk: ranges outside it must still decrypt
+        let data_start = encrypted.len() - (3 * (CHUNK_SIZE + TAG_LENGTH) + 100 + TAG_LENGTH);
+        encrypted[data_start + 10] ^= 1;
+
+        let mut reader = CrateReader::new(std::io::Cursor::new(&encrypted), &credentials).unwrap();
+        assert_eq!(reader.plaintext_length().unwrap(), data.len() as u64);
+
+        // Within a chunk, across a chunk boundary, and into the short last chunk
+        for (offset, len) in [
+            (2 * CHUNK_SIZE + 5, 4096),
+            (2 * CHUNK_SIZE - 10, 20),
+            (3 * CHUNK_SIZE + 90, 50),
+        ] {
+            reader.seek(SeekFrom::Start(offset as u64)).unwrap();
+            let mut buf = Vec::new();
+            (&mut reader).take(len as u64).read_to_end(&mut buf).unwrap();
+            let end = (offset + len).min(data.len());
+            assert_eq!(buf, &data[offset..end]);
+        }
+
+        reader.seek(SeekFrom::End(-3)).unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &data[data.len() - 3..]);
+
+        assert_eq!(reader.seek(SeekFrom::Current(10)).unwrap(), data.len() as u64 + 10);
+        assert_eq!(reader.read(&mut [0u8; 8]).unwrap(), 0);
+
+        let result = reader.seek(SeekFrom::Start(0)).map_err(CrateError::from);
+        assert!(matches!(result, Err(CrateError::AuthenticationFailed(_))));
+    }
 }
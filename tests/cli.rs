@@ -0,0 +1,58 @@
+//! Tests that run the `cryptocrate` binary
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use cryptocrate::{encrypt_file_streaming_with_options, Argon2Params, Credentials, EncryptOptions};
+use tempfile::TempDir;
+
+/// Encrypt `data` into `dir` with cheap Argon2 parameters
+fn encrypt(dir: &Path, data: &[u8]) -> std::path::PathBuf {
+    let input = dir.join("data.bin");
+    let encrypted = dir.join("data.bin.crat");
+    fs::write(&input, data).unwrap();
+    let options = EncryptOptions {
+        argon2: Argon2Params { memory_kb: 8192, time_cost: 1, parallelism: 1 },
+        ..EncryptOptions::default()
+    };
+    encrypt_file_streaming_with_options(&input, &encrypted, &Credentials::password("pw"), &options)
+        .unwrap();
+    encrypted
+}
+
+/// Run the binary detached from any terminal, so a password prompt fails
+/// instead of waiting for input
+fn run(args: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_cryptocrate"));
+    command.args(args).stdin(Stdio::null());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // SAFETY: setsid is async-signal-safe and touches no memory
+        unsafe {
+            command.pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            });
+        }
+    }
+    command.output().unwrap()
+}
+
+#[test]
+fn test_decrypt_range_writes_only_data_to_stdout() {
+    let temp_dir = TempDir::new().unwrap();
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let encrypted = encrypt(temp_dir.path(), &data);
+    let encrypted = encrypted.to_str().unwrap();
+
+    let output = run(&["decrypt", encrypted, "--range", "1000:5000", "-p", "pw"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.stdout, &data[1000..6000]);
+
+    // The password prompt goes to stderr too (here it then fails: no tty)
+    let output = run(&["decrypt", encrypted, "--range", "1000:5000"]);
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("password"));
+}